@@ -0,0 +1,195 @@
+// One-shot health check for bug reports: disk, network interface, ports, internet reachability
+// and free space, gathered into a single report instead of scattered log lines.
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use log::info;
+use serde::Serialize;
+
+use crate::file_transfer;
+
+const DISK_TEST_BYTES: usize = 16 * 1024 * 1024;
+const GITHUB_HOST: (&str, u16) = ("api.github.com", 443);
+const GITHUB_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub struct DiskWriteCheck {
+    pub dir: Option<PathBuf>,
+    pub bytes_written: u64,
+    pub elapsed_ms: Option<u64>,
+    pub mb_per_sec: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InterfaceCheck {
+    pub name: Option<String>,
+    pub ip: Option<String>,
+    pub netmask: Option<String>,
+    /// Broadcast address of the interface's subnet
+    pub subnet_broadcast: Option<String>,
+    /// Address the heartbeat actually sends to
+    pub heartbeat_broadcast: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortCheck {
+    pub port: u16,
+    pub protocol: &'static str,
+    /// AirShare's own listener holds the port, so it isn't probed
+    pub in_use_by_airshare: bool,
+    /// Whether a probe bind succeeded; None when the port is AirShare's own
+    pub bindable: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Where a connectivity probe stopped.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityStatus {
+    Ok,
+    /// The name didn't resolve: offline or no working DNS
+    DnsFailed,
+    /// Resolved, but the connection was refused or the network is unreachable
+    ConnectFailed,
+    TimedOut,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectivityCheck {
+    pub host: String,
+    pub status: ConnectivityStatus,
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsReport {
+    pub app_version: String,
+    pub generated_at: String,
+    pub disk_write: DiskWriteCheck,
+    pub interface: InterfaceCheck,
+    pub ports: Vec<PortCheck>,
+    pub github: ConnectivityCheck,
+    pub download_dir: Option<PathBuf>,
+    pub download_free_bytes: Option<u64>,
+}
+
+/// Run every check and return one structured report; individual failures are reported, not returned as errors.
+#[tauri::command]
+pub async fn run_diagnostics() -> Result<DiagnosticsReport, String> {
+    let download_dir = file_transfer::default_download_dir().await;
+    let download_free_bytes = download_dir.as_deref().and_then(file_transfer::available_space_for);
+    let disk_dir = download_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let disk_write = tokio::task::spawn_blocking(move || check_disk_write(disk_dir))
+        .await
+        .map_err(|e| e.to_string())?;
+    let discovery_port = file_transfer::configured_discovery_port().await;
+    let ports = vec![
+        check_udp_port(discovery_port, crate::discovery_listener_port() == Some(discovery_port)).await,
+        check_tcp_port(file_transfer::file_server_port(), file_transfer::file_server_listening()).await,
+    ];
+    let report = DiagnosticsReport {
+        app_version: file_transfer::APP_VERSION.to_string(),
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        disk_write,
+        interface: check_interface(discovery_port),
+        ports,
+        github: check_github().await,
+        download_dir,
+        download_free_bytes,
+    };
+    info!("Diagnostics: {:?}", report);
+    Ok(report)
+}
+
+/// Write and fsync a temporary file in `dir`, then remove it.
+fn check_disk_write(dir: PathBuf) -> DiskWriteCheck {
+    let path = dir.join(format!(".airshare-diag-{}", uuid::Uuid::new_v4()));
+    let started = Instant::now();
+    let result = (|| -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&path)?;
+        let chunk = vec![0xA5u8; 1024 * 1024];
+        for _ in 0..DISK_TEST_BYTES / chunk.len() {
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()
+    })();
+    let elapsed = started.elapsed();
+    let _ = std::fs::remove_file(&path);
+    match result {
+        Ok(()) => DiskWriteCheck {
+            dir: Some(dir),
+            bytes_written: DISK_TEST_BYTES as u64,
+            elapsed_ms: Some(elapsed.as_millis() as u64),
+            mb_per_sec: Some(DISK_TEST_BYTES as f64 / 1_048_576.0 / elapsed.as_secs_f64().max(0.001)),
+            error: None,
+        },
+        Err(e) => DiskWriteCheck { dir: Some(dir), bytes_written: 0, elapsed_ms: None, mb_per_sec: None, error: Some(e.to_string()) },
+    }
+}
+
+/// The interface discovery uses: the first non-loopback IPv4 address, as in `get_local_ip`.
+fn check_interface(discovery_port: u16) -> InterfaceCheck {
+    let selected = get_if_addrs::get_if_addrs().ok().and_then(|addrs| {
+        addrs.into_iter().find_map(|iface| match iface.addr {
+            get_if_addrs::IfAddr::V4(v4) if !v4.ip.is_loopback() => Some((iface.name, v4)),
+            _ => None,
+        })
+    });
+    InterfaceCheck {
+        name: selected.as_ref().map(|(name, _)| name.clone()),
+        ip: selected.as_ref().map(|(_, v4)| v4.ip.to_string()),
+        netmask: selected.as_ref().map(|(_, v4)| v4.netmask.to_string()),
+        subnet_broadcast: selected.as_ref().and_then(|(_, v4)| v4.broadcast).map(|b| b.to_string()),
+        heartbeat_broadcast: format!("255.255.255.255:{}", discovery_port),
+    }
+}
+
+/// Probe-bind `port` unless AirShare's own listener `owned` it, which would always fail.
+async fn check_udp_port(port: u16, owned: bool) -> PortCheck {
+    if owned {
+        return PortCheck { port, protocol: "udp", in_use_by_airshare: true, bindable: None, error: None };
+    }
+    let result = tokio::net::UdpSocket::bind(("0.0.0.0", port)).await;
+    PortCheck { port, protocol: "udp", in_use_by_airshare: false, bindable: Some(result.is_ok()), error: result.err().map(|e| e.to_string()) }
+}
+
+async fn check_tcp_port(port: u16, owned: bool) -> PortCheck {
+    if owned {
+        return PortCheck { port, protocol: "tcp", in_use_by_airshare: true, bindable: None, error: None };
+    }
+    let result = tokio::net::TcpListener::bind(("0.0.0.0", port)).await;
+    PortCheck { port, protocol: "tcp", in_use_by_airshare: false, bindable: Some(result.is_ok()), error: result.err().map(|e| e.to_string()) }
+}
+
+/// Resolve, then connect, within one overall timeout, reporting which step failed.
+async fn check_github() -> ConnectivityCheck {
+    let host = format!("{}:{}", GITHUB_HOST.0, GITHUB_HOST.1);
+    let started = Instant::now();
+    let probe = async {
+        let addrs: Vec<_> = match tokio::net::lookup_host(GITHUB_HOST).await {
+            Ok(a) => a.collect(),
+            Err(e) => return (ConnectivityStatus::DnsFailed, Some(e.to_string())),
+        };
+        if addrs.is_empty() {
+            return (ConnectivityStatus::DnsFailed, Some("no addresses".to_string()));
+        }
+        match tokio::net::TcpStream::connect(&addrs[..]).await {
+            Ok(_) => (ConnectivityStatus::Ok, None),
+            Err(e) => (ConnectivityStatus::ConnectFailed, Some(e.to_string())),
+        }
+    };
+    let (status, error) = tokio::time::timeout(GITHUB_TIMEOUT, probe)
+        .await
+        .unwrap_or((ConnectivityStatus::TimedOut, Some(format!("timed out after {}s", GITHUB_TIMEOUT.as_secs()))));
+    let reachable = status == ConnectivityStatus::Ok;
+    ConnectivityCheck {
+        host,
+        status,
+        reachable,
+        latency_ms: reachable.then(|| started.elapsed().as_millis() as u64),
+        error,
+    }
+}
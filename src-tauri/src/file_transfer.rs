@@ -27,6 +27,10 @@ static TRANSFER_RESPONSES: Lazy<TokioMutex<HashMap<String, bool>>> = Lazy::new(|
 static BATCH_RESPONSES: Lazy<TokioMutex<HashMap<String, (bool, Option<PathBuf>)>>> =
     Lazy::new(|| TokioMutex::new(HashMap::new()));
 
+// Largest file the user agreed to take when accepting: keyed by transfer_id until the prompt
+// returns, then by batch_id so it applies to every file of the batch
+static USER_SIZE_LIMITS: Lazy<TokioMutex<HashMap<String, u64>>> = Lazy::new(|| TokioMutex::new(HashMap::new()));
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RespondTransferArgs {
@@ -35,6 +39,9 @@ pub struct RespondTransferArgs {
     pub accept: bool,
     #[serde(default)]
     pub trust: Option<bool>,
+    // Accept, but refuse any file of the batch larger than this many bytes
+    #[serde(default, alias = "max_accept_size")]
+    pub max_accept_size: Option<u64>,
 }
 
 // Map transfer_id -> mac (preferred) or fallback ip
@@ -57,6 +64,168 @@ struct AppSettings {
     auto_accept_trusted: bool,
     #[serde(default)]
     notifications_enabled: bool,
+    // Opt-in: write a local breadcrumb dump when a transfer fails
+    #[serde(default)]
+    failure_reports_enabled: bool,
+    #[serde(default)]
+    collision_policy: CollisionPolicy,
+    // Whether a sender's `collision_hint: overwrite` may clobber existing files
+    #[serde(default)]
+    allow_sender_overwrite: bool,
+    // Max bytes received but not yet confirmed written to disk (None = default)
+    #[serde(default)]
+    receive_high_water_mark: Option<u64>,
+    // Seconds to wait for the folder picker on auto-accept (None = default, 0 = no limit)
+    #[serde(default)]
+    auto_accept_folder_timeout_secs: Option<u64>,
+    // When set, accepted transfers are saved here without asking
+    #[serde(default)]
+    default_download_dir: Option<PathBuf>,
+    // Stop advertising as a receiver below this much free space (None = default)
+    #[serde(default)]
+    min_free_space_bytes: Option<u64>,
+    // Put accepted text shares straight on the system clipboard
+    #[serde(default)]
+    auto_copy_received_text: bool,
+    // Opt-in: keep a hash index of received files and skip bodies we already have
+    #[serde(default)]
+    dedup_enabled: bool,
+    // Optional HTTP upload page for devices without AirShare; off by default
+    #[serde(default)]
+    web_receive_enabled: bool,
+    #[serde(default)]
+    web_receive_port: Option<u16>,
+    // Display unit for speeds; stored speeds stay in MB/s
+    #[serde(default)]
+    speed_unit: SpeedUnit,
+    #[serde(default)]
+    discovery_mode: DiscoveryMode,
+    // How peers are found: UDP broadcast, mDNS/DNS-SD, or both
+    #[serde(default)]
+    discovery_backend: DiscoveryBackend,
+    // Subnets (CIDR) where trusted auto-accept is expected; empty disables the check
+    #[serde(default)]
+    safe_subnets: Vec<String>,
+    #[serde(default)]
+    foreign_network_action: ForeignNetworkAction,
+    // Cap on the combined throughput of all receives; None or 0 means unlimited
+    #[serde(default)]
+    max_receive_bytes_per_sec: Option<u64>,
+    // Same for all sends combined, so AirShare doesn't saturate the uplink
+    #[serde(default)]
+    max_send_bytes_per_sec: Option<u64>,
+    // Flood protection for prompts from untrusted senders; None uses the defaults
+    #[serde(default)]
+    offer_rate_limit: Option<OfferRateLimit>,
+    // Folders whose files are hashed ahead of time so sending them skips hashing
+    #[serde(default)]
+    watched_folders: Vec<PathBuf>,
+    // Runtime log verbosity ("off".."trace"); None means DEFAULT_LOG_LEVEL
+    #[serde(default)]
+    log_level: Option<String>,
+    // Compress file bodies sent to peers that can decompress them: "zstd" or "gzip"; None sends as-is
+    #[serde(default)]
+    compression: Option<String>,
+    // Seconds a paused transfer may stay paused before it is cancelled (None = default, 0 = no limit)
+    #[serde(default)]
+    pause_timeout_secs: Option<u64>,
+    // TCP port the file server tries first (None = FILE_SERVER_PORT)
+    #[serde(default)]
+    transfer_port: Option<u16>,
+    // UDP port for discovery heartbeats (None = BROADCAST_PORT)
+    #[serde(default)]
+    discovery_port: Option<u16>,
+    // Also discover over IPv6 multicast and listen for transfers on IPv6
+    #[serde(default)]
+    ipv6_enabled: bool,
+}
+
+/// An untrusted IP making more than `max_offers` first-contact offers within `window_secs`
+/// is auto-rejected (no prompt) for `cooldown_secs`. `max_offers: 0` disables the limit.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OfferRateLimit {
+    pub max_offers: u32,
+    pub window_secs: u64,
+    pub cooldown_secs: u64,
+}
+
+impl Default for OfferRateLimit {
+    fn default() -> Self {
+        OfferRateLimit { max_offers: 5, window_secs: 60, cooldown_secs: 300 }
+    }
+}
+
+/// What a trusted auto-accept does when the sender is outside every safe subnet.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForeignNetworkAction {
+    /// Fall back to the normal accept prompt
+    #[default]
+    Prompt,
+    /// Auto-accept anyway, after emitting `trust_on_foreign_network`
+    Warn,
+}
+
+/// Who can see this device on the network.
+/// - `Open`: heartbeats are broadcast and every peer is listed.
+/// - `KnownOnly`: heartbeats are unicast to favorite and already-known peers, and heartbeats
+///   from unknown peers are ignored. Hidden from strangers, but a known device whose IP
+///   changed is only found again once it reaches us (or is re-pinned by IP).
+/// - `Off`: no heartbeats at all; other devices are still listed and can be sent to,
+///   but nobody can discover this one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    #[default]
+    Open,
+    KnownOnly,
+    Off,
+}
+
+/// How devices find each other. `Mdns` is for networks that drop broadcasts; it advertises
+/// and browses `_airshare._tcp`. Both feed the same device list.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryBackend {
+    #[default]
+    Broadcast,
+    Mdns,
+    Both,
+}
+
+impl DiscoveryBackend {
+    pub fn uses_broadcast(self) -> bool {
+        matches!(self, Self::Broadcast | Self::Both)
+    }
+
+    pub fn uses_mdns(self) -> bool {
+        matches!(self, Self::Mdns | Self::Both)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub enum SpeedUnit {
+    #[default]
+    MBps,
+    Mbps,
+}
+
+const DEFAULT_AUTO_ACCEPT_FOLDER_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_PAUSE_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_MIN_FREE_SPACE_BYTES: u64 = 500 * 1024 * 1024;
+// Headroom kept free on top of an incoming file's size
+const RECEIVE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+const RECEIVE_WRITE_BUFFER: usize = 1024 * 1024;
+const DEFAULT_RECEIVE_HIGH_WATER_MARK: u64 = 4 * 1024 * 1024;
+const MIN_RECEIVE_HIGH_WATER_MARK: u64 = 64 * 1024;
+
+/// What to do when an incoming file name already exists in the destination folder.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    #[default]
+    Overwrite,
+    Rename,
+    Skip,
 }
 
 async fn app_data_dir() -> anyhow::Result<PathBuf> {
@@ -70,890 +239,5570 @@ async fn app_data_dir() -> anyhow::Result<PathBuf> {
 
 async fn settings_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("settings.json")) }
 async fn trusted_devices_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("trusted_macs.json")) }
+async fn favorites_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("favorites.json")) }
+async fn device_nicknames_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("device_nicknames.json")) }
+async fn blocked_devices_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("blocked_devices.json")) }
+async fn hash_index_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("hash_index.json")) }
+async fn peer_profiles_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("peer_profiles.json")) }
+async fn paired_keys_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("paired_keys.json")) }
+async fn hash_cache_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("hash_cache.json")) }
 
-async fn read_settings() -> AppSettings {
-    match settings_path().await.and_then(|p| Ok(p)) {
+/// Random per-installation id, persisted in the data dir so it survives hostname/IP changes.
+pub static DEVICE_UUID: Lazy<String> = Lazy::new(load_or_create_device_uuid);
+
+fn load_or_create_device_uuid() -> String {
+    let Some(mut path) = dirs::data_dir() else {
+        warn!("No data dir, device id will not persist");
+        return Uuid::new_v4().to_string();
+    };
+    path.push("AirShare");
+    path.push("device_id");
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        let existing = existing.trim();
+        if Uuid::parse_str(existing).is_ok() {
+            return existing.to_string();
+        }
+    }
+    let id = Uuid::new_v4().to_string();
+    let written = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(&path, &id));
+    if let Err(e) = written {
+        warn!("Failed to persist device id to {:?}: {}", path, e);
+    }
+    info!("Generated new device id {}", id);
+    id
+}
+
+// --- Heartbeat signing ---
+// Each installation signs its heartbeats with its own key. Pairing hands that key to a peer,
+// which can then tell a real heartbeat from one that merely claims the device id.
+static HEARTBEAT_KEY: Lazy<String> = Lazy::new(load_or_create_heartbeat_key);
+
+fn load_or_create_heartbeat_key() -> String {
+    let Some(mut path) = dirs::data_dir() else {
+        warn!("No data dir, heartbeat key will not persist");
+        return new_heartbeat_key();
+    };
+    path.push("AirShare");
+    path.push("heartbeat_key");
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        let existing = existing.trim();
+        if hex_decode(existing).is_some_and(|k| k.len() >= 16) {
+            return existing.to_string();
+        }
+    }
+    let key = new_heartbeat_key();
+    let written = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(&path, &key));
+    if let Err(e) = written {
+        warn!("Failed to persist heartbeat key to {:?}: {}", path, e);
+    }
+    key
+}
+
+// Two v4 uuids: 244 random bits, hex encoded
+fn new_heartbeat_key() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn heartbeat_mac(key: &str) -> Option<hmac::Hmac<sha2::Sha256>> {
+    use hmac::Mac;
+    hmac::Hmac::<sha2::Sha256>::new_from_slice(&hex_decode(key)?).ok()
+}
+
+/// HMAC-SHA256 of `payload` with this installation's heartbeat key, hex encoded.
+pub(crate) fn sign_heartbeat(payload: &str) -> Option<String> {
+    use hmac::Mac;
+    let mut mac = heartbeat_mac(&HEARTBEAT_KEY)?;
+    mac.update(payload.as_bytes());
+    Some(format!("{:x}", mac.finalize().into_bytes()))
+}
+
+async fn read_paired_keys() -> HashMap<String, String> {
+    match paired_keys_path().await {
         Ok(p) => match tokio::fs::read(&p).await {
             Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
-            _ => AppSettings::default(),
+            _ => HashMap::new(),
         },
-        Err(_) => AppSettings::default(),
+        Err(_) => HashMap::new(),
     }
 }
 
-async fn write_settings(s: &AppSettings) -> anyhow::Result<()> {
-    let p = settings_path().await?;
-    let tmp = p.with_extension("json.tmp");
-    let bytes = serde_json::to_vec_pretty(s)?;
-    tokio::fs::write(&tmp, &bytes).await?;
-    tokio::fs::rename(&tmp, &p).await?;
-    Ok(())
+// paired_keys.json as last read, so heartbeats don't hit the disk; cleared by pair/unpair
+static PAIRED_KEYS: Lazy<TokioMutex<Option<HashMap<String, String>>>> = Lazy::new(|| TokioMutex::new(None));
+
+async fn paired_key(device_uuid: &str) -> Option<String> {
+    let mut cache = PAIRED_KEYS.lock().await;
+    if cache.is_none() {
+        *cache = Some(read_paired_keys().await);
+    }
+    cache.as_ref()?.get(&device_uuid.to_lowercase()).cloned()
 }
 
-async fn read_trusted_macs() -> Vec<String> {
-    match trusted_devices_path().await.and_then(|p| Ok(p)) {
-        Ok(p) => match tokio::fs::read(&p).await {
-            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_else(|_| Vec::new()),
-            _ => Vec::new(),
-        },
-        Err(_) => Vec::new(),
+// Signed heartbeats further than this from our clock, either way, are rejected
+const HEARTBEAT_MAX_SKEW_SECS: i64 = 60;
+
+// Newest validly signed heartbeat time per paired device
+static LAST_HEARTBEAT_AT: Lazy<std::sync::Mutex<HashMap<String, chrono::DateTime<chrono::Utc>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Whether a signed heartbeat sent at `sent_at` is recent and not older than the last one accepted
+/// from `device_uuid`, so a recorded heartbeat can't be replayed. The same heartbeat arriving over
+/// both IPv4 and IPv6 carries the same time, so equal is accepted.
+fn fresh_heartbeat(device_uuid: &str, sent_at: &str) -> bool {
+    let Ok(at) = chrono::DateTime::parse_from_rfc3339(sent_at).map(|t| t.with_timezone(&chrono::Utc)) else {
+        return false;
+    };
+    if (chrono::Utc::now() - at).num_seconds().abs() > HEARTBEAT_MAX_SKEW_SECS {
+        return false;
+    }
+    let mut last = LAST_HEARTBEAT_AT.lock().unwrap();
+    match last.get(device_uuid) {
+        Some(prev) if *prev > at => false,
+        _ => {
+            last.insert(device_uuid.to_string(), at);
+            true
+        }
     }
 }
 
-async fn write_trusted_macs(list: &Vec<String>) -> anyhow::Result<()> {
-    let p = trusted_devices_path().await?;
-    let tmp = p.with_extension("json.tmp");
-    let bytes = serde_json::to_vec_pretty(list)?;
-    tokio::fs::write(&tmp, &bytes).await?;
-    tokio::fs::rename(&tmp, &p).await?;
-    Ok(())
+/// Check a heartbeat signature against the key paired for `device_uuid`, and when `sent_at` is
+/// given, that the heartbeat is fresh (see `fresh_heartbeat`).
+/// `None` when the device isn't paired (nothing to check), otherwise whether the heartbeat is valid.
+pub(crate) async fn verify_heartbeat(device_uuid: &str, payload: &str, signature: Option<&str>, sent_at: Option<&str>) -> Option<bool> {
+    use hmac::Mac;
+    let key = paired_key(device_uuid).await?;
+    let (Some(mut mac), Some(sig)) = (heartbeat_mac(&key), signature.and_then(hex_decode)) else {
+        return Some(false);
+    };
+    mac.update(payload.as_bytes());
+    if mac.verify_slice(&sig).is_err() {
+        return Some(false);
+    }
+    Some(sent_at.map_or(true, |at| fresh_heartbeat(&device_uuid.to_lowercase(), at)))
 }
 
+/// This device's heartbeat key, to be entered on a peer with `pair_device`.
 #[tauri::command]
-pub async fn get_auto_accept_trusted() -> Result<bool, String> {
-    Ok(read_settings().await.auto_accept_trusted)
+pub fn get_pairing_key() -> String {
+    HEARTBEAT_KEY.clone()
 }
 
+/// Store a peer's heartbeat key; from then on its heartbeats must carry a valid signature.
 #[tauri::command]
-pub async fn set_auto_accept_trusted(value: bool) -> Result<(), String> {
-    let mut s = read_settings().await;
-    s.auto_accept_trusted = value;
-    write_settings(&s).await.map_err(|e| e.to_string())
+pub async fn pair_device(device_uuid: String, key: String) -> Result<(), String> {
+    let key = key.trim().to_lowercase();
+    if !hex_decode(&key).is_some_and(|k| k.len() >= 16) {
+        return Err("invalid pairing key".to_string());
+    }
+    let mut keys = read_paired_keys().await;
+    keys.insert(device_uuid.trim().to_lowercase(), key);
+    let path = paired_keys_path().await.map_err(|e| e.to_string())?;
+    let result = write_json_atomic(&path, &keys).await.map_err(|e| e.to_string());
+    *PAIRED_KEYS.lock().await = None;
+    result
 }
 
 #[tauri::command]
-pub async fn list_trusted_devices() -> Result<Vec<String>, String> {
-    Ok(read_trusted_macs().await)
+pub async fn unpair_device(device_uuid: String) -> Result<(), String> {
+    let mut keys = read_paired_keys().await;
+    let device_uuid = device_uuid.trim().to_lowercase();
+    if keys.remove(&device_uuid).is_none() {
+        return Ok(());
+    }
+    let path = paired_keys_path().await.map_err(|e| e.to_string())?;
+    let result = write_json_atomic(&path, &keys).await.map_err(|e| e.to_string());
+    *PAIRED_KEYS.lock().await = None;
+    LAST_HEARTBEAT_AT.lock().unwrap().remove(&device_uuid);
+    result
 }
 
 #[tauri::command]
-pub async fn add_trusted_device_mac(mac: String) -> Result<(), String> {
-    add_trusted_device_mac_internal(&mac).await
+pub async fn list_paired_devices() -> Result<Vec<String>, String> {
+    let mut ids: Vec<String> = read_paired_keys().await.into_keys().collect();
+    ids.sort();
+    Ok(ids)
 }
 
 #[tauri::command]
-pub async fn remove_trusted_device_mac(mac: String) -> Result<(), String> {
-    let mut list = read_trusted_macs().await;
-    list.retain(|x| x != &mac);
-    write_trusted_macs(&list).await.map_err(|e| e.to_string())
+pub fn get_device_uuid() -> String {
+    DEVICE_UUID.clone()
 }
 
-// Helper: try to obtain local MAC as "aa:bb:cc:dd:ee:ff" lowercase
-fn get_local_mac() -> Option<String> {
-    match get_mac_address() {
-        Ok(Some(ma)) => Some(format!("{}", ma).to_lowercase()),
-        _ => None,
+// Settings are served from memory: set_* commands update this copy and a flush writes
+// settings.json once changes have been quiet for SETTINGS_FLUSH_DELAY (and again on exit)
+static SETTINGS: Lazy<std::sync::Mutex<Option<AppSettings>>> = Lazy::new(|| std::sync::Mutex::new(None));
+// Bumped on every change, so only the last scheduled flush of a burst writes
+static SETTINGS_GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static SETTINGS_FLUSHED_GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+const SETTINGS_FLUSH_DELAY: Duration = Duration::from_millis(750);
+// Error of the last scheduled flush, None once one succeeds; see get_settings_save_status
+static SETTINGS_FLUSH_ERROR: Lazy<std::sync::Mutex<Option<String>>> = Lazy::new(|| std::sync::Mutex::new(None));
+
+async fn load_settings_from_disk() -> AppSettings {
+    match settings_path().await.and_then(|p| Ok(p)) {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => AppSettings::default(),
+        },
+        Err(_) => AppSettings::default(),
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileOffer {
-    pub transfer_id: String,
-    pub file_name: String,
-    pub file_size: u64,
-    pub mime: String,
-    pub sha256: Option<String>,
-    // Optionally, batch_id for batch transfers
-    #[serde(default)]
-    pub batch_id: Option<String>,
-    // Optional sender MAC (added to identify device uniquely)
-    #[serde(default)]
-    pub sender_mac: Option<String>,
+async fn read_settings() -> AppSettings {
+    if let Some(s) = SETTINGS.lock().unwrap().as_ref() {
+        return s.clone();
+    }
+    let loaded = load_settings_from_disk().await;
+    SETTINGS.lock().unwrap().get_or_insert(loaded).clone()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FileInfo {
-    pub size: u64,
-    pub name: String,
-    pub is_file: bool,
+/// Change the settings under the lock, so concurrent set_* commands can't undo each other, and
+/// schedule the coalesced flush that writes the change; its outcome is in get_settings_save_status.
+async fn update_settings(f: impl FnOnce(&mut AppSettings)) {
+    let current = read_settings().await;
+    let generation = {
+        let mut guard = SETTINGS.lock().unwrap();
+        f(guard.get_or_insert(current));
+        SETTINGS_GENERATION.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(SETTINGS_FLUSH_DELAY).await;
+        // A later change schedules its own flush, which writes (and reports) this one too
+        if SETTINGS_GENERATION.load(std::sync::atomic::Ordering::SeqCst) != generation {
+            return;
+        }
+        let result = flush_settings().await;
+        if let Err(e) = &result {
+            error!("Failed to save settings: {}", e);
+        }
+        *SETTINGS_FLUSH_ERROR.lock().unwrap() = result.err().map(|e| e.to_string());
+    });
 }
 
-/// Calcola l'ETA basandosi sulla velocità di trasferimento attuale
-fn calculate_eta(bytes_transferred: u64, total_bytes: u64, elapsed_ms: u128) -> (u128, String) {
-    if bytes_transferred == 0 || elapsed_ms == 0 {
-        return (0, "Calcolo ETA...".to_string());
+#[derive(Debug, Serialize)]
+pub struct SettingsSaveStatus {
+    // A change is still waiting for its flush
+    pub pending: bool,
+    // Error of the last flush (e.g. `disk_full`); the change stays in memory until a later one succeeds
+    pub error: Option<String>,
+}
+
+/// Whether settings changes have reached settings.json, for the UI to poll after set_* commands.
+#[tauri::command]
+pub fn get_settings_save_status() -> SettingsSaveStatus {
+    let generation = SETTINGS_GENERATION.load(std::sync::atomic::Ordering::SeqCst);
+    SettingsSaveStatus {
+        pending: SETTINGS_FLUSHED_GENERATION.load(std::sync::atomic::Ordering::SeqCst) < generation,
+        error: SETTINGS_FLUSH_ERROR.lock().unwrap().clone(),
     }
-    
-    let bytes_remaining = total_bytes - bytes_transferred;
-    let bytes_per_ms = bytes_transferred as f64 / elapsed_ms as f64;
-    
-    if bytes_per_ms <= 0.0 {
-        return (0, "Calcolo ETA...".to_string());
+}
+
+/// The in-memory settings, if they changed since the last flush, with their generation.
+fn unflushed_settings() -> Option<(AppSettings, u64)> {
+    let generation = SETTINGS_GENERATION.load(std::sync::atomic::Ordering::SeqCst);
+    if SETTINGS_FLUSHED_GENERATION.load(std::sync::atomic::Ordering::SeqCst) >= generation {
+        return None;
     }
-    
-    let eta_ms = (bytes_remaining as f64 / bytes_per_ms) as u128;
-    
-    // Formatta l'ETA in formato leggibile
-    let eta_formatted = if eta_ms < 1000 {
-        format!("{}ms rimanenti", eta_ms)
-    } else if eta_ms < 60000 {
-        format!("{:.0}s rimanenti", eta_ms as f64 / 1000.0)
-    } else if eta_ms < 3600000 {
-        let minutes = eta_ms / 60000;
-        let seconds = (eta_ms % 60000) / 1000;
-        format!("{}m {}s rimanenti", minutes, seconds)
-    } else {
-        let hours = eta_ms / 3600000;
-        let minutes = (eta_ms % 3600000) / 60000;
-        format!("{}h {}m rimanenti", hours, minutes)
-    };
-    
-    (eta_ms, eta_formatted)
+    SETTINGS.lock().unwrap().clone().map(|s| (s, generation))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum TransferType {
-    Sent,
-    Received,
+async fn flush_settings() -> anyhow::Result<()> {
+    let Some((s, generation)) = unflushed_settings() else { return Ok(()); };
+    write_json_atomic(&settings_path().await?, &s).await?;
+    SETTINGS_FLUSHED_GENERATION.fetch_max(generation, std::sync::atomic::Ordering::SeqCst);
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum TransferStatus {
-    Completed,
-    Cancelled,
-    Failed,
+/// Write pending settings before returning; for app exit, when a scheduled flush would never run.
+pub fn flush_settings_on_exit() {
+    if let Err(e) = tauri::async_runtime::block_on(flush_settings()) {
+        error!("Failed to save settings on exit: {}", e);
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum DeviceType {
-    Desktop,
-    Mobile,
-    Tablet,
-    Unknown,
+/// ENOSPC / ERROR_DISK_FULL / ERROR_HANDLE_DISK_FULL (`ErrorKind::StorageFull` is newer than our MSRV).
+fn is_disk_full(e: &std::io::Error) -> bool {
+    match e.raw_os_error() {
+        #[cfg(windows)]
+        Some(code) => code == 112 || code == 39,
+        #[cfg(not(windows))]
+        Some(code) => code == 28,
+        None => false,
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct TransferRecord {
-    pub id: String,
-    pub file_name: String,
-    pub file_size: u64,
-    #[serde(rename = "type")]
-    pub transfer_type: TransferType,
-    pub status: TransferStatus,
-    pub from_device: String,
-    pub to_device: String,
-    pub start_time: String,
-    pub duration: u64,
-    pub speed: f64,
-    pub device_type: DeviceType,
+/// Serialize `value` to `path` via a `.tmp` sibling + rename.
+/// On failure the temp file is removed and the original left untouched; a full disk reports `disk_full`.
+async fn write_json_atomic<T: Serialize + ?Sized>(path: &std::path::Path, value: &T) -> anyhow::Result<()> {
+    write_atomic(path, &serde_json::to_vec_pretty(value)?).await
 }
 
-static RECENTS_LOCK: Lazy<TokioMutex<()>> = Lazy::new(|| TokioMutex::new(()));
+/// Write `bytes` to `<path>.tmp`, then rename it over `path`, so readers never see a partial file.
+async fn write_atomic(path: &std::path::Path, bytes: &[u8]) -> anyhow::Result<()> {
+    write_atomic_with(path, |tmp| tokio::fs::write(tmp, bytes)).await
+}
 
-async fn save_recent_transfer(_app_handle: &AppHandle, record: &TransferRecord) -> anyhow::Result<()> {
-    let _guard = RECENTS_LOCK.lock().await;
-    // Usa dirs::data_dir come base e crea una sottocartella per l'app
-    let mut dir = dirs::data_dir().ok_or_else(|| anyhow::anyhow!("impossibile ottenere data_dir"))?;
-    dir.push("AirShare");
-    // AGGIUNGI QUESTO LOG
-    info!("📁 Recent transfers path: {:?}", dir.join("recent_transfers.json"));
-    if !dir.exists() {
-        tokio::fs::create_dir_all(&dir).await?;
+/// write_atomic with the temp-file write supplied by the caller (tests inject failures here).
+async fn write_atomic_with<F>(path: &std::path::Path, write_tmp: impl FnOnce(PathBuf) -> F) -> anyhow::Result<()>
+where
+    F: std::future::Future<Output = std::io::Result<()>>,
+{
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let result = match write_tmp(tmp.clone()).await {
+        Ok(()) => tokio::fs::rename(&tmp, path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        if is_disk_full(&e) {
+            warn!("Disk full while writing {:?}, kept the previous version", path);
+            anyhow::bail!("disk_full: {}", e);
+        }
+        return Err(anyhow::anyhow!("failed to write {:?}: {}", path, e));
     }
-    let file_path = dir.join("recent_transfers.json");
+    Ok(())
+}
 
-    // Leggi JSON esistente (array) oppure crea nuovo
-    let existing: Vec<TransferRecord> = match tokio::fs::read(&file_path).await {
-        Ok(bytes) => {
-            if bytes.is_empty() {
-                Vec::new()
-            } else {
-                match serde_json::from_slice::<Vec<TransferRecord>>(&bytes) {
-                    Ok(v) => v,
-                    Err(_) => Vec::new(),
-                }
-            }
-        }
-        Err(_) => Vec::new(),
-    };
+#[cfg(test)]
+mod write_atomic_tests {
+    use super::*;
 
-    let mut updated = existing;
-    updated.insert(0, record.clone());
-    // Mantieni solo gli ultimi 100 record per evitare crescita infinita
-    if updated.len() > 100 {
-        updated.truncate(100);
+    #[cfg(windows)]
+    const DISK_FULL: i32 = 112;
+    #[cfg(not(windows))]
+    const DISK_FULL: i32 = 28;
+
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("airshare-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
     }
 
-    let json = serde_json::to_vec_pretty(&updated)?;
-    // Usa write atomico best-effort
-    let tmp_path = file_path.with_extension("json.tmp");
-    tokio::fs::write(&tmp_path, &json).await?;
-    tokio::fs::rename(&tmp_path, &file_path).await?;
-    Ok(())
-}
+    fn other_files(dir: &std::path::Path, keep: &str) -> Vec<std::ffi::OsString> {
+        std::fs::read_dir(dir).unwrap().flatten().map(|e| e.file_name()).filter(|n| n != keep).collect()
+    }
 
+    #[tokio::test]
+    async fn failed_write_leaves_no_temp_file_and_keeps_original() {
+        let dir = scratch_dir();
+        let path = dir.join("settings.json");
+        std::fs::write(&path, b"original").unwrap();
+
+        // Part of the new content reaches the temp file before the disk fills up
+        let result = write_atomic_with(&path, |tmp| async move {
+            tokio::fs::write(&tmp, b"{\"partial\":").await?;
+            Err(std::io::Error::from_raw_os_error(DISK_FULL))
+        }).await;
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("disk_full"), "unexpected error: {}", err);
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+        assert!(other_files(&dir, "settings.json").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_json_atomic_replaces_the_file() {
+        let dir = scratch_dir();
+        let path = dir.join("trusted_macs.json");
+        std::fs::write(&path, b"[]").unwrap();
+
+        write_json_atomic(&path, &vec!["aa:bb:cc:dd:ee:ff"]).await.unwrap();
+
+        let saved: Vec<String> = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved, vec!["aa:bb:cc:dd:ee:ff"]);
+        assert!(other_files(&dir, "trusted_macs.json").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+async fn read_trusted_macs() -> Vec<String> {
+    match trusted_devices_path().await.and_then(|p| Ok(p)) {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_else(|_| Vec::new()),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+async fn write_trusted_macs(list: &Vec<String>) -> anyhow::Result<()> {
+    let p = trusted_devices_path().await?;
+    write_json_atomic(&p, list).await
+}
+
+// Temp files younger than this may belong to a write still in progress
+const TEMP_ARTIFACT_MIN_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct CleanupResult {
+    pub files_removed: usize,
+    pub bytes_freed: u64,
+    pub removed: Vec<String>,
+}
+
+/// Scan the app data dir for leftovers of interrupted atomic writes (`*.json.tmp`)
+/// and remove them. With `dry_run` the files are only listed.
+#[tauri::command]
+pub async fn cleanup_temp_artifacts(dry_run: Option<bool>) -> Result<CleanupResult, String> {
+    let dry_run = dry_run.unwrap_or(false);
+    let dir = app_data_dir().await.map_err(|e| e.to_string())?;
+    let mut result = CleanupResult::default();
+
+    let mut entries = tokio::fs::read_dir(&dir).await.map_err(|e| e.to_string())?;
+    while let Some(entry) = entries.next_entry().await.map_err(|e| e.to_string())? {
+        let path = entry.path();
+        let is_tmp = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.ends_with(".json.tmp"));
+        if !is_tmp {
+            continue;
+        }
+        let Ok(meta) = entry.metadata().await else { continue; };
+        let old_enough = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.elapsed().ok())
+            .is_some_and(|age| age >= TEMP_ARTIFACT_MIN_AGE);
+        if !meta.is_file() || !old_enough {
+            continue;
+        }
+        if !dry_run {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                warn!("Failed to remove temp artifact {:?}: {}", path, e);
+                continue;
+            }
+        }
+        result.files_removed += 1;
+        result.bytes_freed += meta.len();
+        result.removed.push(path.display().to_string());
+    }
+
+    info!("Temp cleanup (dry_run={}): {} file(s), {} bytes", dry_run, result.files_removed, result.bytes_freed);
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn get_auto_accept_trusted() -> Result<bool, String> {
+    Ok(read_settings().await.auto_accept_trusted)
+}
+
+#[tauri::command]
+pub async fn set_auto_accept_trusted(value: bool) -> Result<(), String> {
+    update_settings(|s| s.auto_accept_trusted = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_auto_accept_folder_timeout() -> Result<u64, String> {
+    Ok(read_settings().await.auto_accept_folder_timeout_secs.unwrap_or(DEFAULT_AUTO_ACCEPT_FOLDER_TIMEOUT_SECS))
+}
+
+/// Set the auto-accept folder selection timeout in seconds; 0 waits indefinitely.
+#[tauri::command]
+pub async fn set_auto_accept_folder_timeout(seconds: u64) -> Result<(), String> {
+    update_settings(|s| s.auto_accept_folder_timeout_secs = Some(seconds)).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_pause_timeout() -> Result<u64, String> {
+    Ok(read_settings().await.pause_timeout_secs.unwrap_or(DEFAULT_PAUSE_TIMEOUT_SECS))
+}
+
+/// Set how many seconds a transfer may stay paused before it is cancelled; 0 waits indefinitely.
+#[tauri::command]
+pub async fn set_pause_timeout(seconds: u64) -> Result<(), String> {
+    update_settings(|s| s.pause_timeout_secs = Some(seconds)).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_min_free_space() -> Result<u64, String> {
+    Ok(read_settings().await.min_free_space_bytes.unwrap_or(DEFAULT_MIN_FREE_SPACE_BYTES))
+}
+
+/// Set the free-space threshold in bytes below which the heartbeat reports `accepting: false`; 0 disables it.
+#[tauri::command]
+pub async fn set_min_free_space(bytes: u64) -> Result<(), String> {
+    update_settings(|s| s.min_free_space_bytes = Some(bytes)).await;
+    Ok(())
+}
+
+/// Free bytes on the volume holding `path` (the disk with the longest matching mount point).
+pub(crate) fn available_space_for(path: &std::path::Path) -> Option<u64> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|d| path.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .map(|d| d.available_space())
+}
+
+/// Where received files land when no destination is picked: the configured default, then Downloads, then home.
+pub(crate) async fn default_download_dir() -> Option<PathBuf> {
+    read_settings().await.default_download_dir.or_else(dirs::download_dir).or_else(dirs::home_dir)
+}
+
+#[tauri::command]
+pub async fn get_default_download_dir() -> Result<Option<PathBuf>, String> {
+    Ok(read_settings().await.default_download_dir)
+}
+
+/// Save accepted files straight into `path` instead of showing the folder picker; None brings the picker back.
+#[tauri::command]
+pub async fn set_default_download_dir(path: Option<PathBuf>) -> Result<(), String> {
+    if let Some(ref p) = path {
+        if !p.is_dir() {
+            return Err(format!("not a folder: {}", p.display()));
+        }
+    }
+    update_settings(|s| s.default_download_dir = path).await;
+    Ok(())
+}
+
+/// Whether this device should advertise itself as able to receive.
+/// Checks free space on the default download volume against the configured threshold.
+pub async fn is_accepting_transfers() -> bool {
+    let threshold = read_settings().await.min_free_space_bytes.unwrap_or(DEFAULT_MIN_FREE_SPACE_BYTES);
+    if threshold == 0 {
+        return true;
+    }
+    let Some(dir) = default_download_dir().await else {
+        return true;
+    };
+    match available_space_for(&dir) {
+        Some(free) if free < threshold => {
+            warn!("Low disk space on {:?}: {} bytes free (threshold {}), not accepting transfers", dir, free, threshold);
+            false
+        }
+        _ => true,
+    }
+}
+
+#[tauri::command]
+pub async fn get_auto_copy_received_text() -> Result<bool, String> {
+    Ok(read_settings().await.auto_copy_received_text)
+}
+
+#[tauri::command]
+pub async fn set_auto_copy_received_text(value: bool) -> Result<(), String> {
+    update_settings(|s| s.auto_copy_received_text = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_dedup_enabled() -> Result<bool, String> {
+    Ok(read_settings().await.dedup_enabled)
+}
+
+#[tauri::command]
+pub async fn set_dedup_enabled(value: bool) -> Result<(), String> {
+    update_settings(|s| s.dedup_enabled = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_compression() -> Result<Option<String>, String> {
+    Ok(read_settings().await.compression)
+}
+
+/// `value` is one of SUPPORTED_COMPRESSIONS, or None to send files uncompressed.
+#[tauri::command]
+pub async fn set_compression(value: Option<String>) -> Result<(), String> {
+    let value = value.map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty());
+    if let Some(ref v) = value {
+        if !SUPPORTED_COMPRESSIONS.contains(&v.as_str()) {
+            return Err(format!("unsupported compression '{}', expected one of {}", v, SUPPORTED_COMPRESSIONS.join(", ")));
+        }
+    }
+    update_settings(|s| s.compression = value).await;
+    Ok(())
+}
+
+/// Port of the browser upload server, or None when it is disabled.
+pub(crate) async fn web_receive_port() -> Option<u16> {
+    let s = read_settings().await;
+    s.web_receive_enabled.then(|| s.web_receive_port.unwrap_or(crate::web_receive::DEFAULT_WEB_RECEIVE_PORT))
+}
+
+#[tauri::command]
+pub async fn get_web_receive_enabled() -> Result<bool, String> {
+    Ok(read_settings().await.web_receive_enabled)
+}
+
+/// Turn the browser upload server on/off; takes effect immediately.
+#[tauri::command]
+pub async fn set_web_receive_enabled(app_handle: AppHandle, value: bool, port: Option<u16>) -> Result<(), String> {
+    update_settings(|s| {
+        s.web_receive_enabled = value;
+        if port.is_some() {
+            s.web_receive_port = port;
+        }
+    }).await;
+    crate::web_receive::apply_settings(app_handle).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_speed_unit() -> Result<SpeedUnit, String> {
+    Ok(read_settings().await.speed_unit)
+}
+
+#[tauri::command]
+pub async fn set_speed_unit(value: SpeedUnit) -> Result<(), String> {
+    update_settings(|s| s.speed_unit = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_discovery_mode() -> Result<DiscoveryMode, String> {
+    Ok(read_settings().await.discovery_mode)
+}
+
+#[tauri::command]
+pub async fn set_discovery_mode(value: DiscoveryMode) -> Result<(), String> {
+    update_settings(|s| s.discovery_mode = value).await;
+    Ok(())
+}
+
+pub(crate) async fn discovery_mode() -> DiscoveryMode {
+    read_settings().await.discovery_mode
+}
+
+/// Discovery backend from the settings; read once at startup.
+pub(crate) async fn discovery_backend() -> DiscoveryBackend {
+    read_settings().await.discovery_backend
+}
+
+#[tauri::command]
+pub async fn get_discovery_backend() -> Result<DiscoveryBackend, String> {
+    Ok(discovery_backend().await)
+}
+
+/// Pick broadcast, mDNS or both for discovery. Takes effect on the next start.
+#[tauri::command]
+pub async fn set_discovery_backend(value: DiscoveryBackend) -> Result<(), String> {
+    update_settings(|s| s.discovery_backend = value).await;
+    info!("Discovery backend set to {:?} (applies after restart)", value);
+    Ok(())
+}
+
+/// A favorite or trusted peer, matched by device id, MAC or IP.
+pub(crate) async fn is_known_peer(ip: &str, mac: Option<&str>, device_uuid: Option<&str>) -> bool {
+    if read_favorites().await.iter().any(|f| f.matches(ip, mac, device_uuid)) {
+        return true;
+    }
+    let trusted = read_trusted_macs().await;
+    [device_uuid, mac, Some(ip)]
+        .iter()
+        .flatten()
+        .any(|id| trusted.contains(&id.to_lowercase()))
+}
+
+/// Parse "a.b.c.d/n" (or an IPv6 equivalent); a bare address is a single-host subnet.
+fn parse_subnet(cidr: &str) -> Option<(std::net::IpAddr, u8)> {
+    let (addr, len) = match cidr.trim().split_once('/') {
+        Some((a, l)) => (a.parse::<std::net::IpAddr>().ok()?, l.parse::<u8>().ok()?),
+        None => {
+            let a = cidr.trim().parse::<std::net::IpAddr>().ok()?;
+            (a, if a.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((addr, len))
+}
+
+fn ip_in_subnet(ip: std::net::IpAddr, cidr: &str) -> bool {
+    use std::net::IpAddr;
+    let Some((net, len)) = parse_subnet(cidr) else { return false; };
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = if len == 0 { 0 } else { u32::MAX << (32 - len) };
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = if len == 0 { 0 } else { u128::MAX << (128 - len) };
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Trusted auto-accept from outside every safe subnet may be an impersonation: emit
+/// `trust_on_foreign_network` and return whether the auto-accept should still go ahead.
+async fn trusted_network_allows_auto_accept(app_handle: &AppHandle, addr: std::net::SocketAddr, transfer_id: &str, device_id: Option<String>) -> bool {
+    let settings = read_settings().await;
+    if settings.safe_subnets.is_empty() || settings.safe_subnets.iter().any(|c| ip_in_subnet(addr.ip(), c)) {
+        return true;
+    }
+    warn!("({addr}) Trusted sender outside safe subnets {:?} (action {:?})", settings.safe_subnets, settings.foreign_network_action);
+    tauri_log(app_handle, "warn", format!("Trusted sender {} is outside the safe subnets", addr.ip())).await;
+    let _ = app_handle.emit("trust_on_foreign_network", serde_json::json!({
+        "transfer_id": transfer_id,
+        "ip": addr.ip().to_string(),
+        "device_id": device_id,
+        "action": settings.foreign_network_action,
+    }));
+    settings.foreign_network_action == ForeignNetworkAction::Warn
+}
+
+#[tauri::command]
+pub async fn get_safe_subnets() -> Result<Vec<String>, String> {
+    Ok(read_settings().await.safe_subnets)
+}
+
+/// Set the subnets (CIDR, e.g. "192.168.1.0/24") where trusted auto-accept needs no extra warning.
+#[tauri::command]
+pub async fn set_safe_subnets(subnets: Vec<String>) -> Result<(), String> {
+    if let Some(bad) = subnets.iter().find(|c| parse_subnet(c).is_none()) {
+        return Err(format!("invalid subnet: {}", bad));
+    }
+    update_settings(|s| s.safe_subnets = subnets.into_iter().map(|c| c.trim().to_string()).collect()).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_foreign_network_action() -> Result<ForeignNetworkAction, String> {
+    Ok(read_settings().await.foreign_network_action)
+}
+
+#[tauri::command]
+pub async fn set_foreign_network_action(value: ForeignNetworkAction) -> Result<(), String> {
+    update_settings(|s| s.foreign_network_action = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_failure_reports_enabled() -> Result<bool, String> {
+    Ok(read_settings().await.failure_reports_enabled)
+}
+
+#[tauri::command]
+pub async fn set_failure_reports_enabled(value: bool) -> Result<(), String> {
+    update_settings(|s| s.failure_reports_enabled = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_collision_policy() -> Result<CollisionPolicy, String> {
+    Ok(read_settings().await.collision_policy)
+}
+
+#[tauri::command]
+pub async fn set_collision_policy(value: CollisionPolicy) -> Result<(), String> {
+    update_settings(|s| s.collision_policy = value).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_allow_sender_overwrite() -> Result<bool, String> {
+    Ok(read_settings().await.allow_sender_overwrite)
+}
+
+#[tauri::command]
+pub async fn set_allow_sender_overwrite(value: bool) -> Result<(), String> {
+    update_settings(|s| s.allow_sender_overwrite = value).await;
+    Ok(())
+}
+
+/// Policy for one incoming file: the sender's hint wins, except that a forced
+/// overwrite is only honored when the user allowed it.
+fn effective_collision_policy(hint: Option<CollisionPolicy>, settings: &AppSettings) -> CollisionPolicy {
+    match hint {
+        Some(CollisionPolicy::Overwrite) if !settings.allow_sender_overwrite => settings.collision_policy,
+        Some(h) => h,
+        None => settings.collision_policy,
+    }
+}
+
+/// Pick the destination path for `file_name` inside `dir`.
+/// Returns None when the file already exists and the policy is Skip.
+fn resolve_collision(dir: &std::path::Path, file_name: &str, policy: CollisionPolicy) -> Option<PathBuf> {
+    let candidate = dir.join(file_name);
+    if !candidate.exists() {
+        return Some(candidate);
+    }
+    match policy {
+        CollisionPolicy::Overwrite => Some(candidate),
+        CollisionPolicy::Skip => None,
+        CollisionPolicy::Rename => {
+            let name = std::path::Path::new(file_name);
+            let stem = name.file_stem().and_then(|s| s.to_str()).unwrap_or(file_name);
+            let ext = name.extension().and_then(|e| e.to_str());
+            (2u32..)
+                .map(|i| match ext {
+                    Some(ext) => dir.join(format!("{} ({}).{}", stem, i, ext)),
+                    None => dir.join(format!("{} ({})", stem, i)),
+                })
+                .find(|p| !p.exists())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_receive_high_water_mark() -> Result<u64, String> {
+    Ok(read_settings().await.receive_high_water_mark.unwrap_or(DEFAULT_RECEIVE_HIGH_WATER_MARK))
+}
+
+#[tauri::command]
+pub async fn set_receive_high_water_mark(value: Option<u64>) -> Result<(), String> {
+    update_settings(|s| s.receive_high_water_mark = value.map(|v| v.max(MIN_RECEIVE_HIGH_WATER_MARK))).await;
+    Ok(())
+}
+
+// --- Bandwidth limiting ---
+/// Byte-rate limiter: callers take what they just transferred and sleep for the returned delay.
+/// Tokens may go negative, so one large read is paid for by waiting afterwards.
+struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        TokenBucket { rate, tokens: 0.0, last: Instant::now() }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        if self.rate != rate {
+            *self = TokenBucket::new(rate);
+        }
+    }
+
+    /// Account for `n` bytes; returns how long to pause to stay under the rate. A zero rate never waits.
+    fn take(&mut self, n: u64) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        let now = Instant::now();
+        // Allow at most one second of burst after an idle period
+        let capacity = self.rate as f64;
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate as f64).min(capacity);
+        self.last = now;
+        self.tokens -= n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+// Shared by every active receive loop, so the limit applies to their sum
+static RECEIVE_BUCKET: Lazy<std::sync::Mutex<TokenBucket>> = Lazy::new(|| std::sync::Mutex::new(TokenBucket::new(0)));
+
+async fn sync_receive_limit() {
+    let rate = read_settings().await.max_receive_bytes_per_sec.unwrap_or(0);
+    RECEIVE_BUCKET.lock().unwrap().set_rate(rate);
+}
+
+/// Pause a receive loop after it read `n` bytes, if the global receive cap requires it.
+async fn throttle_receive(n: u64) {
+    let delay = RECEIVE_BUCKET.lock().unwrap().take(n);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Buffered writer for a received body. tokio's File finishes writes in the background, so
+/// it is flushed once `high_water_mark` bytes are outstanding: the receive loop then waits for
+/// the disk, and a slow drive backpressures the sender through TCP flow control instead of
+/// piling up memory.
+struct ReceiveSink<W: tokio::io::AsyncWrite + Unpin> {
+    writer: tokio::io::BufWriter<W>,
+    high_water_mark: u64,
+    unflushed: u64,
+}
+
+impl<W: tokio::io::AsyncWrite + Unpin> ReceiveSink<W> {
+    fn new(inner: W, high_water_mark: u64) -> Self {
+        ReceiveSink {
+            // Batch the 64KiB socket reads into fewer, larger writes
+            writer: tokio::io::BufWriter::with_capacity(RECEIVE_WRITE_BUFFER, inner),
+            high_water_mark: high_water_mark.max(MIN_RECEIVE_HIGH_WATER_MARK),
+            unflushed: 0,
+        }
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(chunk).await?;
+        self.unflushed += chunk.len() as u64;
+        if self.unflushed >= self.high_water_mark {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush().await?;
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+}
+
+#[cfg(test)]
+mod receive_sink_tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A drive that accepts every write at once and only persists on flush, like tokio's
+    /// File in front of a slow disk: whatever is not flushed stays in memory.
+    #[derive(Default)]
+    struct LaggingDisk {
+        backlog: u64,
+        peak_backlog: u64,
+        persisted: u64,
+    }
+
+    impl tokio::io::AsyncWrite for LaggingDisk {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.backlog += buf.len() as u64;
+            self.peak_backlog = self.peak_backlog.max(self.backlog);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.persisted += self.backlog;
+            self.backlog = 0;
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_stays_bounded_with_a_slow_disk() {
+        let chunk = vec![7u8; 64 * 1024];
+        let total = 64 * 1024 * 1024u64;
+        for high_water_mark in [MIN_RECEIVE_HIGH_WATER_MARK, 256 * 1024, DEFAULT_RECEIVE_HIGH_WATER_MARK] {
+            let mut sink = ReceiveSink::new(LaggingDisk::default(), high_water_mark);
+            let mut peak_in_memory = 0u64;
+            for _ in 0..total / chunk.len() as u64 {
+                sink.write(&chunk).await.unwrap();
+                let in_memory = sink.writer.buffer().len() as u64 + sink.get_ref().backlog;
+                peak_in_memory = peak_in_memory.max(in_memory);
+            }
+            sink.flush().await.unwrap();
+
+            let bound = high_water_mark + chunk.len() as u64;
+            assert!(peak_in_memory <= bound, "hwm {}: {} bytes held", high_water_mark, peak_in_memory);
+            assert!(sink.get_ref().peak_backlog <= bound, "hwm {}: {} bytes queued", high_water_mark, sink.get_ref().peak_backlog);
+            assert_eq!(sink.get_ref().persisted, total);
+        }
+    }
+}
+
+// Shared by every active send loop
+static SEND_BUCKET: Lazy<std::sync::Mutex<TokenBucket>> = Lazy::new(|| std::sync::Mutex::new(TokenBucket::new(0)));
+
+async fn sync_send_limit() {
+    let rate = read_settings().await.max_send_bytes_per_sec.unwrap_or(0);
+    SEND_BUCKET.lock().unwrap().set_rate(rate);
+}
+
+/// Pause a send loop after it wrote `n` bytes, if the global send cap requires it.
+async fn throttle_send(n: u64) {
+    let delay = SEND_BUCKET.lock().unwrap().take(n);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// --- First-contact offer rate limiting ---
+struct OfferRate {
+    window_start: Instant,
+    count: u32,
+    cooldown_until: Option<Instant>,
+}
+
+// Tracked IPs beyond this trigger a sweep of expired entries
+const MAX_OFFER_RATE_ENTRIES: usize = 256;
+
+static OFFER_RATES: Lazy<std::sync::Mutex<HashMap<std::net::IpAddr, OfferRate>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Count a first-contact offer from `ip`; true if it should be rejected as `rate_limited`.
+fn offer_rate_limited(ip: std::net::IpAddr, limit: OfferRateLimit) -> bool {
+    if limit.max_offers == 0 {
+        return false;
+    }
+    let now = Instant::now();
+    let window = Duration::from_secs(limit.window_secs);
+    let mut rates = OFFER_RATES.lock().unwrap();
+    if rates.len() > MAX_OFFER_RATE_ENTRIES {
+        rates.retain(|_, r| r.cooldown_until.is_some_and(|t| t > now) || now.duration_since(r.window_start) < window);
+    }
+    let rate = rates.entry(ip).or_insert(OfferRate { window_start: now, count: 0, cooldown_until: None });
+    if let Some(until) = rate.cooldown_until {
+        if until > now {
+            return true;
+        }
+        rate.cooldown_until = None;
+        rate.window_start = now;
+        rate.count = 0;
+    }
+    if now.duration_since(rate.window_start) >= window {
+        rate.window_start = now;
+        rate.count = 0;
+    }
+    rate.count += 1;
+    if rate.count > limit.max_offers {
+        warn!("{} sent {} offers within {}s, ignoring it for {}s", ip, rate.count, limit.window_secs, limit.cooldown_secs);
+        rate.cooldown_until = Some(now + Duration::from_secs(limit.cooldown_secs));
+        return true;
+    }
+    false
+}
+
+#[tauri::command]
+pub async fn get_offer_rate_limit() -> Result<OfferRateLimit, String> {
+    Ok(read_settings().await.offer_rate_limit.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn set_offer_rate_limit(value: OfferRateLimit) -> Result<(), String> {
+    if value.max_offers > 0 && value.window_secs == 0 {
+        return Err("window_secs must be greater than 0".to_string());
+    }
+    update_settings(|s| s.offer_rate_limit = Some(value)).await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_max_receive_bytes_per_sec() -> Result<Option<u64>, String> {
+    Ok(read_settings().await.max_receive_bytes_per_sec)
+}
+
+/// Limit the combined speed of all incoming transfers; `None` or 0 removes the limit.
+#[tauri::command]
+pub async fn set_max_receive_bytes_per_sec(value: Option<u64>) -> Result<(), String> {
+    update_settings(|s| s.max_receive_bytes_per_sec = value.filter(|v| *v > 0)).await;
+    sync_receive_limit().await;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_send_rate_limit() -> Result<Option<u64>, String> {
+    Ok(read_settings().await.max_send_bytes_per_sec)
+}
+
+/// Limit the combined speed of all outgoing transfers; `None` or 0 removes the limit.
+#[tauri::command]
+pub async fn set_send_rate_limit(bytes_per_sec: Option<u64>) -> Result<(), String> {
+    update_settings(|s| s.max_send_bytes_per_sec = bytes_per_sec.filter(|v| *v > 0)).await;
+    sync_send_limit().await;
+    Ok(())
+}
+
+const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+/// Restore the saved verbosity; called once at startup after the logger is attached.
+pub async fn apply_saved_log_level() {
+    let level = read_settings()
+        .await
+        .log_level
+        .and_then(|l| l.parse::<log::LevelFilter>().ok())
+        .unwrap_or(DEFAULT_LOG_LEVEL);
+    log::set_max_level(level);
+}
+
+#[tauri::command]
+pub async fn get_log_level() -> Result<String, String> {
+    Ok(log::max_level().to_string().to_lowercase())
+}
+
+/// Change the verbosity of both the log file/console and `backend_log` events without a restart.
+#[tauri::command]
+pub async fn set_log_level(level: String) -> Result<String, String> {
+    let filter: log::LevelFilter = level
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid log level '{}': expected off, error, warn, info, debug or trace", level))?;
+    let applied = filter.to_string().to_lowercase();
+    log::set_max_level(filter);
+    update_settings(|s| s.log_level = Some(applied.clone())).await;
+    info!("Log level set to {}", applied);
+    Ok(applied)
+}
+
+/// Every setting as actually applied, defaults filled in, plus the runtime values they lead to.
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub app_version: String,
+    pub device_uuid: String,
+    pub capabilities: Vec<String>,
+    pub discovery_port: u16,
+    pub file_server_port: u16,
+    pub ipv6_enabled: bool,
+    /// None while browser receive is off
+    pub web_receive_port: Option<u16>,
+    pub discovery_mode: DiscoveryMode,
+    pub discovery_backend: DiscoveryBackend,
+    pub auto_accept_trusted: bool,
+    pub auto_accept_folder_timeout_secs: u64,
+    /// 0 = paused transfers never time out
+    pub pause_timeout_secs: u64,
+    pub safe_subnets: Vec<String>,
+    pub foreign_network_action: ForeignNetworkAction,
+    pub offer_rate_limit: OfferRateLimit,
+    pub download_dir: Option<PathBuf>,
+    pub collision_policy: CollisionPolicy,
+    pub allow_sender_overwrite: bool,
+    pub dedup_enabled: bool,
+    /// None sends file bodies uncompressed
+    pub compression: Option<String>,
+    pub min_free_space_bytes: u64,
+    pub accepting_transfers: bool,
+    /// 0 = unlimited
+    pub max_receive_bytes_per_sec: u64,
+    /// 0 = unlimited
+    pub max_send_bytes_per_sec: u64,
+    pub receive_high_water_mark: u64,
+    /// Per peer
+    pub max_concurrent_sends: usize,
+    pub notifications_enabled: bool,
+    pub failure_reports_enabled: bool,
+    pub auto_copy_received_text: bool,
+    pub speed_unit: SpeedUnit,
+    pub watched_folders: Vec<PathBuf>,
+    pub paired_devices: usize,
+    pub trusted_devices: usize,
+    pub log_level: String,
+}
+
+/// Read-only snapshot of the whole configuration, for support and for the settings screen.
+#[tauri::command]
+pub async fn get_effective_config() -> Result<EffectiveConfig, String> {
+    let s = read_settings().await;
+    Ok(EffectiveConfig {
+        app_version: APP_VERSION.to_string(),
+        device_uuid: DEVICE_UUID.clone(),
+        capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        discovery_port: configured_discovery_port().await,
+        file_server_port: file_server_port(),
+        ipv6_enabled: s.ipv6_enabled,
+        web_receive_port: web_receive_port().await,
+        discovery_mode: s.discovery_mode,
+        discovery_backend: s.discovery_backend,
+        auto_accept_trusted: s.auto_accept_trusted,
+        auto_accept_folder_timeout_secs: s.auto_accept_folder_timeout_secs.unwrap_or(DEFAULT_AUTO_ACCEPT_FOLDER_TIMEOUT_SECS),
+        pause_timeout_secs: s.pause_timeout_secs.unwrap_or(DEFAULT_PAUSE_TIMEOUT_SECS),
+        safe_subnets: s.safe_subnets.clone(),
+        foreign_network_action: s.foreign_network_action,
+        offer_rate_limit: s.offer_rate_limit.unwrap_or_default(),
+        download_dir: default_download_dir().await,
+        collision_policy: s.collision_policy,
+        allow_sender_overwrite: s.allow_sender_overwrite,
+        dedup_enabled: s.dedup_enabled,
+        compression: s.compression.clone(),
+        min_free_space_bytes: s.min_free_space_bytes.unwrap_or(DEFAULT_MIN_FREE_SPACE_BYTES),
+        accepting_transfers: is_accepting_transfers().await,
+        max_receive_bytes_per_sec: s.max_receive_bytes_per_sec.unwrap_or(0),
+        max_send_bytes_per_sec: s.max_send_bytes_per_sec.unwrap_or(0),
+        receive_high_water_mark: s
+            .receive_high_water_mark
+            .unwrap_or(DEFAULT_RECEIVE_HIGH_WATER_MARK)
+            .max(MIN_RECEIVE_HIGH_WATER_MARK),
+        max_concurrent_sends: MAX_CONCURRENT_SENDS_PER_PEER,
+        notifications_enabled: s.notifications_enabled,
+        failure_reports_enabled: s.failure_reports_enabled,
+        auto_copy_received_text: s.auto_copy_received_text,
+        speed_unit: s.speed_unit,
+        watched_folders: s.watched_folders.clone(),
+        paired_devices: read_paired_keys().await.len(),
+        trusted_devices: read_trusted_macs().await.len(),
+        log_level: log::max_level().to_string().to_lowercase(),
+    })
+}
+
+#[tauri::command]
+pub async fn list_trusted_devices() -> Result<Vec<String>, String> {
+    Ok(read_trusted_macs().await)
+}
+
+/// Trust a device by MAC (or device id / IP); matching is case-insensitive.
+#[tauri::command]
+pub async fn add_trusted_device_mac(mac: String) -> Result<(), String> {
+    let mac = mac.trim().to_lowercase();
+    if mac.is_empty() {
+        return Err("empty device id".to_string());
+    }
+    add_trusted_device_mac_internal(&mac).await
+}
+
+#[tauri::command]
+pub async fn remove_trusted_device_mac(mac: String) -> Result<(), String> {
+    let mac = mac.trim().to_lowercase();
+    let mut list = read_trusted_macs().await;
+    list.retain(|x| !x.eq_ignore_ascii_case(&mac));
+    write_trusted_macs(&list).await.map_err(|e| e.to_string())
+}
+
+/// MAC of the discovered device currently at `ip`, for senders whose offers don't carry one.
+fn discovered_mac(app_handle: &AppHandle, ip: std::net::IpAddr) -> Option<String> {
+    use tauri::Manager;
+    let devices = app_handle.try_state::<crate::SharedDevices>()?;
+    let ip = ip.to_string();
+    let devs = devices.lock().unwrap();
+    devs.iter().find(|e| e.device.ip == ip).and_then(|e| e.device.mac.as_ref()).map(|m| m.to_lowercase())
+}
+
+/// The trusted-list entry a sender matches, if any: its device id, its MAC (from the offer, else
+/// from the discovered device at its address, so a trusted MAC works whatever IP DHCP handed out),
+/// or the IP itself for entries saved from senders with neither.
+async fn trusted_sender_id(app_handle: &AppHandle, offer: &FileOffer, addr: std::net::SocketAddr) -> Option<String> {
+    let trusted = read_trusted_macs().await;
+    // A browser upload is trusted only as itself (sender_uuid "browser:<ip>"), never as the device at its address
+    if offer.forwarded_for.is_some() {
+        return offer.sender_uuid.as_ref().map(|id| id.to_lowercase()).filter(|id| trusted.contains(id));
+    }
+    let mac = offer.sender_mac.clone().or_else(|| discovered_mac(app_handle, addr.ip()));
+    let ip = Some(addr.ip()).filter(|ip| !ip.is_loopback()).map(|ip| ip.to_string());
+    [offer.sender_uuid.clone(), mac, ip]
+        .into_iter()
+        .flatten()
+        .map(|id| id.to_lowercase())
+        .find(|id| trusted.contains(id))
+}
+
+// --- Favorites (pinned devices, independent from trust) ---
+/// Last known details of a favorite, so it can still be listed while offline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FavoriteDevice {
+    /// IP or MAC (lowercase) the user pinned
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub ip: String,
+    #[serde(default)]
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub device_uuid: Option<String>,
+    #[serde(default)]
+    pub last_seen: String,
+}
+
+impl FavoriteDevice {
+    /// Match on the stable device id when both sides know it, else fall back to IP/MAC.
+    pub fn matches(&self, ip: &str, mac: Option<&str>, device_uuid: Option<&str>) -> bool {
+        if let Some(uuid) = device_uuid {
+            if self.id == uuid || self.device_uuid.as_deref() == Some(uuid) {
+                return true;
+            }
+            if self.device_uuid.is_some() {
+                return false;
+            }
+        }
+        self.id == ip || mac.is_some_and(|m| m.eq_ignore_ascii_case(&self.id))
+    }
+}
+
+pub async fn read_favorites() -> Vec<FavoriteDevice> {
+    match favorites_path().await {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+#[tauri::command]
+pub async fn list_favorites() -> Result<Vec<FavoriteDevice>, String> {
+    Ok(read_favorites().await)
+}
+
+/// Pin a device by IP, MAC or device id. Name/IP are snapshotted from the current device list.
+#[tauri::command]
+pub async fn add_favorite(devices: tauri::State<'_, crate::SharedDevices>, ip_or_mac: String) -> Result<(), String> {
+    let id = ip_or_mac.trim().to_lowercase();
+    if id.is_empty() {
+        return Err("empty device id".to_string());
+    }
+    let mut list = read_favorites().await;
+    if list.iter().any(|f| f.id == id) {
+        return Ok(());
+    }
+    let snapshot = {
+        let devs = devices.lock().unwrap();
+        devs.iter()
+            .find(|e| e.device.ip == id || e.device.mac.as_deref() == Some(id.as_str()) || e.device.device_uuid.as_deref() == Some(id.as_str()))
+            .map(|e| e.device.clone())
+    };
+    let favorite = match snapshot {
+        Some(d) => FavoriteDevice { id, name: d.name, ip: d.ip, mac: d.mac, device_uuid: d.device_uuid, last_seen: d.last_seen },
+        None => FavoriteDevice {
+            ip: if id.parse::<std::net::IpAddr>().is_ok() { id.clone() } else { String::new() },
+            device_uuid: Uuid::parse_str(&id).ok().map(|_| id.clone()),
+            name: id.clone(),
+            id,
+            mac: None,
+            last_seen: String::new(),
+        },
+    };
+    list.push(favorite);
+    let p = favorites_path().await.map_err(|e| e.to_string())?;
+    write_json_atomic(&p, &list).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn remove_favorite(ip_or_mac: String) -> Result<(), String> {
+    let id = ip_or_mac.trim().to_lowercase();
+    let mut list = read_favorites().await;
+    list.retain(|f| f.id != id);
+    let p = favorites_path().await.map_err(|e| e.to_string())?;
+    write_json_atomic(&p, &list).await.map_err(|e| e.to_string())
+}
+
+// --- Device nicknames (user labels shown instead of the advertised name) ---
+/// Nicknames keyed by lowercase MAC, or by IP for devices that don't advertise one.
+pub async fn read_device_nicknames() -> HashMap<String, String> {
+    match device_nicknames_path().await {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => HashMap::new(),
+        },
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// The nickname for a device: by MAC when one is set for it, else by IP.
+pub fn nickname_for<'a>(nicknames: &'a HashMap<String, String>, ip: &str, mac: Option<&str>) -> Option<&'a String> {
+    mac.and_then(|m| nicknames.get(&m.to_lowercase())).or_else(|| nicknames.get(ip))
+}
+
+#[tauri::command]
+pub async fn get_device_nicknames() -> Result<HashMap<String, String>, String> {
+    Ok(read_device_nicknames().await)
+}
+
+/// Label a device by MAC (or by IP for devices without one); an empty nickname removes it.
+#[tauri::command]
+pub async fn set_device_nickname(mac: String, nickname: String) -> Result<(), String> {
+    let key = mac.trim().to_lowercase();
+    if key.is_empty() {
+        return Err("empty device id".to_string());
+    }
+    let mut nicknames = read_device_nicknames().await;
+    let nickname = nickname.trim();
+    if nickname.is_empty() {
+        nicknames.remove(&key);
+    } else {
+        nicknames.insert(key, nickname.to_string());
+    }
+    let p = device_nicknames_path().await.map_err(|e| e.to_string())?;
+    write_json_atomic(&p, &nicknames).await.map_err(|e| e.to_string())
+}
+
+// --- Blocked devices (offers refused without a prompt, hidden from the device list) ---
+/// Blocked IPs, MACs or device ids, lowercase.
+pub async fn read_blocked_devices() -> Vec<String> {
+    match blocked_devices_path().await {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Whether any of a device's identifiers is on the blocklist.
+pub fn is_blocked(blocked: &[String], ip: &str, mac: Option<&str>, device_uuid: Option<&str>) -> bool {
+    [Some(ip), mac, device_uuid]
+        .into_iter()
+        .flatten()
+        .any(|id| blocked.iter().any(|b| b.eq_ignore_ascii_case(id)))
+}
+
+#[tauri::command]
+pub async fn list_blocked_devices() -> Result<Vec<String>, String> {
+    Ok(read_blocked_devices().await)
+}
+
+/// Block a device by IP (a MAC or device id works too): its offers are refused with `blocked`.
+#[tauri::command]
+pub async fn block_device(ip: String) -> Result<(), String> {
+    let id = ip.trim().to_lowercase();
+    if id.is_empty() {
+        return Err("empty device id".to_string());
+    }
+    let mut list = read_blocked_devices().await;
+    if list.contains(&id) {
+        return Ok(());
+    }
+    list.push(id);
+    let p = blocked_devices_path().await.map_err(|e| e.to_string())?;
+    write_json_atomic(&p, &list).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn unblock_device(ip: String) -> Result<(), String> {
+    let id = ip.trim().to_lowercase();
+    let mut list = read_blocked_devices().await;
+    list.retain(|b| b != &id);
+    let p = blocked_devices_path().await.map_err(|e| e.to_string())?;
+    write_json_atomic(&p, &list).await.map_err(|e| e.to_string())
+}
+
+// --- Per-peer speed profiles (adaptive send buffer) ---
+const DEFAULT_SEND_BUFFER: usize = 64 * 1024;
+// Weight of the newest transfer in the moving average
+const PEER_SPEED_EMA_WEIGHT: f64 = 0.3;
+// Tiny files say little about link speed
+const MIN_PROFILE_SAMPLE_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerProfile {
+    /// Moving average of completed send speeds, MB/s
+    pub avg_speed: f64,
+    pub samples: u32,
+    pub updated_at: String,
+}
+
+static PEER_PROFILES_LOCK: Lazy<TokioMutex<()>> = Lazy::new(|| TokioMutex::new(()));
+
+async fn read_peer_profiles() -> HashMap<String, PeerProfile> {
+    match peer_profiles_path().await {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => HashMap::new(),
+        },
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Socket write size for a peer: bigger chunks for fast links, smaller ones for slow Wi-Fi.
+async fn send_buffer_size_for(peer: &str) -> usize {
+    match read_peer_profiles().await.get(peer) {
+        Some(p) if p.avg_speed >= 60.0 => 1024 * 1024,
+        Some(p) if p.avg_speed >= 20.0 => 256 * 1024,
+        Some(p) if p.avg_speed < 2.0 => 32 * 1024,
+        _ => DEFAULT_SEND_BUFFER,
+    }
+}
+
+async fn update_peer_profile(peer: &str, bytes: u64, elapsed_ms: u128) {
+    if bytes < MIN_PROFILE_SAMPLE_BYTES {
+        return;
+    }
+    let speed = speed_mb_per_sec(bytes, elapsed_ms);
+    let _guard = PEER_PROFILES_LOCK.lock().await;
+    let mut profiles = read_peer_profiles().await;
+    let profile = profiles.entry(peer.to_string()).or_insert(PeerProfile { avg_speed: speed, samples: 0, updated_at: String::new() });
+    if profile.samples > 0 {
+        profile.avg_speed = PEER_SPEED_EMA_WEIGHT * speed + (1.0 - PEER_SPEED_EMA_WEIGHT) * profile.avg_speed;
+    }
+    profile.samples = profile.samples.saturating_add(1);
+    profile.updated_at = chrono::Utc::now().to_rfc3339();
+    match peer_profiles_path().await {
+        Ok(p) => {
+            if let Err(e) = write_json_atomic(&p, &profiles).await {
+                warn!("Failed to save peer profiles: {}", e);
+            }
+        }
+        Err(e) => warn!("Failed to save peer profiles: {}", e),
+    }
+}
+
+#[tauri::command]
+pub async fn get_peer_profiles() -> Result<HashMap<String, PeerProfile>, String> {
+    Ok(read_peer_profiles().await)
+}
+
+// --- Content hashes and the receiver's dedup index ---
+// Oldest entries are dropped beyond this
+const MAX_HASH_INDEX_ENTRIES: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HashIndexEntry {
+    sha256: String,
+    path: PathBuf,
+    size: u64,
+    // When the file was indexed; 0 for entries written before this was recorded
+    #[serde(default)]
+    mtime_ms: u64,
+}
+
+static HASH_INDEX_LOCK: Lazy<TokioMutex<()>> = Lazy::new(|| TokioMutex::new(()));
+
+async fn read_hash_index() -> Vec<HashIndexEntry> {
+    match hash_index_path().await {
+        Ok(p) => match tokio::fs::read(&p).await {
+            Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Hex SHA256 of a file, read in 64KiB chunks.
+async fn sha256_file(path: &std::path::Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// An indexed file in `dir` with this hash that still has it on disk: unchanged since it was
+/// indexed (same size and mtime), or re-hashed to the same content if it was touched since.
+async fn find_indexed_file(sha256: &str, size: u64, dir: &std::path::Path) -> Option<PathBuf> {
+    let candidates: Vec<HashIndexEntry> = {
+        let _guard = HASH_INDEX_LOCK.lock().await;
+        read_hash_index().await.into_iter().filter(|e| {
+            e.sha256.eq_ignore_ascii_case(sha256) && e.size == size && e.path.parent() == Some(dir)
+        }).collect()
+    };
+    for entry in candidates {
+        let Ok(meta) = tokio::fs::metadata(&entry.path).await else {
+            continue;
+        };
+        if meta.len() != size {
+            continue;
+        }
+        // Same size alone isn't proof: the file may have been edited in place since
+        if mtime_ms(&meta) == entry.mtime_ms || sha256_file(&entry.path).await.is_ok_and(|h| h.eq_ignore_ascii_case(sha256)) {
+            return Some(entry.path);
+        }
+    }
+    None
+}
+
+async fn index_received_file(sha256: &str, path: &std::path::Path, size: u64) -> anyhow::Result<()> {
+    let _guard = HASH_INDEX_LOCK.lock().await;
+    let mut index = read_hash_index().await;
+    index.retain(|e| e.path != path && e.path.exists());
+    let mtime = tokio::fs::metadata(path).await.map_or(0, |m| mtime_ms(&m));
+    index.push(HashIndexEntry { sha256: sha256.to_lowercase(), path: path.to_path_buf(), size, mtime_ms: mtime });
+    if index.len() > MAX_HASH_INDEX_ENTRIES {
+        let excess = index.len() - MAX_HASH_INDEX_ENTRIES;
+        index.drain(..excess);
+    }
+    write_json_atomic(&hash_index_path().await?, &index).await
+}
+
+// --- Sender-side hash cache for watched folders ---
+// Watched folders are rescanned this often (and on add/rehash)
+const HASH_CACHE_RESCAN_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HashCacheEntry {
+    size: u64,
+    mtime_ms: u64,
+    sha256: String,
+}
+
+// Loaded from hash_cache.json on first use, keyed by path
+static HASH_CACHE: Lazy<TokioMutex<Option<HashMap<PathBuf, HashCacheEntry>>>> = Lazy::new(|| TokioMutex::new(None));
+static HASH_CACHE_RESCAN: Lazy<tokio::sync::Notify> = Lazy::new(tokio::sync::Notify::new);
+
+fn mtime_ms(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+async fn with_hash_cache<R>(f: impl FnOnce(&mut HashMap<PathBuf, HashCacheEntry>) -> R) -> R {
+    let mut guard = HASH_CACHE.lock().await;
+    if guard.is_none() {
+        let loaded = match hash_cache_path().await {
+            Ok(p) => match tokio::fs::read(&p).await {
+                Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes).unwrap_or_default(),
+                _ => HashMap::new(),
+            },
+            Err(_) => HashMap::new(),
+        };
+        *guard = Some(loaded);
+    }
+    f(guard.as_mut().unwrap())
+}
+
+async fn save_hash_cache() {
+    let snapshot = with_hash_cache(|c| c.clone()).await;
+    let result = match hash_cache_path().await {
+        Ok(p) => write_json_atomic(&p, &snapshot).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Failed to save hash cache: {}", e);
+    }
+}
+
+/// SHA256 of a file to send, from the cache when its size and mtime are unchanged.
+async fn cached_sha256(path: &std::path::Path) -> std::io::Result<String> {
+    let meta = tokio::fs::metadata(path).await?;
+    let (size, mtime) = (meta.len(), mtime_ms(&meta));
+    let cached = with_hash_cache(|c| {
+        c.get(path).filter(|e| e.size == size && e.mtime_ms == mtime).map(|e| e.sha256.clone())
+    }).await;
+    if let Some(hash) = cached {
+        return Ok(hash);
+    }
+    let hash = sha256_file(path).await?;
+    let watched = read_settings().await.watched_folders;
+    if watched.iter().any(|w| path.starts_with(w)) {
+        with_hash_cache(|c| c.insert(path.to_path_buf(), HashCacheEntry { size, mtime_ms: mtime, sha256: hash.clone() })).await;
+        save_hash_cache().await;
+    }
+    Ok(hash)
+}
+
+/// Regular files under `dir`, recursively; symlinks are not followed.
+fn list_files_recursive(dir: &std::path::Path, out: &mut Vec<(PathBuf, u64, u64)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return; };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = path.symlink_metadata() else { continue; };
+        if meta.is_dir() {
+            list_files_recursive(&path, out);
+        } else if meta.is_file() {
+            out.push((path, meta.len(), mtime_ms(&meta)));
+        }
+    }
+}
+
+/// Files under `folder` for a per-file folder send, in walk order.
+pub(crate) async fn list_folder_files(folder: PathBuf) -> Vec<PathBuf> {
+    tokio::task::spawn_blocking(move || {
+        let mut out = Vec::new();
+        list_files_recursive(&folder, &mut out);
+        out.into_iter().map(|(p, _, _)| p).collect()
+    }).await.unwrap_or_default()
+}
+
+/// Bring the cache in line with the watched folders: hash new or changed files, drop the rest.
+async fn rescan_watched_folders() {
+    let watched = read_settings().await.watched_folders;
+    let files = tokio::task::spawn_blocking(move || {
+        let mut out = Vec::new();
+        for dir in &watched {
+            list_files_recursive(dir, &mut out);
+        }
+        out
+    }).await.unwrap_or_default();
+    let live: std::collections::HashSet<PathBuf> = files.iter().map(|(p, _, _)| p.clone()).collect();
+    with_hash_cache(|c| c.retain(|p, _| live.contains(p))).await;
+    let mut hashed = 0;
+    for (path, size, mtime) in files {
+        let fresh = with_hash_cache(|c| c.get(&path).is_some_and(|e| e.size == size && e.mtime_ms == mtime)).await;
+        if fresh {
+            continue;
+        }
+        match sha256_file(&path).await {
+            Ok(sha256) => {
+                with_hash_cache(|c| c.insert(path, HashCacheEntry { size, mtime_ms: mtime, sha256 })).await;
+                hashed += 1;
+            }
+            Err(e) => warn!("Failed to hash {:?} for the cache: {}", path, e),
+        }
+    }
+    info!("Hash cache: {} files tracked, {} hashed", live.len(), hashed);
+    save_hash_cache().await;
+}
+
+/// Background indexer for watched folders; runs until the app exits.
+pub async fn hash_cache_loop() {
+    loop {
+        if !read_settings().await.watched_folders.is_empty() {
+            rescan_watched_folders().await;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(HASH_CACHE_RESCAN_SECS)) => {}
+            _ = HASH_CACHE_RESCAN.notified() => {}
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn list_watched_folders() -> Result<Vec<PathBuf>, String> {
+    Ok(read_settings().await.watched_folders)
+}
+
+/// Pre-hash the files in `path` (and keep them hashed) so sending them doesn't wait for SHA256.
+#[tauri::command]
+pub async fn add_watched_folder(path: String) -> Result<(), String> {
+    let dir = PathBuf::from(&path);
+    if !dir.is_dir() {
+        return Err(format!("not a folder: {}", path));
+    }
+    if !read_settings().await.watched_folders.contains(&dir) {
+        update_settings(|s| {
+            if !s.watched_folders.contains(&dir) {
+                s.watched_folders.push(dir);
+            }
+        }).await;
+    }
+    HASH_CACHE_RESCAN.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_watched_folder(path: String) -> Result<(), String> {
+    let dir = PathBuf::from(&path);
+    update_settings(|s| s.watched_folders.retain(|d| d != &dir)).await;
+    with_hash_cache(|c| c.retain(|p, _| !p.starts_with(&dir))).await;
+    save_hash_cache().await;
+    Ok(())
+}
+
+/// Forget every cached hash and re-hash the watched folders in the background.
+#[tauri::command]
+pub async fn rehash_watched_folders() -> Result<(), String> {
+    with_hash_cache(|c| c.clear()).await;
+    HASH_CACHE_RESCAN.notify_one();
+    Ok(())
+}
+
+// Helper: try to obtain local MAC as "aa:bb:cc:dd:ee:ff" lowercase
+fn get_local_mac() -> Option<String> {
+    match get_mac_address() {
+        Ok(Some(ma)) => Some(format!("{}", ma).to_lowercase()),
+        _ => {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| warn!("MAC address unavailable, offers identify this device by device id {} only", *DEVICE_UUID));
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOffer {
+    pub transfer_id: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub mime: String,
+    pub sha256: Option<String>,
+    // Optionally, batch_id for batch transfers
+    #[serde(default)]
+    pub batch_id: Option<String>,
+    // Optional sender MAC (added to identify device uniquely)
+    #[serde(default)]
+    pub sender_mac: Option<String>,
+    // Sender's persistent device id; absent on old peers
+    #[serde(default)]
+    pub sender_uuid: Option<String>,
+    // Optional preferred collision strategy (e.g. a sync tool always wants overwrite)
+    #[serde(default)]
+    pub collision_hint: Option<CollisionPolicy>,
+    // Sender's AirShare version (CARGO_PKG_VERSION), absent on old peers
+    #[serde(default)]
+    pub app_version: Option<String>,
+    // Payload kind: None/"file" for files, "text" for a text snippet delivered in memory
+    #[serde(default)]
+    pub kind: Option<String>,
+    // Another offer follows on this connection after this file (MULTI_FILE_CAPABILITY peers only)
+    #[serde(default)]
+    pub keep_alive: bool,
+    // Body compression ("zstd"/"gzip", COMPRESSION_CAPABILITY peers only); file_size stays uncompressed
+    #[serde(default)]
+    pub compression: Option<String>,
+    // Body bytes before encryption when `compression` is set
+    #[serde(default)]
+    pub compressed_size: Option<u64>,
+    // ENCRYPTION_AES_GCM when the body is sealed with a key derived from the receiver's PIN
+    #[serde(default)]
+    pub encryption: Option<String>,
+    // Hex PBKDF2 salt for that key
+    #[serde(default)]
+    pub encryption_salt: Option<String>,
+    // Hex 8-byte nonce prefix; each chunk appends its big-endian u32 index
+    #[serde(default)]
+    pub encryption_nonce: Option<String>,
+    // Position of this file in its batch (0-based) and the batch's file count, when the sender knows them
+    #[serde(default)]
+    pub file_index: Option<usize>,
+    #[serde(default)]
+    pub total_files: Option<usize>,
+    // Browser uploads bridged from web_receive: the browser's IP. Only believed from loopback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_for: Option<String>,
+}
+
+impl FileOffer {
+    /// A plain offer from this device with a fresh transfer id; callers override the kind,
+    /// batch, body and identity fields they need with struct update syntax.
+    pub fn new(file_name: impl Into<String>, file_size: u64, mime: impl Into<String>) -> Self {
+        FileOffer {
+            transfer_id: Uuid::new_v4().to_string(),
+            file_name: file_name.into(),
+            file_size,
+            mime: mime.into(),
+            sha256: None,
+            batch_id: None,
+            sender_mac: get_local_mac(),
+            sender_uuid: Some(DEVICE_UUID.clone()),
+            collision_hint: None,
+            app_version: Some(APP_VERSION.to_string()),
+            kind: None,
+            keep_alive: false,
+            compression: None,
+            compressed_size: None,
+            encryption: None,
+            encryption_salt: None,
+            encryption_nonce: None,
+            file_index: None,
+            total_files: None,
+            forwarded_for: None,
+        }
+    }
+
+    /// Body bytes before encryption: the compressed size for compressed offers.
+    fn body_size(&self) -> u64 {
+        match (&self.compression, self.compressed_size) {
+            (Some(_), Some(size)) => size,
+            _ => self.file_size,
+        }
+    }
+
+    /// Bytes of body that follow the ack, tags included for encrypted offers.
+    fn wire_size(&self) -> u64 {
+        let body = self.body_size();
+        match self.encryption {
+            Some(_) => body + body.div_ceil(ENCRYPTION_CHUNK as u64) * GCM_TAG_LEN,
+            None => body,
+        }
+    }
+}
+
+// Text shares are held in memory, so cap them
+const MAX_TEXT_SHARE_BYTES: u64 = 1024 * 1024;
+
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Protocol compatibility follows the semver major of the app version.
+fn protocol_major(version: &str) -> Option<u64> {
+    version.trim().trim_start_matches('v').split('.').next()?.parse().ok()
+}
+
+/// False only when both versions parse and their majors differ.
+fn is_protocol_compatible(peer_version: &str) -> bool {
+    match (protocol_major(peer_version), protocol_major(APP_VERSION)) {
+        (Some(peer), Some(local)) => peer == local,
+        _ => true,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub size: u64,
+    pub name: String,
+    pub is_file: bool,
+}
+
+/// Average speed in MB/s (the canonical unit, as in TransferRecord.speed).
+fn speed_mb_per_sec(bytes: u64, elapsed_ms: u128) -> f64 {
+    if elapsed_ms == 0 {
+        return 0.0;
+    }
+    (bytes as f64 / 1024.0 / 1024.0) / (elapsed_ms as f64 / 1000.0)
+}
+
+/// Format a MB/s value in the user's chosen unit.
+fn format_speed(mb_per_sec: f64, unit: SpeedUnit) -> String {
+    match unit {
+        SpeedUnit::MBps => format!("{:.1} MB/s", mb_per_sec),
+        // MB here is MiB: 1 MiB/s = 1048576 * 8 bits/s
+        SpeedUnit::Mbps => format!("{:.1} Mbps", mb_per_sec * 1_048_576.0 * 8.0 / 1_000_000.0),
+    }
+}
+
+/// Progress in percent, clamped to [0, 100]; sizes come from the peer and shared counters can run ahead.
+fn percent_of(done: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    ((done as f64 / total as f64) * 100.0).clamp(0.0, 100.0)
+}
+
+/// Calcola l'ETA basandosi sulla velocità di trasferimento attuale
+fn calculate_eta(bytes_transferred: u64, total_bytes: u64, elapsed_ms: u128) -> (u128, String) {
+    if bytes_transferred == 0 || elapsed_ms == 0 {
+        return (0, "Calcolo ETA...".to_string());
+    }
+    
+    let bytes_remaining = total_bytes.saturating_sub(bytes_transferred);
+    let bytes_per_ms = bytes_transferred as f64 / elapsed_ms as f64;
+    
+    if bytes_per_ms <= 0.0 {
+        return (0, "Calcolo ETA...".to_string());
+    }
+    
+    let eta_ms = (bytes_remaining as f64 / bytes_per_ms) as u128;
+    
+    // Formatta l'ETA in formato leggibile
+    let eta_formatted = if eta_ms < 1000 {
+        format!("{}ms rimanenti", eta_ms)
+    } else if eta_ms < 60000 {
+        format!("{:.0}s rimanenti", eta_ms as f64 / 1000.0)
+    } else if eta_ms < 3600000 {
+        let minutes = eta_ms / 60000;
+        let seconds = (eta_ms % 60000) / 1000;
+        format!("{}m {}s rimanenti", minutes, seconds)
+    } else {
+        let hours = eta_ms / 3600000;
+        let minutes = (eta_ms % 3600000) / 60000;
+        format!("{}h {}m rimanenti", hours, minutes)
+    };
+    
+    (eta_ms, eta_formatted)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferType {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferRecord {
+    pub id: String,
+    pub file_name: String,
+    pub file_size: u64,
+    #[serde(rename = "type")]
+    pub transfer_type: TransferType,
+    pub status: TransferStatus,
+    pub from_device: String,
+    pub to_device: String,
+    pub start_time: String,
+    pub duration: u64,
+    pub speed: f64,
+    pub device_type: DeviceType,
+    // Integrity check result: None when no checksum was available
+    #[serde(default)]
+    pub verified: Option<bool>,
+    // Sent only: the receiver's receipt matched what we sent
+    #[serde(default)]
+    pub delivery_confirmed: bool,
+    // The other device's stable id, so history survives IP changes; None for older records
+    #[serde(default)]
+    pub peer_uuid: Option<String>,
+}
+
+/// Integrity details and peer id recorded alongside a history entry.
+#[derive(Debug, Clone, Default)]
+struct RecordExtras {
+    verified: Option<bool>,
+    delivery_confirmed: bool,
+    peer_uuid: Option<String>,
+}
+
+static RECENTS_LOCK: Lazy<TokioMutex<()>> = Lazy::new(|| TokioMutex::new(()));
+
+async fn save_recent_transfer(_app_handle: &AppHandle, record: &TransferRecord) -> anyhow::Result<()> {
+    let _guard = RECENTS_LOCK.lock().await;
+    // Usa dirs::data_dir come base e crea una sottocartella per l'app
+    let mut dir = dirs::data_dir().ok_or_else(|| anyhow::anyhow!("impossibile ottenere data_dir"))?;
+    dir.push("AirShare");
+    // AGGIUNGI QUESTO LOG
+    info!("📁 Recent transfers path: {:?}", dir.join("recent_transfers.json"));
+    if !dir.exists() {
+        tokio::fs::create_dir_all(&dir).await?;
+    }
+    let file_path = dir.join("recent_transfers.json");
+
+    // Leggi JSON esistente (array) oppure crea nuovo
+    let existing: Vec<TransferRecord> = match tokio::fs::read(&file_path).await {
+        Ok(bytes) => {
+            if bytes.is_empty() {
+                Vec::new()
+            } else {
+                match serde_json::from_slice::<Vec<TransferRecord>>(&bytes) {
+                    Ok(v) => v,
+                    Err(_) => Vec::new(),
+                }
+            }
+        }
+        Err(_) => Vec::new(),
+    };
+
+    let mut updated = existing;
+    updated.insert(0, record.clone());
+    // Mantieni solo gli ultimi 100 record per evitare crescita infinita
+    if updated.len() > 100 {
+        updated.truncate(100);
+    }
+
+    write_json_atomic(&file_path, &updated).await
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn add_recent_transfer(
+    app_handle: tauri::AppHandle,
+    file_name: String,
+    file_size: u64,
+    transfer_type: TransferType,
+    _target_ip: String,
+    target_name: String,
+    elapsed_ms: u128,
+    status: TransferStatus,
+    peer_uuid: Option<String>,
+) -> Result<(), String> {
+    record_transfer(app_handle, file_name, file_size, transfer_type, target_name, elapsed_ms, status, RecordExtras { peer_uuid, ..Default::default() }).await
+}
+
+/// Display name and stable id for the other side of a transfer. `target` is whatever the caller
+/// had (often just the IP): the name comes from discovery, then favorites, by device id or IP.
+async fn resolve_peer(app_handle: &AppHandle, peer_uuid: Option<String>, target: &str) -> (String, Option<String>) {
+    use tauri::Manager;
+    let peer_uuid = peer_uuid.map(|u| u.to_lowercase());
+    let found = {
+        let devices = app_handle.state::<crate::SharedDevices>();
+        let devs = devices.lock().unwrap();
+        let by_uuid = peer_uuid.as_deref().and_then(|u| devs.iter().find(|e| e.device.device_uuid.as_deref() == Some(u)));
+        by_uuid
+            .or_else(|| devs.iter().find(|e| e.device.ip == target))
+            .map(|e| (e.device.name.clone(), e.device.device_uuid.clone()))
+    };
+    if let Some((name, uuid)) = found {
+        return (name, peer_uuid.or(uuid));
+    }
+    let favorite = read_favorites().await.into_iter().find(|f| match peer_uuid.as_deref() {
+        Some(u) => f.device_uuid.as_deref() == Some(u),
+        None => f.ip == target,
+    });
+    match favorite {
+        Some(f) => (f.name, peer_uuid.or(f.device_uuid)),
+        None => (target.to_string(), peer_uuid),
+    }
+}
+
+/// Same as add_recent_transfer, with the integrity details of the file.
+#[allow(clippy::too_many_arguments)]
+async fn record_transfer(
+    app_handle: tauri::AppHandle,
+    file_name: String,
+    file_size: u64,
+    transfer_type: TransferType,
+    target_name: String,
+    elapsed_ms: u128,
+    status: TransferStatus,
+    extras: RecordExtras,
+) -> Result<(), String> {
+    // Calcola velocità in MB/s
+    let speed_mbps = speed_mb_per_sec(file_size, elapsed_ms);
+
+    // Nome dispositivo locale
+    let local_device = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "Unknown Device".to_string());
+
+    let (peer_name, peer_uuid) = resolve_peer(&app_handle, extras.peer_uuid, &target_name).await;
+    let (from_device, to_device) = match transfer_type {
+        TransferType::Sent => (local_device.clone(), peer_name.clone()),
+        TransferType::Received => (peer_name.clone(), local_device.clone()),
+    };
+
+    let record = TransferRecord {
+        id: uuid::Uuid::new_v4().to_string(),
+        file_name: file_name.clone(),
+        file_size,
+        transfer_type: transfer_type.clone(),
+        status: status.clone(),
+        from_device,
+        to_device,
+        start_time: chrono::Utc::now().to_rfc3339(),
+        duration: (elapsed_ms / 1000) as u64,
+        speed: speed_mbps,
+        device_type: DeviceType::Desktop,
+        verified: extras.verified,
+        delivery_confirmed: extras.delivery_confirmed,
+        peer_uuid,
+    };
+
+    save_recent_transfer(&app_handle, &record)
+        .await
+        .map_err(|e| format!("failed to save recent transfer: {}", e))?;
+
+    // Invia notifica se il trasferimento è completato
+    if matches!(status, TransferStatus::Completed) {
+        let (from, to) = match transfer_type {
+            TransferType::Sent => (local_device, peer_name),
+            TransferType::Received => (peer_name, local_device),
+        };
+        let _ = send_completion_notification(&app_handle, &file_name, &from, &to, &transfer_type, file_size).await;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_recent_transfers() -> Result<Vec<TransferRecord>, String> {
+    let mut dir = dirs::data_dir()
+        .ok_or_else(|| "impossibile ottenere data_dir".to_string())?;
+    dir.push("AirShare");
+    dir.push("recent_transfers.json");
+    match tokio::fs::read(&dir).await {
+        Ok(bytes) if !bytes.is_empty() => {
+            serde_json::from_slice::<Vec<TransferRecord>>(&bytes)
+                .map_err(|e| format!("Failed to parse transfers: {}", e))
+        }
+        Ok(_) => Ok(Vec::new()),
+        Err(e) => Err(format!("Failed to read file: {}", e)),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Same lowercase names as in the JSON store
+fn serde_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+// `duration` is stored in whole seconds
+fn history_csv(records: &[TransferRecord]) -> String {
+    let mut csv = String::from("id,file_name,file_size,type,status,from_device,to_device,start_time,duration_s,speed_mb_s,peer_uuid\n");
+    for r in records {
+        let row = [
+            csv_field(&r.id),
+            csv_field(&r.file_name),
+            r.file_size.to_string(),
+            serde_name(&r.transfer_type),
+            serde_name(&r.status),
+            csv_field(&r.from_device),
+            csv_field(&r.to_device),
+            csv_field(&r.start_time),
+            r.duration.to_string(),
+            format!("{:.2}", r.speed),
+            csv_field(r.peer_uuid.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod history_csv_tests {
+    use super::*;
+
+    fn record(file_name: &str) -> TransferRecord {
+        TransferRecord {
+            id: "t1".to_string(),
+            file_name: file_name.to_string(),
+            file_size: 2048,
+            transfer_type: TransferType::Received,
+            status: TransferStatus::Completed,
+            from_device: "192.168.1.20".to_string(),
+            to_device: "192.168.1.10".to_string(),
+            start_time: "2026-01-01T10:00:00Z".to_string(),
+            duration: 3,
+            speed: 1.5,
+            device_type: DeviceType::Desktop,
+            verified: None,
+            delivery_confirmed: false,
+            peer_uuid: None,
+        }
+    }
+
+    #[test]
+    fn header_names_duration_in_seconds() {
+        let csv = history_csv(&[record("a.txt")]);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next().unwrap(), "id,file_name,file_size,type,status,from_device,to_device,start_time,duration_s,speed_mb_s,peer_uuid");
+        assert_eq!(lines.next().unwrap(), "t1,a.txt,2048,received,completed,192.168.1.20,192.168.1.10,2026-01-01T10:00:00Z,3,1.50,");
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn commas_and_quotes_are_escaped() {
+        let csv = history_csv(&[record("report, \"final\".pdf")]);
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.starts_with("t1,\"report, \"\"final\"\".pdf\",2048,"), "unexpected row: {}", row);
+    }
+}
+
+/// Transfer history as CSV (one row per record, with a header row). When `path` is given the CSV
+/// is also written there; the text is returned either way.
+#[tauri::command]
+pub async fn export_history_csv(path: Option<String>) -> Result<String, String> {
+    let records = get_recent_transfers().await?;
+    let csv = history_csv(&records);
+    if let Some(path) = path {
+        write_atomic(std::path::Path::new(&path), csv.as_bytes()).await.map_err(|e| e.to_string())?;
+        info!("Exported {} transfers to {}", records.len(), path);
+    }
+    Ok(csv)
+}
+
+/// Write the transfer history to `dest_path` as `"csv"` or `"json"` (same records as
+/// get_recent_transfers). Returns the number of records written.
+#[tauri::command]
+pub async fn export_transfer_history(format: String, dest_path: String) -> Result<usize, String> {
+    let records = get_recent_transfers().await?;
+    let bytes = match format.to_lowercase().as_str() {
+        "csv" => history_csv(&records).into_bytes(),
+        "json" => serde_json::to_vec_pretty(&records).map_err(|e| e.to_string())?,
+        other => return Err(format!("unsupported export format: {} (expected csv or json)", other)),
+    };
+    write_atomic(std::path::Path::new(&dest_path), &bytes).await.map_err(|e| e.to_string())?;
+    info!("Exported {} transfers as {} to {}", records.len(), format, dest_path);
+    Ok(records.len())
+}
+
+async fn delete_transfer_by_id(transfer_id: &str) -> anyhow::Result<()> {
+    let _guard = RECENTS_LOCK.lock().await;
+    let mut dir = dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("impossibile ottenere data_dir"))?;
+    dir.push("AirShare");
+    
+    if !dir.exists() {
+        return Ok(());
+    }
+    
+    let file_path = dir.join("recent_transfers.json");
+    
+    let existing: Vec<TransferRecord> = match tokio::fs::read(&file_path).await {
+        Ok(bytes) if !bytes.is_empty() => {
+            match serde_json::from_slice::<Vec<TransferRecord>>(&bytes) {
+                Ok(v) => v,
+                Err(_) => Vec::new(),
+            }
+        }
+        _ => Vec::new(),
+    };
+    
+    let updated: Vec<TransferRecord> = existing.into_iter()
+        .filter(|t| t.id != transfer_id)
+        .collect();
+    
+    write_json_atomic(&file_path, &updated).await
+}
+
+#[tauri::command]
+pub async fn delete_recent_transfer(transfer_id: String) -> Result<(), String> {
+    delete_transfer_by_id(&transfer_id)
+        .await
+        .map_err(|e| format!("failed to delete transfer: {}", e))
+}
+
+/// Recent transfers started within `[from_iso, to_iso]` (RFC 3339; either bound may be omitted)
+/// and, when `transfer_type` is given ("Sent"/"Received"), in that direction only. Newest first.
+#[tauri::command]
+pub async fn get_recent_transfers_filtered(
+    from_iso: Option<String>,
+    to_iso: Option<String>,
+    transfer_type: Option<String>,
+) -> Result<Vec<TransferRecord>, String> {
+    let bound = |name: &str, value: Option<String>| match value {
+        Some(v) => parse_record_time(&v)
+            .map(Some)
+            .ok_or_else(|| format!("invalid {}: {:?} is not an RFC 3339 date", name, v)),
+        None => Ok(None),
+    };
+    let from = bound("from_iso", from_iso)?;
+    let to = bound("to_iso", to_iso)?;
+    if let (Some(f), Some(t)) = (from, to) {
+        if f > t {
+            return Err("from_iso is after to_iso".to_string());
+        }
+    }
+    let direction = match transfer_type.as_deref() {
+        None => None,
+        Some(t) if t.eq_ignore_ascii_case("sent") => Some(TransferType::Sent),
+        Some(t) if t.eq_ignore_ascii_case("received") => Some(TransferType::Received),
+        Some(t) => return Err(format!("invalid transfer_type: {} (expected Sent or Received)", t)),
+    };
+    let records = get_recent_transfers().await?;
+    Ok(records
+        .into_iter()
+        .filter(|r| match &direction {
+            Some(TransferType::Sent) => matches!(r.transfer_type, TransferType::Sent),
+            Some(TransferType::Received) => matches!(r.transfer_type, TransferType::Received),
+            None => true,
+        })
+        .filter(|r| {
+            if from.is_none() && to.is_none() {
+                return true;
+            }
+            // Records without a readable start time can't be placed in a range
+            parse_record_time(&r.start_time).is_some_and(|t| from.map_or(true, |f| t >= f) && to.map_or(true, |to| t <= to))
+        })
+        .collect())
+}
+
+/// Wipe the whole transfer history; returns how many records were removed.
+#[tauri::command]
+pub async fn clear_recent_transfers() -> Result<usize, String> {
+    let _guard = RECENTS_LOCK.lock().await;
+    let dir = dirs::data_dir()
+        .ok_or_else(|| "impossibile ottenere data_dir".to_string())?
+        .join("AirShare");
+    let file_path = dir.join("recent_transfers.json");
+    let removed = match tokio::fs::read(&file_path).await {
+        Ok(bytes) if !bytes.is_empty() => serde_json::from_slice::<Vec<TransferRecord>>(&bytes).map_or(0, |v| v.len()),
+        _ => 0,
+    };
+    if !dir.exists() {
+        return Ok(0);
+    }
+    write_json_atomic(&file_path, &Vec::<TransferRecord>::new())
+        .await
+        .map_err(|e| format!("failed to clear transfers: {}", e))?;
+    info!("Cleared {} recent transfers", removed);
+    Ok(removed)
+}
+
+/// Get file information for a given file path
+#[tauri::command]
+pub fn get_file_info(file_path: String) -> Result<FileInfo, String> {
+    match std::fs::metadata(&file_path) {
+        Ok(metadata) => {
+            let name = std::path::Path::new(&file_path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string();
+                
+            Ok(FileInfo {
+                size: metadata.len(),
+                name,
+                is_file: metadata.is_file(),
+            })
+        }
+        Err(e) => Err(format!("Failed to get file info: {}", e)),
+    }
+}
+
+// --- Send preview: what a send would contain, without connecting ---
+#[derive(Debug, Serialize)]
+pub struct PreviewItem {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+    pub is_dir: bool,
+    /// Files the item contributes (1 for a file, the recursive count for a folder)
+    pub file_count: usize,
+    /// Why the item can't be sent as-is (missing, unreadable...)
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SendPreview {
+    pub items: Vec<PreviewItem>,
+    pub total_files: usize,
+    pub total_bytes: u64,
+    /// MB/s used for the estimate, from the peer's profile
+    pub estimated_speed: Option<f64>,
+    pub estimated_secs: Option<f64>,
+}
+
+/// Recursive file count and size of a folder; symlinks are not followed.
+fn folder_totals(dir: &std::path::Path) -> std::io::Result<(usize, u64)> {
+    let (mut files, mut bytes) = (0, 0);
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.path().symlink_metadata()?;
+        if meta.is_dir() {
+            let (f, b) = folder_totals(&entry.path())?;
+            files += f;
+            bytes += b;
+        } else if meta.is_file() {
+            files += 1;
+            bytes += meta.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+fn preview_item(path: &str) -> PreviewItem {
+    let p = std::path::Path::new(path);
+    let mut item = PreviewItem {
+        path: path.to_string(),
+        name: p.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string(),
+        size: 0,
+        mime: mime_guess::from_path(p).first_or_octet_stream().to_string(),
+        is_dir: false,
+        file_count: 0,
+        error: None,
+    };
+    match get_file_info(path.to_string()) {
+        Ok(info) if info.is_file => {
+            // Sending opens the file, so an unreadable one would fail later anyway
+            match std::fs::File::open(p) {
+                Ok(_) => {
+                    item.size = info.size;
+                    item.file_count = 1;
+                }
+                Err(e) => item.error = Some(format!("not readable: {}", e)),
+            }
+        }
+        Ok(_) => {
+            item.is_dir = true;
+            item.mime = "inode/directory".to_string();
+            match folder_totals(p) {
+                Ok((files, bytes)) => {
+                    item.file_count = files;
+                    item.size = bytes;
+                }
+                Err(e) => item.error = Some(format!("failed to read folder: {}", e)),
+            }
+        }
+        Err(e) => item.error = Some(e),
+    }
+    item
+}
+
+/// Summarise `paths` for a send confirmation. `peer_key` picks the speed profile used for the estimate.
+pub async fn preview_send_paths(paths: Vec<String>, peer_key: &str) -> Result<SendPreview, String> {
+    let items = tokio::task::spawn_blocking(move || paths.iter().map(|p| preview_item(p)).collect::<Vec<_>>())
+        .await
+        .map_err(|e| e.to_string())?;
+    let total_files = items.iter().map(|i| i.file_count).sum();
+    let total_bytes = items.iter().map(|i| i.size).sum::<u64>();
+    let estimated_speed = read_peer_profiles().await.get(peer_key).map(|p| p.avg_speed).filter(|s| *s > 0.0);
+    let estimated_secs = estimated_speed.map(|mbps| total_bytes as f64 / 1_048_576.0 / mbps);
+    Ok(SendPreview { items, total_files, total_bytes, estimated_speed, estimated_secs })
+}
+
+/// Tell the UI which folder was just picked, so it can offer to remember it with set_default_download_dir.
+fn emit_download_folder_chosen(app_handle: &AppHandle, batch_id: &str, dir: Option<&PathBuf>) {
+    if let Some(dir) = dir {
+        let _ = app_handle.emit("download_folder_chosen", serde_json::json!({
+            "batch_id": batch_id,
+            "path": dir,
+        }));
+    }
+}
+
+/// Emit a backend_log event to the frontend with a level and message
+pub async fn tauri_log(app_handle: &AppHandle, level: &str, message: impl Into<String>) {
+    // Same filter as the `log` macros, so set_log_level quiets both
+    if level.parse::<log::Level>().is_ok_and(|l| l > log::max_level()) {
+        return;
+    }
+    let payload = serde_json::json!({
+        "level": level,
+        "message": message.into(),
+        "ts": chrono::Utc::now().to_rfc3339(),
+    });
+    let _ = app_handle.emit("backend_log", payload);
+}
+
+// --- Active transfers registry (polled by get_transfer_status) ---
+struct ActiveTransfer {
+    peer: String,
+    direction: &'static str,
+    file_name: String,
+    transferred: u64,
+    total: u64,
+    started: Instant,
+}
+
+// std Mutex on purpose: it is never held across an await and lets the guard clean up in Drop
+static ACTIVE_TRANSFERS: Lazy<std::sync::Mutex<HashMap<String, ActiveTransfer>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// Incoming offers still waiting for the user's decision or folder selection
+static PENDING_OFFERS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Keeps a transfer listed in ACTIVE_TRANSFERS for as long as it is alive.
+struct ActiveTransferGuard {
+    transfer_id: String,
+}
+
+impl ActiveTransferGuard {
+    fn register(transfer_id: &str, peer: String, direction: &'static str, file_name: &str, total: u64) -> Self {
+        let mut map = ACTIVE_TRANSFERS.lock().unwrap();
+        map.insert(transfer_id.to_string(), ActiveTransfer {
+            peer,
+            direction,
+            file_name: file_name.to_string(),
+            transferred: 0,
+            total,
+            started: Instant::now(),
+        });
+        ActiveTransferGuard { transfer_id: transfer_id.to_string() }
+    }
+
+    fn update(&self, transferred: u64) {
+        if let Some(t) = ACTIVE_TRANSFERS.lock().unwrap().get_mut(&self.transfer_id) {
+            t.transferred = transferred;
+        }
+    }
+}
+
+impl Drop for ActiveTransferGuard {
+    fn drop(&mut self) {
+        ACTIVE_TRANSFERS.lock().unwrap().remove(&self.transfer_id);
+    }
+}
+
+/// Counts an incoming offer as queued until it is dropped.
+struct PendingOfferGuard;
+
+impl PendingOfferGuard {
+    fn new() -> Self {
+        PENDING_OFFERS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        PendingOfferGuard
+    }
+}
+
+impl Drop for PendingOfferGuard {
+    fn drop(&mut self) {
+        PENDING_OFFERS.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveTransferInfo {
+    pub transfer_id: String,
+    pub peer: String,
+    pub direction: String,
+    pub file_name: String,
+    pub transferred: u64,
+    pub total: u64,
+    pub percent: f64,
+    /// MB/s, same unit as TransferRecord.speed
+    pub speed: f64,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferStatusSnapshot {
+    pub active: Vec<ActiveTransferInfo>,
+    /// Incoming offers awaiting a decision plus sends waiting for a slot
+    pub queued: usize,
+    /// Sum of the active transfers' speeds (MB/s)
+    pub total_speed: f64,
+}
+
+/// Snapshot of all transfers currently in flight. Cheap enough to poll.
+#[tauri::command]
+pub fn get_transfer_status() -> TransferStatusSnapshot {
+    let map = ACTIVE_TRANSFERS.lock().unwrap();
+    let active: Vec<ActiveTransferInfo> = map.iter().map(|(id, t)| {
+        let secs = t.started.elapsed().as_secs_f64();
+        let speed = if secs > 0.0 { (t.transferred as f64 / 1024.0 / 1024.0) / secs } else { 0.0 };
+        let percent = percent_of(t.transferred, t.total);
+        ActiveTransferInfo {
+            transfer_id: id.clone(),
+            peer: t.peer.clone(),
+            direction: t.direction.to_string(),
+            file_name: t.file_name.clone(),
+            transferred: t.transferred,
+            total: t.total,
+            percent,
+            speed,
+            paused: PAUSED_TRANSFERS.lock().unwrap().contains_key(id),
+        }
+    }).collect();
+    let total_speed = active.iter().map(|t| t.speed).sum();
+    TransferStatusSnapshot {
+        active,
+        queued: PENDING_OFFERS.load(std::sync::atomic::Ordering::SeqCst) + SEND_SCHEDULER.lock().unwrap().waiting.len(),
+        total_speed,
+    }
+}
+
+// --- Failure breadcrumbs (strictly local, never sent anywhere) ---
+const BREADCRUMB_LIMIT: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Breadcrumb {
+    pub ts: String,
+    pub event: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub transfer_id: String,
+    pub direction: String,
+    pub peer: String,
+    pub file_name: String,
+    pub error: String,
+    pub created_at: String,
+    pub app_version: String,
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+async fn failure_report_path() -> anyhow::Result<PathBuf> { Ok(app_data_dir().await?.join("last_failure_report.json")) }
+
+/// Last N significant protocol events of a single transfer.
+struct Breadcrumbs {
+    transfer_id: String,
+    direction: &'static str,
+    peer: String,
+    file_name: String,
+    events: std::collections::VecDeque<Breadcrumb>,
+}
+
+impl Breadcrumbs {
+    fn new(transfer_id: &str, direction: &'static str, peer: &str, file_name: &str) -> Self {
+        Breadcrumbs {
+            transfer_id: transfer_id.to_string(),
+            direction,
+            peer: peer.to_string(),
+            file_name: file_name.to_string(),
+            events: std::collections::VecDeque::with_capacity(BREADCRUMB_LIMIT),
+        }
+    }
+
+    fn add(&mut self, event: impl Into<String>) {
+        if self.events.len() == BREADCRUMB_LIMIT {
+            self.events.pop_front();
+        }
+        self.events.push_back(Breadcrumb { ts: chrono::Utc::now().to_rfc3339(), event: event.into() });
+    }
+
+    /// Record a byte milestone each time the transfer crosses the next 25% step.
+    fn progress(&mut self, next_milestone: &mut u64, done: u64, total: u64) {
+        if total == 0 {
+            return;
+        }
+        let percent = done.saturating_mul(100) / total;
+        while *next_milestone <= 100 && percent >= *next_milestone {
+            self.add(format!("bytes {} / {} ({}%)", done, total, *next_milestone));
+            *next_milestone += 25;
+        }
+    }
+
+    /// Dump the trail to `last_failure_report.json` if the user opted in.
+    async fn fail(&mut self, error: impl Into<String>) {
+        let error = error.into();
+        self.add(format!("error: {}", error));
+        if !read_settings().await.failure_reports_enabled {
+            return;
+        }
+        let report = FailureReport {
+            transfer_id: self.transfer_id.clone(),
+            direction: self.direction.to_string(),
+            peer: self.peer.clone(),
+            file_name: self.file_name.clone(),
+            error,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            app_version: APP_VERSION.to_string(),
+            breadcrumbs: self.events.iter().cloned().collect(),
+        };
+        let res = match failure_report_path().await {
+            Ok(p) => write_json_atomic(&p, &report).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!("Failed to write failure report for {}: {}", self.transfer_id, e);
+        }
+    }
+}
+
+/// Most recent failure dump, if any (only written when failure reports are enabled).
+#[tauri::command]
+pub async fn get_last_failure_report() -> Result<Option<FailureReport>, String> {
+    let p = failure_report_path().await.map_err(|e| e.to_string())?;
+    match tokio::fs::read(&p).await {
+        Ok(bytes) if !bytes.is_empty() => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse failure report: {}", e)),
+        _ => Ok(None),
+    }
+}
+
+pub const FILE_SERVER_PORT: u16 = 40124;
+// Ports tried in order when the default one is taken; senders try the same list for peers added by hand
+const FILE_SERVER_PORT_CANDIDATES: std::ops::RangeInclusive<u16> = FILE_SERVER_PORT..=FILE_SERVER_PORT + 4;
+static BOUND_FILE_SERVER_PORT: std::sync::atomic::AtomicU16 = std::sync::atomic::AtomicU16::new(FILE_SERVER_PORT);
+// Whether the file server currently holds BOUND_FILE_SERVER_PORT
+static FILE_SERVER_LISTENING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Port the file server actually listens on, advertised in the heartbeat.
+pub fn file_server_port() -> u16 {
+    BOUND_FILE_SERVER_PORT.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Whether the file server is listening on `file_server_port()` right now.
+pub fn file_server_listening() -> bool {
+    FILE_SERVER_LISTENING.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+async fn preferred_file_server_port() -> u16 {
+    read_settings().await.transfer_port.unwrap_or(FILE_SERVER_PORT)
+}
+
+/// UDP discovery port from the settings; the heartbeat and listener read it once at startup.
+pub async fn configured_discovery_port() -> u16 {
+    read_settings().await.discovery_port.unwrap_or(crate::BROADCAST_PORT)
+}
+
+#[tauri::command]
+pub async fn get_transfer_port() -> Result<u16, String> {
+    Ok(preferred_file_server_port().await)
+}
+
+/// Set the TCP port the file server listens on (the next free one of the four after it if taken).
+/// Takes effect on the next start; peers learn it from the heartbeat.
+#[tauri::command]
+pub async fn set_transfer_port(port: u16) -> Result<(), String> {
+    if port == 0 {
+        return Err("port must be between 1 and 65535".to_string());
+    }
+    update_settings(|s| s.transfer_port = (port != FILE_SERVER_PORT).then_some(port)).await;
+    info!("Transfer port set to {} (applies after restart)", port);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_discovery_port() -> Result<u16, String> {
+    Ok(configured_discovery_port().await)
+}
+
+/// Set the UDP port heartbeats are sent and heard on. Takes effect on the next start, and only
+/// devices using the same discovery port see each other.
+#[tauri::command]
+pub async fn set_discovery_port(port: u16) -> Result<(), String> {
+    if port == 0 {
+        return Err("port must be between 1 and 65535".to_string());
+    }
+    update_settings(|s| s.discovery_port = (port != crate::BROADCAST_PORT).then_some(port)).await;
+    info!("Discovery port set to {} (applies after restart)", port);
+    Ok(())
+}
+
+/// Whether IPv6 discovery and transfers are on; read once at startup like the ports.
+pub async fn ipv6_enabled() -> bool {
+    read_settings().await.ipv6_enabled
+}
+
+#[tauri::command]
+pub async fn get_ipv6_enabled() -> Result<bool, String> {
+    Ok(ipv6_enabled().await)
+}
+
+/// Turn IPv6 discovery (multicast to ff02::1 next to the IPv4 broadcast) and IPv6 transfers on
+/// or off. Takes effect on the next start.
+#[tauri::command]
+pub async fn set_ipv6_enabled(enabled: bool) -> Result<(), String> {
+    update_settings(|s| s.ipv6_enabled = enabled).await;
+    info!("IPv6 {} (applies after restart)", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+/// An IPv6-only socket on `[::]:port`, so it can share the port with the IPv4 one.
+pub fn bind_ipv6_only(ty: socket2::Type, port: u16) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV6, ty, None)?;
+    socket.set_only_v6(true)?;
+    socket.bind(&std::net::SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+// Peers' advertised IPv6 addresses keyed by their IPv4 address, which stays the peer's identity
+static PEER_IPV6: Lazy<std::sync::Mutex<HashMap<String, String>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+const IPV6_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Remember (or forget) the IPv6 address a peer advertised in its heartbeat.
+pub fn note_peer_ipv6(ipv4: &str, ipv6: Option<&str>) {
+    let mut peers = PEER_IPV6.lock().unwrap();
+    match ipv6 {
+        Some(v6) => {
+            peers.insert(ipv4.to_string(), v6.to_string());
+        }
+        None => {
+            peers.remove(ipv4);
+        }
+    }
+}
+
+/// The IPv4 address a peer is listed under, for a connection that reached us over IPv6.
+fn peer_ipv4_for(v6: &std::net::Ipv6Addr) -> Option<std::net::Ipv4Addr> {
+    let peers = PEER_IPV6.lock().unwrap();
+    peers
+        .iter()
+        .find(|(_, addr)| addr.parse::<std::net::Ipv6Addr>().is_ok_and(|a| a == *v6))
+        .and_then(|(ipv4, _)| ipv4.parse().ok())
+}
+
+/// Connect to a peer's file server. When `port` is the default (a hand-added device or one whose
+/// port we don't know), the fallback ports are tried too before giving up.
+/// Prefers the peer's IPv6 address when both ends advertise one, falling back to IPv4.
+async fn connect_file_server(ip: &str, port: u16) -> std::io::Result<TcpStream> {
+    let ipv6 = PEER_IPV6.lock().unwrap().get(ip).cloned();
+    if let Some(v6) = ipv6.filter(|_| crate::get_local_ipv6().is_some()) {
+        match timeout(IPV6_CONNECT_TIMEOUT, TcpStream::connect((v6.as_str(), port))).await {
+            Ok(Ok(s)) => {
+                info!("Connected to {} over IPv6 ({})", ip, v6);
+                return Ok(s);
+            }
+            Ok(Err(e)) => warn!("IPv6 connect to {} ({}) failed: {}, falling back to IPv4", ip, v6, e),
+            Err(_) => warn!("IPv6 connect to {} ({}) timed out, falling back to IPv4", ip, v6),
+        }
+    }
+    let first_err = match TcpStream::connect(format!("{}:{}", ip, port)).await {
+        Ok(s) => return Ok(s),
+        Err(e) => e,
+    };
+    if port != FILE_SERVER_PORT || first_err.kind() != std::io::ErrorKind::ConnectionRefused {
+        return Err(first_err);
+    }
+    for candidate in FILE_SERVER_PORT_CANDIDATES.skip(1) {
+        if let Ok(s) = TcpStream::connect(format!("{}:{}", ip, candidate)).await {
+            info!("{} has no file server on {}, connected on fallback port {}", ip, port, candidate);
+            return Ok(s);
+        }
+    }
+    Err(first_err)
+}
+
+// --- Startup errors (kept so the UI can fetch them after it has mounted) ---
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupError {
+    pub code: String,
+    pub port: u16,
+    pub message: String,
+}
+
+static STARTUP_ERRORS: Lazy<std::sync::Mutex<Vec<StartupError>>> = Lazy::new(|| std::sync::Mutex::new(Vec::new()));
+
+/// Record a startup failure and emit it as `startup_error`.
+/// A bind on a port already taken (usually another AirShare instance) is reported as `port_in_use`.
+pub fn report_bind_error(app_handle: &AppHandle, port: u16, e: &std::io::Error) {
+    let code = if e.kind() == std::io::ErrorKind::AddrInUse { "port_in_use" } else { "bind_failed" };
+    let err = StartupError {
+        code: code.to_string(),
+        port,
+        message: format!("Failed to bind port {}: {}", port, e),
+    };
+    error!("[STARTUP] {} ({})", err.message, err.code);
+    let _ = app_handle.emit("startup_error", &err);
+    STARTUP_ERRORS.lock().unwrap().push(err);
+}
+
+#[tauri::command]
+pub fn get_startup_errors() -> Vec<StartupError> {
+    STARTUP_ERRORS.lock().unwrap().clone()
+}
+
+const FILE_SERVER_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const FILE_SERVER_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Bind the first free port of `preferred` and the four after it.
+async fn bind_file_server(preferred: u16) -> std::io::Result<(TcpListener, u16)> {
+    let candidates = preferred..=preferred.saturating_add(4);
+    let last = *candidates.end();
+    for port in candidates {
+        match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(l) => return Ok((l, port)),
+            // Another program has the port: move on to the next candidate
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && port < last => {
+                warn!("File server port {} is in use, trying {}", port, port + 1);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, "no file server port available"))
+}
+
+/// Accept errors that concern one connection (or a momentary fd shortage), not the listener.
+fn is_transient_accept_error(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), ConnectionAborted | ConnectionReset | ConnectionRefused | Interrupted | WouldBlock | TimedOut)
+        || is_out_of_descriptors(e)
+}
+
+/// Out of sockets until some connection closes: EMFILE / ENFILE, or WSAEMFILE on Windows.
+#[cfg(unix)]
+fn is_out_of_descriptors(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ENFILE) | Some(libc::EMFILE))
+}
+
+#[cfg(windows)]
+fn is_out_of_descriptors(e: &std::io::Error) -> bool {
+    const WSAEMFILE: i32 = 10024;
+    e.raw_os_error() == Some(WSAEMFILE)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_out_of_descriptors(_e: &std::io::Error) -> bool {
+    false
+}
+
+/// Run the TCP file server for incoming transfers, rebinding with backoff if the listener dies.
+/// Emits `server_down` when it stops and `server_restarted` once it listens again.
+pub async fn start_file_server(app_handle: tauri::AppHandle) -> anyhow::Result<()> {
+    let mut backoff = FILE_SERVER_MIN_BACKOFF;
+    let mut first_attempt = true;
+    loop {
+        let preferred = preferred_file_server_port().await;
+        let error = match bind_file_server(preferred).await {
+            Ok((listener, port)) => {
+                BOUND_FILE_SERVER_PORT.store(port, std::sync::atomic::Ordering::Relaxed);
+                FILE_SERVER_LISTENING.store(true, std::sync::atomic::Ordering::Relaxed);
+                if !first_attempt {
+                    info!("File server restarted on port {}", port);
+                    let _ = app_handle.emit("server_restarted", serde_json::json!({ "port": port }));
+                }
+                backoff = FILE_SERVER_MIN_BACKOFF;
+                // IPv6 peers get their own listener on the same port, stopped with the IPv4 one
+                let ipv6_task = if ipv6_enabled().await {
+                    match bind_ipv6_only(socket2::Type::STREAM, port).and_then(|s| {
+                        s.listen(128)?;
+                        TcpListener::from_std(s.into())
+                    }) {
+                        Ok(l) => Some(tokio::spawn(serve_file_connections(app_handle.clone(), l, port))),
+                        Err(e) => {
+                            warn!("IPv6 file server on port {} not started: {}", port, e);
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                let error = serve_file_connections(app_handle.clone(), listener, port).await;
+                if let Some(task) = ipv6_task {
+                    task.abort();
+                }
+                error
+            }
+            Err(e) => {
+                // Only the first failure is a startup error; retries are reported via server_down
+                if first_attempt {
+                    report_bind_error(&app_handle, preferred, &e);
+                }
+                e
+            }
+        };
+        first_attempt = false;
+        FILE_SERVER_LISTENING.store(false, std::sync::atomic::Ordering::Relaxed);
+        error!("File server down: {}, retrying in {:?}", error, backoff);
+        let _ = app_handle.emit("server_down", serde_json::json!({
+            "error": error.to_string(),
+            "retry_in_ms": backoff.as_millis() as u64,
+        }));
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(FILE_SERVER_MAX_BACKOFF);
+    }
+}
+
+/// Accept and handle connections until the listener fails; returns that error.
+async fn serve_file_connections(app_handle: tauri::AppHandle, listener: TcpListener, port: u16) -> std::io::Error {
+    let local = listener.local_addr().map_or_else(|_| format!("0.0.0.0:{}", port), |a| a.to_string());
+    info!("File server listening on {}", local);
+    tauri_log(&app_handle, "info", format!("File server listening on {}", local)).await;
+    info!("Entering file server loop");
+    
+    // Log delle interfacce di rete disponibili per debug
+    if let Ok(addrs) = get_if_addrs::get_if_addrs() {
+        info!("Available network interfaces:");
+        for iface in addrs {
+            if !iface.is_loopback() {
+                info!("  - {}: {}", iface.name, iface.ip());
+            }
+        }
+    }
+    loop {
+        let (mut socket, addr) = match listener.accept().await {
+            Ok(res) => {
+                info!("Accepted new connection from {}", res.1);
+                tauri_log(&app_handle, "info", format!("Accepted new connection from {}", res.1)).await;
+                res
+            },
+            Err(e) if is_transient_accept_error(&e) => {
+                warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                tauri_log(&app_handle, "error", format!("Failed to accept connection: {}", e)).await;
+                return e;
+            }
+        };
+
+        if let Err(e) = socket.set_nodelay(true) {
+            warn!("Failed to set TCP_NODELAY on {}: {}", addr, e);
+            tauri_log(&app_handle, "warn", format!("Failed to set TCP_NODELAY on {}: {}", addr, e)).await;
+        }
+
+        let app_handle = app_handle.clone();
+        tokio::spawn(async move {
+            // A keep_alive offer is followed by the next file's header on this connection
+            let mut files_on_connection = 0usize;
+            while handle_offer(app_handle.clone(), &mut socket, addr, files_on_connection).await {
+                files_on_connection += 1;
+            }
+        });
+    }
+}
+
+/// The address the checks on an offer use: for a browser upload that came over web_receive's loopback
+/// bridge the browser's own (`forwarded_for` is ignored from anywhere else); for a connection to the
+/// IPv6 listener the IPv4 address the peer is discovered, trusted and blocked under; otherwise the socket's.
+fn effective_peer_addr(offer: &FileOffer, addr: std::net::SocketAddr) -> std::net::SocketAddr {
+    match offer.forwarded_for.as_deref().and_then(|ip| ip.parse().ok()) {
+        Some(ip) if addr.ip().is_loopback() => return std::net::SocketAddr::new(ip, addr.port()),
+        _ => {}
+    }
+    match addr.ip() {
+        std::net::IpAddr::V6(v6) => match v6.to_ipv4_mapped().or_else(|| peer_ipv4_for(&v6)) {
+            Some(v4) => std::net::SocketAddr::new(v4.into(), addr.port()),
+            None => addr,
+        },
+        std::net::IpAddr::V4(_) => addr,
+    }
+}
+
+/// Handle one offer on an accepted connection: read its header, answer it and receive the body.
+/// Returns true when the sender announced another offer on the same connection (`keep_alive`).
+async fn handle_offer(app_handle: AppHandle, socket: &mut TcpStream, addr: std::net::SocketAddr, files_on_connection: usize) -> bool {
+    // Read header JSON until newline
+    let mut header_buf = Vec::new();
+    info!("({addr}) Waiting for header JSON line (ending with \\n)...");
+    loop {
+        let mut byte = [0u8; 1];
+        if let Err(e) = socket.read_exact(&mut byte).await {
+            if header_buf.is_empty() && e.kind() == std::io::ErrorKind::UnexpectedEof {
+                if files_on_connection > 0 {
+                    info!("({addr}) Sender closed the connection after {} files", files_on_connection);
+                } else {
+                    // Connect-and-close, e.g. a peer's refresh_discovery reachability check
+                    info!("({addr}) Connection closed before any header (reachability probe)");
+                }
+                return false;
+            }
+            error!("({addr}) Failed to read header byte (client closed early?): {}", e);
+            // Could not read header at all -> nothing we can do; no ack to send
+            return false;
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        header_buf.push(byte[0]);
+        // Limit header size for safety
+        if header_buf.len() > 16 * 1024 {
+            error!("({addr}) Header too large (>16KiB) without newline. Sending negative ack and closing.");
+            let nack = serde_json::json!({ "accept": false, "error": "header too large or missing newline" });
+            let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+            if let Err(e) = socket.write_all(nack_str.as_bytes()).await {
+                error!("({addr}) Failed to write negative ack: {}", e);
+            } else {
+                let _ = socket.flush().await;
+                info!("({addr}) Negative ack sent for oversized header.");
+            }
+            return false;
+        }
+    }
+    info!("({addr}) Read header bytes, length: {}", header_buf.len());
+    let header_str = match String::from_utf8(header_buf) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("({addr}) Invalid header utf8: {}. Sending negative ack.", e);
+            tauri_log(&app_handle, "error", format!("Invalid header utf8 from {}: {}", addr, e)).await;
+            let nack = serde_json::json!({ "accept": false, "error": "invalid utf8 in header" });
+            let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+            if let Err(w) = socket.write_all(nack_str.as_bytes()).await {
+                error!("({addr}) Failed to write negative ack: {}", w);
+            } else {
+                let _ = socket.flush().await;
+                info!("({addr}) Negative ack sent due to UTF-8 error.");
+            }
+            return false;
+        }
+    };
+    info!("({addr}) Received header line: {}", header_str);
+
+    if serde_json::from_str::<ControlHeader>(&header_str).is_ok_and(|h| h.kind.as_deref() == Some("ping")) {
+        reply_ping(socket).await;
+        return false;
+    }
+    let offer: FileOffer = match serde_json::from_str(&header_str) {
+        Ok(o) => o,
+        Err(e) => {
+            error!("({addr}) Invalid header JSON: {}. Sending negative ack.", e);
+            tauri_log(&app_handle, "error", format!("Invalid header JSON from {}: {}", addr, e)).await;
+            let nack = serde_json::json!({ "accept": false, "error": "invalid json" });
+            let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+            if let Err(w) = socket.write_all(nack_str.as_bytes()).await {
+                error!("({addr}) Failed to write negative ack: {}", w);
+            } else {
+                let _ = socket.flush().await;
+                info!("({addr}) Negative ack sent due to JSON parse error.");
+            }
+            return false;
+        }
+    };
+    info!(
+        "[RECV] Parsed FileOffer | transfer_id={} batch_id={:?} file_name={}",
+        offer.transfer_id, offer.batch_id, offer.file_name
+    );
+    // Emit the full header JSON line to the frontend for debugging
+    tauri_log(&app_handle, "debug", format!("[RECV] Full FileOffer JSON: {}", header_str)).await;
+    // Determine batch_id (use transfer_id if not present)
+    let batch_id = offer.batch_id.clone().unwrap_or_else(|| offer.transfer_id.clone());
+    info!("({addr}) Parsed file offer: {:?}, batch_id: {}", offer, batch_id);
+    tauri_log(&app_handle, "info", format!("Parsed file offer from {}: {} ({} bytes)", addr, offer.file_name, offer.file_size)).await;
+
+    // Answered before any version or trust check: mismatches are exactly what it is for
+    if offer.kind.as_deref() == Some(IDENTITY_KIND) {
+        reply_identity(socket, addr).await;
+        return false;
+    }
+    // From here on every check (blocklist, trust, subnet, rate limit, history) sees the real peer
+    let addr = effective_peer_addr(&offer, addr);
+    let blocked = read_blocked_devices().await;
+    if is_blocked(&blocked, &addr.ip().to_string(), offer.sender_mac.as_deref(), offer.sender_uuid.as_deref()) {
+        info!("({addr}) Refusing offer {} from blocked device", offer.transfer_id);
+        let nack = serde_json::json!({ "accept": false, "error": "blocked", "device_uuid": *DEVICE_UUID });
+        let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+        let _ = socket.flush().await;
+        return false;
+    }
+    let transfer_id = offer.transfer_id.clone();
+    let mut crumbs = Breadcrumbs::new(&transfer_id, "receive", &addr.to_string(), &offer.file_name);
+    crumbs.add(format!("offer received ({} bytes, batch {})", offer.file_size, batch_id));
+
+    if let Some(ref peer_version) = offer.app_version {
+        if !is_protocol_compatible(peer_version) {
+            warn!("({addr}) Refusing offer from incompatible version {} (local {})", peer_version, APP_VERSION);
+            tauri_log(&app_handle, "warn", format!("Refusing offer from {}: incompatible version {} (local {})", addr, peer_version, APP_VERSION)).await;
+            let nack = serde_json::json!({ "accept": false, "error": "incompatible_version", "app_version": APP_VERSION });
+            let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+            let _ = socket.write_all(nack_str.as_bytes()).await;
+            let _ = socket.flush().await;
+            return false;
+        }
+    }
+    let _batch_conn = BatchConnection::open(&batch_id);
+    if is_batch_cancelled(&batch_id).await {
+        info!("({addr}) Refusing {} from cancelled batch {}", offer.file_name, batch_id);
+        let nack = serde_json::json!({ "accept": false, "error": "batch_cancelled", "device_uuid": *DEVICE_UUID });
+        let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+        let _ = socket.flush().await;
+        return false;
+    }
+    if let Some(ref compression) = offer.compression {
+        // Only plain file bodies are ever compressed; streamed ones (no compressed_size) are never encrypted
+        let streamed = offer.compressed_size.is_none();
+        if !SUPPORTED_COMPRESSIONS.contains(&compression.as_str()) || offer.kind.is_some() || (streamed && offer.encryption.is_some()) {
+            warn!("({addr}) Refusing {} with unsupported compression {:?}", offer.file_name, compression);
+            let nack = serde_json::json!({ "accept": false, "error": "unsupported_compression", "device_uuid": *DEVICE_UUID });
+            let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+            let _ = socket.flush().await;
+            return false;
+        }
+    }
+    let mut body_cipher = None;
+    if let Some(ref encryption) = offer.encryption {
+        let pin = RECEIVE_PIN.lock().unwrap().clone();
+        let error = if encryption != ENCRYPTION_AES_GCM || offer.kind.is_some() {
+            Some("unsupported_encryption")
+        } else if let Some(pin) = pin {
+            match BodyCipher::for_offer(&offer, &pin).await {
+                Ok(cipher) => {
+                    body_cipher = Some(cipher);
+                    None
+                }
+                Err(e) => {
+                    warn!("({addr}) Bad encryption parameters from {}: {}", addr, e);
+                    Some("unsupported_encryption")
+                }
+            }
+        } else {
+            // No PIN on screen yet: let the UI show one so the sender can retry with it
+            let _ = app_handle.emit("encryption_pin_required", serde_json::json!({
+                "transfer_id": offer.transfer_id,
+                "file_name": offer.file_name,
+                "ip": addr.ip().to_string()
+            }));
+            Some("encryption_pin_required")
+        };
+        if let Some(error) = error {
+            info!("({addr}) Refusing encrypted offer {}: {}", offer.file_name, error);
+            let nack = serde_json::json!({ "accept": false, "error": error, "device_uuid": *DEVICE_UUID });
+            let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+            let _ = socket.flush().await;
+            return false;
+        }
+    }
+    if offer.kind.as_deref() == Some("text") {
+        receive_text_share(&app_handle, socket, &offer, addr).await;
+        return false;
+    }
+    // Record transfer -> ip for potential trust saving
+    {
+        let mut tmap = TRANSFER_IPS.lock().await;
+        // prefer the sender's device id, then sender_mac, fallback to ip string
+        if let Some(ref uuid) = offer.sender_uuid {
+            tmap.insert(transfer_id.clone(), uuid.to_lowercase());
+        } else if let Some(ref mac) = offer.sender_mac {
+            tmap.insert(transfer_id.clone(), mac.to_lowercase());
+        } else {
+            tmap.insert(transfer_id.clone(), addr.ip().to_string());
+        }
+    }
+    let mut accept: bool;
+    let mut save_dir: Option<PathBuf>;
+    let mut is_batch_first = false;
+
+    // Check if we already have a batch response
+    {
+        let map = BATCH_RESPONSES.lock().await;
+        if let Some((a, d)) = map.get(&batch_id) {
+            info!("({addr}) [BATCH] Existing batch_id {} found. Reusing accept/dir for new connection.", batch_id);
+            tauri_log(&app_handle, "info", format!("[BATCH] Existing batch_id {} found. Reusing accept/dir for new connection from {}.", batch_id, addr)).await;
+            accept = *a;
+            save_dir = d.clone();
+        } else {
+            info!("({addr}) [BATCH] No entry for batch_id {}. Checking auto-accept/trust or asking user.", batch_id);
+            tauri_log(&app_handle, "info", format!("[BATCH] No entry for batch_id {}. Checking auto-accept/trust or asking user from {}.", batch_id, addr)).await;
+            is_batch_first = true;
+            accept = false;
+            save_dir = None;
+        }
+    }
+
+    if is_batch_first {
+        let _pending = PendingOfferGuard::new();
+        // Check if auto-accept is enabled and IP is trusted
+        let maybe_mac = offer.sender_mac.clone().map(|s| s.to_lowercase());
+        let auto_enabled = read_settings().await.auto_accept_trusted;
+        let maybe_uuid = offer.sender_uuid.clone().map(|s| s.to_lowercase());
+        let trusted_id = trusted_sender_id(&app_handle, &offer, addr).await;
+        let mut should_auto_accept = auto_enabled && trusted_id.is_some();
+        if should_auto_accept {
+            should_auto_accept = trusted_network_allows_auto_accept(&app_handle, addr, &transfer_id, trusted_id.clone()).await;
+        }
+        if should_auto_accept {
+            // Prefer the MAC for display, the device id when MAC is unavailable
+            let sender_id = maybe_mac.clone().or(maybe_uuid.clone()).or(trusted_id.clone()).unwrap_or_default();
+            info!("({addr}) ✅ Auto-accept enabled for trusted device: {}", sender_id);
+            tauri_log(&app_handle, "info", format!("✅ Auto-accept enabled for trusted device: {}", sender_id)).await;
+
+            accept = true;
+
+            // Emit notification event to frontend
+            let _ = app_handle.emit(
+                "transfer_auto_accepted",
+                serde_json::json!({
+                    "transfer_id": transfer_id,
+                    "file_name": offer.file_name,
+                    "file_size": offer.file_size,
+                    "ip": addr.ip().to_string(),
+                    "device_name": sender_id,
+                }),
+            );
+
+            let settings = read_settings().await;
+            let default_dir = settings.default_download_dir.clone().filter(|d| d.is_dir());
+            let chosen_dir = if let Some(dir) = default_dir {
+                // Hands-free: save straight into the default download folder
+                info!("({addr}) Auto-accept: using default download folder {:?}", dir);
+                tauri_log(&app_handle, "info", format!("Auto-accept: saving to default download folder {:?}", dir)).await;
+                Some(dir)
+            } else {
+                // Ask only for destination folder (auto-accept)
+                use std::sync::Arc;
+                use tokio::sync::Mutex;
+                let save_dir_result: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
+                let save_dir_clone = save_dir_result.clone();
+
+                FileDialogBuilder::new(app_handle.dialog().clone())
+                    .set_title("Scegli la cartella di destinazione per il file dal dispositivo fidato")
+                    .pick_folder(move |path| {
+                        let save_dir_clone = save_dir_clone.clone();
+                        tauri::async_runtime::spawn(async move {
+                            let mut result = save_dir_clone.lock().await;
+                            *result = path.and_then(|p| p.as_path().map(|path| PathBuf::from(path)));
+                        });
+                    });
+
+                info!("({addr}) Auto-accept: Waiting for user to select destination folder...");
+                tauri_log(&app_handle, "info", format!("Auto-accept: Waiting for destination folder selection for {}", addr.ip())).await;
+
+                // Wait for folder selection with timeout (0 = no limit)
+                let timeout_secs = settings.auto_accept_folder_timeout_secs.unwrap_or(DEFAULT_AUTO_ACCEPT_FOLDER_TIMEOUT_SECS);
+                let timeout_duration = tokio::time::Duration::from_secs(timeout_secs);
+                let start_time = tokio::time::Instant::now();
+
+                let picked = loop {
+                    if timeout_secs > 0 && start_time.elapsed() > timeout_duration {
+                        error!("({addr}) Timeout waiting for folder selection");
+                        tauri_log(&app_handle, "error", format!("Timeout waiting for folder selection from {}", addr)).await;
+
+                        // Send rejection
+                        let nack = serde_json::json!({ "accept": false, "error": "timeout_folder_selection" });
+                        let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+                        let _ = socket.write_all(nack_str.as_bytes()).await;
+                        let _ = socket.flush().await;
+                        return false;
+                    }
+
+                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                    let result = save_dir_result.lock().await;
+                    if result.is_some() {
+                        break result.clone();
+                    }
+                };
+                emit_download_folder_chosen(&app_handle, &batch_id, picked.as_ref());
+                picked
+            };
+
+            save_dir = chosen_dir;
+
+            if save_dir.is_none() {
+                info!("({addr}) User cancelled folder selection for auto-accepted transfer");
+                tauri_log(&app_handle, "info", format!("User cancelled folder selection for auto-accepted transfer from {}", addr)).await;
+
+                // Send rejection
+                let nack = serde_json::json!({ "accept": false, "error": "user_cancelled_folder" });
+                let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
+                if let Err(e) = socket.write_all(nack_str.as_bytes()).await {
+                    error!("({addr}) Failed to write cancellation ack: {}", e);
+                } else {
+                    let _ = socket.flush().await;
+                }
+                return false;
+            }
+
+            // Save to BATCH_RESPONSES
+            {
+                let mut map = BATCH_RESPONSES.lock().await;
+                map.insert(batch_id.clone(), (accept, save_dir.clone()));
+                info!("({addr}) [BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = true (auto-accept) and save_dir = {:?}", batch_id, save_dir);
+                tauri_log(&app_handle, "info", format!("[BATCH] Saved batch_id {} to BATCH_RESPONSES (auto-accept)", batch_id)).await;
+            }
+        } else {
+            // An untrusted peer flooding us with prompts is turned away silently
+            if trusted_id.is_none() && offer_rate_limited(addr.ip(), read_settings().await.offer_rate_limit.unwrap_or_default()) {
+                log::debug!("({addr}) Offer {} rate limited", transfer_id);
+                let nack = serde_json::json!({ "accept": false, "error": "rate_limited", "device_uuid": *DEVICE_UUID });
+                let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+                let _ = socket.flush().await;
+                return false;
+            }
+            // NOT auto-accept: show normal prompt
+            info!("({addr}) Auto-accept disabled or IP not trusted. Showing normal prompt.");
+            tauri_log(&app_handle, "info", format!("Auto-accept disabled or IP not trusted for {}. Showing prompt.", addr.ip())).await;
+
+            // Emit event to frontend (include source address info)
+            info!("({addr}) Emitting transfer_request event for batch_id: {}", batch_id);
+            tauri_log(&app_handle, "info", format!("Emitting transfer_request for {} from {}", transfer_id, addr)).await;
+            let _ = app_handle.emit(
+                "transfer_request",
+                serde_json::json!({
+                    "offer": offer,
+                    "ip": addr.ip().to_string(),
+                    "port": addr.port(),
+                    "direction": "receive"
+                }),
+            );
+            info!("({addr}) Waiting for user confirmation for transfer_id: {}", transfer_id);
+            tauri_log(&app_handle, "info", format!("Waiting for user confirmation for transfer_id: {}", transfer_id)).await;
+
+            // Wait for user response
+            accept = wait_for_user_response(&transfer_id).await;
+
+            info!("({addr}) User responded with accept = {} for transfer_id: {}", accept, transfer_id);
+            tauri_log(&app_handle, "info", format!("User responded with accept = {} for transfer_id: {}", accept, transfer_id)).await;
+
+            {
+                let mut limits = USER_SIZE_LIMITS.lock().await;
+                if let Some(limit) = limits.remove(&transfer_id) {
+                    limits.insert(batch_id.clone(), limit);
+                }
+            }
+
+            // If accepted, ask for folder; if user chose to trust, front-end will call respond_transfer with trust=true
+            let remembered_dir = read_settings().await.default_download_dir.filter(|d| d.is_dir());
+            if let (true, Some(dir)) = (accept, remembered_dir) {
+                info!("({addr}) Saving batch {} to default download folder {:?}", batch_id, dir);
+                tauri_log(&app_handle, "info", format!("Saving to default download folder {:?}", dir)).await;
+                save_dir = Some(dir);
+            } else if accept {
+                use std::sync::Arc;
+                use tokio::sync::Mutex;
+                let save_dir_result: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
+                let save_dir_clone = save_dir_result.clone();
+                FileDialogBuilder::new(app_handle.dialog().clone())
+                    .set_title("Scegli la cartella di destinazione per il file")
+                    .pick_folder(move |path| {
+                        let save_dir_clone = save_dir_clone.clone();
+                        tauri::async_runtime::spawn(async move {
+                            let mut result = save_dir_clone.lock().await;
+                            *result = path.and_then(|p| p.as_path().map(|path| PathBuf::from(path)));
+                        });
+                    });
+                info!("({addr}) Waiting for user to select destination folder for batch_id: {}", batch_id);
+                tauri_log(&app_handle, "info", format!("Waiting for user to select destination folder for batch_id: {}", batch_id)).await;
+
+                let chosen_dir = loop {
+                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                    let result = save_dir_result.lock().await;
+                    if result.is_some() {
+                        break result.clone();
+                    }
+                };
+                save_dir = chosen_dir;
+                info!("({addr}) User selected destination folder for batch_id: {}: {:?}", batch_id, save_dir);
+                tauri_log(&app_handle, "info", format!("User selected destination folder for batch_id: {}: {:?}", batch_id, save_dir)).await;
+                emit_download_folder_chosen(&app_handle, &batch_id, save_dir.as_ref());
+            }
+
+            // Save to BATCH_RESPONSES (even if rejected, to avoid repeated asks)
+            {
+                let mut map = BATCH_RESPONSES.lock().await;
+                map.insert(batch_id.clone(), (accept, save_dir.clone()));
+                info!("({addr}) [BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = {} and save_dir = {:?}", batch_id, accept, save_dir);
+                tauri_log(&app_handle, "info", format!("[BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = {} and save_dir = {:?}", batch_id, accept, save_dir)).await;
+            }
+        }
+    }
+    // Resolve the final name before acking so a Skip policy can still refuse
+    let mut reject_reason = "user_rejected";
+    let mut resolved_path: Option<PathBuf> = None;
+    let mut already_have: Option<PathBuf> = None;
+    if let (true, Some(dir)) = (accept, save_dir.as_deref()) {
+        if is_forbidden_destination(&app_handle, dir) {
+            warn!("({addr}) Refusing to save {} into app state folder {:?}", offer.file_name, dir);
+            tauri_log(&app_handle, "warn", format!("Refused to save {} into AirShare's own folder {:?}", offer.file_name, dir)).await;
+            let _ = app_handle.emit("transfer_failed", serde_json::json!({
+                "transfer_id": transfer_id,
+                "reason": "forbidden_destination",
+                "path": dir,
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "direction": "receive"
+            }));
+            accept = false;
+            reject_reason = "forbidden_destination";
+        }
+    }
+    if accept {
+        let limit = USER_SIZE_LIMITS.lock().await.get(&batch_id).copied();
+        if let Some(limit) = limit.filter(|l| offer.file_size > *l) {
+            info!("({addr}) Refusing {} ({} bytes): over the {} bytes accepted for batch {}", offer.file_name, offer.file_size, limit, batch_id);
+            tauri_log(&app_handle, "info", format!("Refused {} from {}: larger than the accepted limit of {} bytes", offer.file_name, addr, limit)).await;
+            accept = false;
+            reject_reason = "exceeds_user_limit";
+        }
+    }
+    if accept {
+        if let Some(ref dir) = save_dir {
+            let settings = read_settings().await;
+            // An encrypted offer's sha256 is keyed, so it can't match the index
+            if let (true, None, Some(sha)) = (settings.dedup_enabled, &offer.encryption, offer.sha256.as_deref()) {
+                already_have = find_indexed_file(sha, offer.file_size, dir).await;
+            }
+            let policy = effective_collision_policy(offer.collision_hint, &settings);
+            if offer.kind.as_deref() == Some(FOLDER_TAR_KIND) {
+                // Extracted into a new folder next to whatever is already there
+                resolved_path = resolve_collision(dir, &folder_tar_name(&offer.file_name), CollisionPolicy::Rename);
+            } else if already_have.is_some() {
+                info!("({addr}) Already have {} (sha256 {}), skipping body", offer.file_name, offer.sha256.clone().unwrap_or_default());
+            } else {
+                match resolve_collision(dir, &offer.file_name, policy) {
+                    Some(p) => resolved_path = Some(p),
+                    None => {
+                        info!("({addr}) {} already exists in {:?}, skipping (policy {:?})", offer.file_name, dir, policy);
+                        accept = false;
+                        reject_reason = "file_exists";
+                    }
+                }
+            }
+        }
+    }
+    // Checked per file: a batch can run out of room part-way through
+    if let (true, None, Some(dir)) = (accept, &already_have, save_dir.as_deref()) {
+        let needed = offer.file_size.saturating_add(RECEIVE_SPACE_MARGIN);
+        if let Some(available) = available_space_for(dir).filter(|free| *free < needed) {
+            warn!("({addr}) Not enough space for {} in {:?}: {} bytes free, {} needed", offer.file_name, dir, available, needed);
+            tauri_log(&app_handle, "warn", format!("Not enough space in {:?} for {} ({} bytes free, {} needed)", dir, offer.file_name, available, needed)).await;
+            let _ = app_handle.emit("insufficient_space", serde_json::json!({
+                "transfer_id": transfer_id,
+                "batch_id": batch_id,
+                "file_name": offer.file_name,
+                "path": dir,
+                "available": available,
+                "needed": needed,
+                "shortfall": needed - available,
+            }));
+            accept = false;
+            reject_reason = "insufficient_space";
+        }
+    }
+
+    // Send ack JSON (expanded for potential error reporting)
+    let ack = if accept {
+        serde_json::json!({ "accept": true, "already_have": already_have.is_some(), "device_uuid": *DEVICE_UUID })
+    } else {
+        serde_json::json!({ "accept": false, "error": reject_reason, "device_uuid": *DEVICE_UUID })
+    };
+    let ack_str = serde_json::to_string(&ack).unwrap() + "\n";
+    match socket.write_all(ack_str.as_bytes()).await {
+        Ok(_) => {
+            info!("({addr}) Sent ack to client: {}", ack_str.trim_end());
+            crumbs.add(format!("ack sent (accept={})", accept));
+            tauri_log(&app_handle, "info", format!("Sent ack to {} for transfer {}", addr, transfer_id)).await;
+            if let Err(e) = socket.flush().await {
+                warn!("({addr}) Flush after ack failed: {}", e);
+                tauri_log(&app_handle, "warn", format!("Flush after ack failed for {}: {}", addr, e)).await;
+            }
+        }
+        Err(e) => {
+            error!("({addr}) Failed to write ack: {}", e);
+            crumbs.fail(format!("failed to write ack: {}", e)).await;
+            tauri_log(&app_handle, "error", format!("Failed to write ack to {}: {}", addr, e)).await;
+            // On error, cleanup batch entry if we just created it
+            if is_batch_first {
+                let mut map = BATCH_RESPONSES.lock().await;
+                map.remove(&batch_id);
+            }
+            return false;
+        }
+    }
+    if !accept {
+        info!("({addr}) Transfer rejected: {}", reject_reason);
+        // On reject, cleanup batch entry if we just created it (a skipped file keeps the batch going)
+        // A forbidden folder is dropped too, so the next file of the batch asks again
+        if (is_batch_first && reject_reason == "user_rejected") || reject_reason == "forbidden_destination" {
+            let mut map = BATCH_RESPONSES.lock().await;
+            map.remove(&batch_id);
+        }
+        // Per-file skips leave a kept-alive connection open for the rest of the batch
+        if offer.keep_alive && matches!(reject_reason, "file_exists" | "exceeds_user_limit") {
+            return true;
+        }
+        return false;
+    }
+    if offer.kind.as_deref() == Some(FOLDER_TAR_KIND) {
+        if let Some(dest) = resolved_path {
+            receive_folder_tar(&app_handle, socket, &offer, addr, dest).await;
+        }
+        return false;
+    }
+    emit_transfer_accepted(&app_handle, &transfer_id, "receive", &addr.ip().to_string(), offer.sender_uuid.as_deref(), &offer.file_name, offer.file_size);
+    let batch_file = ReceiveBatchFile::start(&batch_id);
+    let lifecycle = FileLifecycle::start(&app_handle, &transfer_id, "receive", Some(&batch_id), &offer.file_name, offer.file_index, offer.total_files);
+    if let Some(existing) = already_have {
+        batch_file.finish(BatchFileOutcome::Completed);
+        lifecycle.finish("completed");
+        let _ = app_handle.emit("transfer_complete", serde_json::json!({
+            "transfer_id": transfer_id,
+            "path": existing,
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "direction": "receive",
+            "verified": true,
+            "deduped": true
+        }));
+        let _ = record_transfer(
+            app_handle.clone(),
+            offer.file_name.clone(),
+            offer.file_size,
+            TransferType::Received,
+            addr.ip().to_string(),
+            0,
+            TransferStatus::Completed,
+            RecordExtras { verified: Some(true), peer_uuid: offer.sender_uuid.clone(), ..Default::default() },
+        ).await;
+        tauri_log(&app_handle, "info", format!("receive deduped | id={} ip={} path={}", transfer_id, addr.ip(), existing.display())).await;
+        if offer.keep_alive {
+            return true;
+        }
+        return false;
+    }
+    // Retrieve save_dir from batch map (in case not first)
+    let actual_save_dir = {
+        let map = BATCH_RESPONSES.lock().await;
+        map.get(&batch_id).and_then(|(_, dir)| dir.clone())
+    };
+    let save_dir = match actual_save_dir {
+        Some(path) => path,
+        None => {
+            info!("({addr}) Trasferimento annullato dall'utente.");
+            let _ = app_handle.emit("transfer_rejected", serde_json::json!({
+                "transfer_id": transfer_id,
+                "reason": "user_cancelled"
+            }));
+            // On cancel, cleanup batch entry if we just created it
+            if is_batch_first {
+                let mut map = BATCH_RESPONSES.lock().await;
+                map.remove(&batch_id);
+            }
+            return false;
+        }
+    };
+
+    let temp_path = resolved_path.unwrap_or_else(|| save_dir.join(&offer.file_name));
+    if let Err(e) = tokio::fs::create_dir_all(&save_dir).await {
+        error!("({addr}) Failed to create selected directory: {}", e);
+        tauri_log(&app_handle, "error", format!("Failed to create selected directory {}: {}", save_dir.display(), e)).await;
+        crumbs.fail(format!("failed to create directory: {}", e)).await;
+        // On error, cleanup batch entry if we just created it
+        if is_batch_first {
+            let mut map = BATCH_RESPONSES.lock().await;
+            map.remove(&batch_id);
+        }
+        return false;
+    }
+    info!("({addr}) Creating destination file at {:?}", temp_path);
+    let file = match fs::File::create(&temp_path).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("({addr}) Failed to create file: {}", e);
+            tauri_log(&app_handle, "error", format!("Failed to create file {}: {}", temp_path.display(), e)).await;
+            crumbs.fail(format!("failed to create file: {}", e)).await;
+            return false;
+        }
+    };
+    let high_water_mark = read_settings().await
+        .receive_high_water_mark
+        .unwrap_or(DEFAULT_RECEIVE_HIGH_WATER_MARK);
+    let mut file = ReceiveSink::new(file, high_water_mark);
+
+    // Receive exactly wire_size bytes, or the frames of a streamed compressed body;
+    // `received` counts those, `written` the (decompressed) file
+    let wire_size = offer.wire_size();
+    let mut frames = (offer.compression.is_some() && offer.compressed_size.is_none()).then(BodyFrames::default);
+    let mut decoder = match offer.compression.as_deref().map(BodyDecoder::new).transpose() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("({addr}) Failed to set up decompression: {}", e);
+            crumbs.fail(format!("decompression setup failed: {}", e)).await;
+            return false;
+        }
+    };
+    let mut decode_error: Option<std::io::Error> = None;
+    let mut written: u64 = 0;
+    let active = ActiveTransferGuard::register(&transfer_id, addr.ip().to_string(), "receive", &offer.file_name, wire_size);
+
+    let mut received: u64 = 0;
+    let mut next_milestone: u64 = 25;
+    let mut hasher = <sha2::Sha256 as sha2::Digest>::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut last_log = Instant::now();
+    let transfer_start = Instant::now();
+    let speed_unit = read_settings().await.speed_unit;
+    sync_receive_limit().await;
+    info!("({addr}) Beginning binary receive of {} bytes for transfer {}", wire_size, transfer_id);
+    while frames.as_ref().map_or(received < wire_size, |f| !f.ended) {
+        if !wait_if_paused(&transfer_id, Some(&batch_id)).await {
+            CANCELLED_RECEIVE.lock().await.insert(transfer_id.clone());
+        }
+        // Check if transfer was cancelled
+        if is_receive_cancelled(&transfer_id).await || is_batch_cancelled(&batch_id).await {
+            error!("({addr}) Receive transfer was cancelled by user");
+            tauri_log(&app_handle, "warn", format!("Receive transfer {} was cancelled", transfer_id)).await;
+            let _ = add_recent_transfer(
+                app_handle.clone(),
+                offer.file_name.clone(),
+                offer.file_size,
+                TransferType::Received,
+                addr.ip().to_string(),
+                addr.ip().to_string(),
+                transfer_start.elapsed().as_millis(),
+                TransferStatus::Cancelled,
+                offer.sender_uuid.clone(),
+            ).await;
+            // Cleanup cancelled state and temp file
+            let mut cancelled = CANCELLED_RECEIVE.lock().await;
+            cancelled.remove(&transfer_id);
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            batch_file.finish(BatchFileOutcome::Cancelled);
+            lifecycle.finish("cancelled");
+            return false;
+        }
+
+        let wanted = frames.as_ref().map_or(wire_size - received, |f| f.wanted() as u64);
+        let to_read = std::cmp::min(buffer.len() as u64, wanted) as usize;
+        let n = match socket.read(&mut buffer[..to_read]).await {
+            Ok(0) => {
+                error!(
+                    "({addr}) Peer closed connection early at {} / {} bytes for transfer {}",
+                    received, wire_size, transfer_id
+                );
+                crumbs.fail(format!("peer closed connection at {} / {} bytes", received, wire_size)).await;
+                return false;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                error!("({addr}) Error receiving file: {}", e);
+                crumbs.fail(format!("socket read error: {}", e)).await;
+                return false;
+            }
+        };
+        let decrypted = match body_cipher.as_mut().map(|c| c.open(&buffer[..n])) {
+            Some(Ok(plain)) => Some(plain),
+            Some(Err(e)) => {
+                // Wrong PIN or tampering: nothing of this file can be trusted
+                error!("({addr}) Decryption failed for {}: {}", transfer_id, e);
+                tauri_log(&app_handle, "error", format!("Decryption failed for transfer {}: {}", transfer_id, e)).await;
+                drop(file);
+                crumbs.fail(format!("decryption failed: {}", e)).await;
+                fail_corrupt_receive(&app_handle, &transfer_id, &offer, addr, &temp_path, transfer_start.elapsed().as_millis(), "decryption_failed").await;
+                return false;
+            }
+            None => None,
+        };
+        let body = decrypted.as_deref().unwrap_or(&buffer[..n]);
+        let body = match frames.as_mut().map(|f| f.feed(body)) {
+            Some(Ok(payload)) => payload,
+            Some(Err(e)) => {
+                // Past a bad frame header there is no telling where the body ends
+                error!("({addr}) Bad compressed frame for {}: {}", transfer_id, e);
+                drop(file);
+                crumbs.fail(format!("bad compressed frame: {}", e)).await;
+                fail_corrupt_receive(&app_handle, &transfer_id, &offer, addr, &temp_path, transfer_start.elapsed().as_millis(), "decompression_failed").await;
+                return false;
+            }
+            None => body,
+        };
+        // A plain body is written as read; a compressed one as it inflates, a bounded step at a
+        // time. After a decode error the rest of the body is drained, not written.
+        if let Some(d) = decoder.as_mut().filter(|_| decode_error.is_none()) {
+            d.push(body);
+        }
+        let mut plain = decoder.is_none().then_some(body);
+        loop {
+            let piece = match decoder.as_mut() {
+                None => match plain.take() {
+                    Some(b) => std::borrow::Cow::Borrowed(b),
+                    None => break,
+                },
+                Some(_) if decode_error.is_some() => break,
+                Some(d) => match d.pull() {
+                    Ok(Some(out)) => std::borrow::Cow::Owned(out),
+                    Ok(None) => break,
+                    Err(e) => {
+                        decode_error = Some(e);
+                        break;
+                    }
+                },
+            };
+            if written + piece.len() as u64 > offer.file_size {
+                warn!("({addr}) Body of {} inflates past its declared {} bytes", transfer_id, offer.file_size);
+                drop(file);
+                crumbs.fail("body inflates past the declared size").await;
+                fail_corrupt_receive(&app_handle, &transfer_id, &offer, addr, &temp_path, transfer_start.elapsed().as_millis(), "decompression_failed").await;
+                return false;
+            }
+            if let Err(e) = file.write(&piece).await {
+                error!("({addr}) File write error: {}", e);
+                tauri_log(&app_handle, "error", format!("File write error {}: {}", temp_path.display(), e)).await;
+                crumbs.fail(format!("file write error: {}", e)).await;
+                drop(file);
+                handle_destination_unavailable(&app_handle, &transfer_id, &offer, addr, &temp_path, &batch_id, transfer_start.elapsed().as_millis(), &e).await;
+                return false;
+            }
+            sha2::Digest::update(&mut hasher, &piece[..]);
+            written += piece.len() as u64;
+        }
+        // Not reading from the socket lets TCP flow control slow the sender down
+        throttle_receive(n as u64).await;
+        received += n as u64;
+        // A streamed body's size isn't known up front, so its progress is measured in file bytes
+        let (done, total) = if frames.is_some() { (written, offer.file_size) } else { (received, wire_size) };
+        active.update(done);
+        crumbs.progress(&mut next_milestone, done, total);
+
+        // Calcola ETA per il progresso
+        let elapsed_ms = transfer_start.elapsed().as_millis();
+        let (eta_ms, eta_formatted) = calculate_eta(done, total, elapsed_ms);
+        let speed = speed_mb_per_sec(done, elapsed_ms);
+
+        // Emit progress con ETA
+        let progress = serde_json::json!({
+            "transfer_id": transfer_id,
+            "received": done,
+            "total": total,
+            "percent": percent_of(done, total),
+            "batch_id": batch_id,
+            "file_name": offer.file_name,
+            "file_index": offer.file_index,
+            "total_files": offer.total_files,
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "direction": "receive",
+            "eta_ms": eta_ms,
+            "eta_formatted": eta_formatted,
+            "speed": speed,
+            "speed_formatted": format_speed(speed, speed_unit)
+        });
+        let _ = app_handle.emit("transfer_progress", progress);
+        info!("({addr}) Received {} / {} bytes", done, total);
+
+        // Throttled log once per second for frontend debugging context
+        if last_log.elapsed().as_secs_f64() >= 1.0 {
+            let percent = percent_of(done, total);
+            let (_, eta_formatted) = calculate_eta(done, total, elapsed_ms);
+            info!(
+                "recv progress | id={} ip={} port={} received={} total={} percent={:.1} eta={}",
+                transfer_id,
+                addr.ip(),
+                addr.port(),
+                done,
+                total,
+                percent,
+                eta_formatted
+            );
+            tauri_log(&app_handle, "info", format!(
+                "recv progress | id={} ip={} port={} received={} total={} percent={:.1} eta={}",
+                transfer_id, addr.ip(), addr.port(), done, total, percent, eta_formatted
+            )).await;
+            last_log = Instant::now();
+        }
+    }
+    if let Some(d) = decoder.take().filter(|_| decode_error.is_none()) {
+        match d.finish() {
+            Ok(tail) if written + tail.len() as u64 > offer.file_size => {
+                decode_error = Some(std::io::Error::new(std::io::ErrorKind::InvalidData, "body inflates past the declared size"));
+            }
+            Ok(tail) => {
+                if let Err(e) = file.write(&tail).await {
+                    error!("({addr}) File write error: {}", e);
+                    crumbs.fail(format!("file write error: {}", e)).await;
+                    drop(file);
+                    handle_destination_unavailable(&app_handle, &transfer_id, &offer, addr, &temp_path, &batch_id, transfer_start.elapsed().as_millis(), &e).await;
+                    return false;
+                }
+                sha2::Digest::update(&mut hasher, &tail);
+                written += tail.len() as u64;
+            }
+            Err(e) => decode_error = Some(e),
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        error!("({addr}) Final flush error: {}", e);
+        tauri_log(&app_handle, "error", format!("File flush error {}: {}", temp_path.display(), e)).await;
+        crumbs.fail(format!("final flush error: {}", e)).await;
+        drop(file);
+        handle_destination_unavailable(&app_handle, &transfer_id, &offer, addr, &temp_path, &batch_id, transfer_start.elapsed().as_millis(), &e).await;
+        return false;
+    }
+    if let Err(e) = file.get_ref().sync_all().await {
+        warn!("({addr}) Failed to fsync file {:?}: {}", temp_path, e);
+    }
+
+    // Funzione di dialogo rimossa come richiesto
+
+    let actual_sha256 = format!("{:x}", sha2::Digest::finalize(hasher));
+    // What the offer and receipt carry: the hash itself, or keyed for encrypted transfers
+    let wire_sha256 = body_cipher.as_ref().map_or_else(|| actual_sha256.clone(), |c| c.keyed_digest(&actual_sha256));
+    let decompression_failed = decode_error.is_some() || written != offer.file_size;
+    let verified = if decompression_failed {
+        Some(false)
+    } else {
+        offer.sha256.as_ref().map(|expected| expected.eq_ignore_ascii_case(&wire_sha256))
+    };
+    if decompression_failed {
+        warn!(
+            "({addr}) Bad {:?} body for {:?}: {} of {} bytes decoded ({})",
+            offer.compression, temp_path, written, offer.file_size,
+            decode_error.as_ref().map(|e| e.to_string()).unwrap_or_else(|| "size mismatch".to_string())
+        );
+        tauri_log(&app_handle, "warn", format!("Decompression failed for transfer {}", transfer_id)).await;
+    } else if verified == Some(false) {
+        warn!("({addr}) Checksum mismatch for {:?}: expected {}, got {}", temp_path, offer.sha256.clone().unwrap_or_default(), wire_sha256);
+        tauri_log(&app_handle, "warn", format!("Checksum mismatch for transfer {}", transfer_id)).await;
+    } else if read_settings().await.dedup_enabled {
+        if let Err(e) = index_received_file(&actual_sha256, &temp_path, offer.file_size).await {
+            warn!("({addr}) Failed to update hash index: {}", e);
+        }
+    }
+
+    // Proof of delivery for the sender; old senders have already hung up and ignore it
+    let receipt = serde_json::json!({
+        "receipt": {
+            "transfer_id": transfer_id,
+            "received": written,
+            "sha256": wire_sha256
+        }
+    });
+    if let Err(e) = socket.write_all((serde_json::to_string(&receipt).unwrap() + "\n").as_bytes()).await {
+        warn!("({addr}) Failed to send receipt: {}", e);
+    } else {
+        let _ = socket.flush().await;
+    }
+
+    // A corrupted file is not kept; the receipt above already told the sender the real hash
+    if verified == Some(false) {
+        let reason = if decompression_failed { "decompression_failed" } else { "checksum_mismatch" };
+        drop(file);
+        crumbs.fail(reason.replace('_', " ")).await;
+        fail_corrupt_receive(&app_handle, &transfer_id, &offer, addr, &temp_path, transfer_start.elapsed().as_millis(), reason).await;
+        if offer.keep_alive {
+            return true;
+        }
+        return false;
+    }
+
+    let _ = app_handle.emit("transfer_complete", serde_json::json!({
+        "transfer_id": transfer_id,
+        "path": temp_path,
+        "ip": addr.ip().to_string(),
+        "port": addr.port(),
+        "direction": "receive",
+        "verified": verified
+    }));
+    info!("({addr}) File transfer complete: {:?}", temp_path);
+    batch_file.finish(BatchFileOutcome::Completed);
+    lifecycle.finish("completed");
+    tauri_log(&app_handle, "info", format!("receive complete | id={} ip={} port={} path={}", transfer_id, addr.ip(), addr.port(), temp_path.display())).await;
+
+    // Registra nella cronologia (ricezione completata)
+    let _ = record_transfer(
+        app_handle.clone(),
+        offer.file_name.clone(),
+        offer.file_size,
+        TransferType::Received,
+        addr.ip().to_string(),
+        transfer_start.elapsed().as_millis(),
+        TransferStatus::Completed,
+        RecordExtras { verified, peer_uuid: offer.sender_uuid.clone(), ..Default::default() },
+    ).await;
+
+    // --- PATCH: Do NOT remove batch entry here. Removal must be done only when all files in the batch are complete. ---
+    // The entry for batch_id will persist until explicit cleanup logic is added (not here).
+
+    if offer.keep_alive {
+        return true;
+    }
+    // Gracefully shutdown write half (if any) to signal proper end
+    if let Err(e) = AsyncWriteExt::shutdown(socket).await {
+        warn!("({addr}) Socket shutdown after receive failed: {}", e);
+        tauri_log(&app_handle, "warn", format!("Socket shutdown after receive failed for {}: {}", addr, e)).await;
+    }
+    false
+}
+
+/// `transfer_accepted`: the ack was exchanged and the body is about to flow, emitted on both sides
+/// so the UI can show "Receiving from…" before the first progress event.
+fn emit_transfer_accepted(app_handle: &AppHandle, transfer_id: &str, direction: &str, peer_ip: &str, peer_device_id: Option<&str>, file_name: &str, file_size: u64) {
+    let _ = app_handle.emit("transfer_accepted", serde_json::json!({
+        "transfer_id": transfer_id,
+        "direction": direction,
+        "peer_ip": peer_ip,
+        "peer_device_id": peer_device_id,
+        "file_name": file_name,
+        "file_size": file_size,
+    }));
+}
+
+/// Emits `file_started` now and `file_finished` (with a status) once finished or dropped, so a UI
+/// can list a batch's files and tick them off. Dropped without `finish` it reports "failed".
+struct FileLifecycle {
+    app_handle: AppHandle,
+    payload: serde_json::Value,
+    status: &'static str,
+}
+
+impl FileLifecycle {
+    fn start(app_handle: &AppHandle, transfer_id: &str, direction: &str, batch_id: Option<&str>, file_name: &str, file_index: Option<usize>, total_files: Option<usize>) -> Self {
+        let payload = serde_json::json!({
+            "transfer_id": transfer_id,
+            "direction": direction,
+            "batch_id": batch_id,
+            "file_name": file_name,
+            "file_index": file_index,
+            "total_files": total_files,
+        });
+        let _ = app_handle.emit("file_started", &payload);
+        FileLifecycle { app_handle: app_handle.clone(), payload, status: "failed" }
+    }
+
+    /// "completed" or "cancelled"; emits `file_finished` right away.
+    fn finish(mut self, status: &'static str) {
+        self.status = status;
+    }
+}
+
+impl Drop for FileLifecycle {
+    fn drop(&mut self) {
+        self.payload["status"] = serde_json::Value::from(self.status);
+        let _ = self.app_handle.emit("file_finished", &self.payload);
+    }
+}
+
+/// A send that ended because the user cancelled or the peer refused, as opposed to a failure.
+#[derive(Debug)]
+pub struct TransferAborted(pub String);
+
+impl std::fmt::Display for TransferAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransferAborted {}
+
+/// A file of a kept-alive batch that the peer skipped (`file_exists`, `exceeds_user_limit`);
+/// the connection stays usable for the next file.
+#[derive(Debug)]
+pub struct FileSkipped(pub String);
+
+impl std::fmt::Display for FileSkipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Skipped by peer: {}", self.0)
+    }
+}
+
+impl std::error::Error for FileSkipped {}
+
+// --- Server-side send batches (allocated by begin_batch) ---
+pub struct SendBatch {
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub target_ip: String,
+    pub target_port: u16,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub overall_sent: std::sync::Arc<TokioMutex<u64>>,
+    pub created_at: String,
+}
+
+impl SendBatch {
+    pub fn is_finished(&self) -> bool {
+        self.completed + self.failed + self.cancelled >= self.total_files
+    }
+}
+
+// Finished batches are kept (for reporting) up to this many
+const MAX_FINISHED_BATCHES: usize = 50;
+
+pub static SEND_BATCHES: Lazy<TokioMutex<HashMap<String, SendBatch>>> = Lazy::new(|| TokioMutex::new(HashMap::new()));
+
+/// Allocate a batch handle with its counters. Pass the returned id as `batch_id`
+/// to every send_file_with_progress call of the batch.
 #[tauri::command]
-pub async fn add_recent_transfer(
-    app_handle: tauri::AppHandle,
-    file_name: String,
-    file_size: u64,
-    transfer_type: TransferType,
-    _target_ip: String,
-    target_name: String,
-    elapsed_ms: u128,
-    status: TransferStatus,
-) -> Result<(), String> {
-    // Calcola velocità in MB/s
-    let duration_secs = (elapsed_ms as f64) / 1000.0;
-    let speed_mbps = if duration_secs > 0.0 {
-        (file_size as f64 / 1024.0 / 1024.0) / duration_secs
+pub async fn begin_batch(total_files: usize, total_bytes: u64, target_ip: String, target_port: u16) -> Result<String, String> {
+    let batch_id = Uuid::new_v4().to_string();
+    let mut batches = SEND_BATCHES.lock().await;
+    if batches.values().filter(|b| b.is_finished()).count() >= MAX_FINISHED_BATCHES {
+        batches.retain(|_, b| !b.is_finished());
+    }
+    batches.insert(batch_id.clone(), SendBatch {
+        total_files,
+        total_bytes,
+        target_ip,
+        target_port,
+        completed: 0,
+        failed: 0,
+        cancelled: 0,
+        overall_sent: std::sync::Arc::new(TokioMutex::new(0)),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    });
+    info!("[BATCH] begin_batch {} ({} files, {} bytes)", batch_id, total_files, total_bytes);
+    Ok(batch_id)
+}
+
+/// Byte counter and total of a server-side batch, if `batch_id` is one.
+pub async fn batch_progress_counter(batch_id: &str) -> Option<(std::sync::Arc<TokioMutex<u64>>, u64)> {
+    let batches = SEND_BATCHES.lock().await;
+    batches.get(batch_id).map(|b| (b.overall_sent.clone(), b.total_bytes))
+}
+
+// --- Receive-side batch tallies; the sender's file count isn't known here ---
+#[derive(Default)]
+struct ReceiveBatchTally {
+    in_flight: usize,
+    completed: usize,
+    failed: usize,
+    cancelled: usize,
+    first_seen: String,
+}
+
+// std Mutex on purpose: only touched from ReceiveBatchFile, never across an await
+static RECEIVE_BATCHES: Lazy<std::sync::Mutex<HashMap<String, ReceiveBatchTally>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+enum BatchFileOutcome {
+    Completed,
+    Cancelled,
+}
+
+/// One accepted incoming file, counted against its batch. Dropped without `finish` it counts as failed.
+struct ReceiveBatchFile {
+    batch_id: String,
+    outcome: Option<BatchFileOutcome>,
+}
+
+impl ReceiveBatchFile {
+    fn start(batch_id: &str) -> Self {
+        let mut batches = RECEIVE_BATCHES.lock().unwrap();
+        if batches.len() >= MAX_FINISHED_BATCHES && !batches.contains_key(batch_id) {
+            batches.retain(|_, t| t.in_flight > 0);
+        }
+        let tally = batches.entry(batch_id.to_string()).or_insert_with(|| ReceiveBatchTally {
+            first_seen: chrono::Utc::now().to_rfc3339(),
+            ..Default::default()
+        });
+        tally.in_flight += 1;
+        ReceiveBatchFile { batch_id: batch_id.to_string(), outcome: None }
+    }
+
+    fn finish(mut self, outcome: BatchFileOutcome) {
+        self.outcome = Some(outcome);
+    }
+}
+
+impl Drop for ReceiveBatchFile {
+    fn drop(&mut self) {
+        let mut batches = RECEIVE_BATCHES.lock().unwrap();
+        let Some(tally) = batches.get_mut(&self.batch_id) else { return; };
+        tally.in_flight = tally.in_flight.saturating_sub(1);
+        match self.outcome {
+            Some(BatchFileOutcome::Completed) => tally.completed += 1,
+            Some(BatchFileOutcome::Cancelled) => tally.cancelled += 1,
+            None => tally.failed += 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub direction: &'static str,
+    /// Send batches: the target IP
+    pub peer: Option<String>,
+    /// Receive batches: the user's (or auto-accept's) decision; None once it has been forgotten
+    pub accepted: Option<bool>,
+    pub destination: Option<PathBuf>,
+    /// Send batches: as declared to begin_batch; receive batches: files offered so far
+    pub total_files: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub active: bool,
+    pub created_at: Option<String>,
+}
+
+/// Every batch this session knows about, send and receive, newest first.
+#[tauri::command]
+pub async fn get_batches() -> Result<Vec<BatchSummary>, String> {
+    let mut out: Vec<BatchSummary> = SEND_BATCHES
+        .lock()
+        .await
+        .iter()
+        .map(|(id, b)| BatchSummary {
+            batch_id: id.clone(),
+            direction: "send",
+            peer: Some(b.target_ip.clone()),
+            accepted: None,
+            destination: None,
+            total_files: b.total_files,
+            completed: b.completed,
+            failed: b.failed,
+            cancelled: b.cancelled,
+            active: !b.is_finished(),
+            created_at: Some(b.created_at.clone()),
+        })
+        .collect();
+    let responses = BATCH_RESPONSES.lock().await.clone();
+    let mut receive_ids: Vec<String> = responses.keys().cloned().collect();
+    {
+        let tallies = RECEIVE_BATCHES.lock().unwrap();
+        receive_ids.extend(tallies.keys().filter(|id| !responses.contains_key(*id)).cloned());
+        for id in receive_ids {
+            let tally = tallies.get(&id);
+            let response = responses.get(&id);
+            out.push(BatchSummary {
+                direction: "receive",
+                peer: None,
+                accepted: response.map(|(accept, _)| *accept),
+                destination: response.and_then(|(_, dir)| dir.clone()),
+                total_files: tally.map_or(0, |t| t.in_flight + t.completed + t.failed + t.cancelled),
+                completed: tally.map_or(0, |t| t.completed),
+                failed: tally.map_or(0, |t| t.failed),
+                cancelled: tally.map_or(0, |t| t.cancelled),
+                active: tally.is_some_and(|t| t.in_flight > 0),
+                created_at: tally.map(|t| t.first_seen.clone()),
+                batch_id: id,
+            });
+        }
+    }
+    out.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(out)
+}
+
+/// Count one file's outcome and emit `batch_complete` once every file is accounted for.
+pub async fn record_batch_outcome(app_handle: &AppHandle, batch_id: &str, result: &anyhow::Result<()>) {
+    let mut batches = SEND_BATCHES.lock().await;
+    let Some(batch) = batches.get_mut(batch_id) else { return; };
+    if batch.is_finished() {
+        return;
+    }
+    match result {
+        Ok(_) => batch.completed += 1,
+        Err(e) if e.downcast_ref::<TransferAborted>().is_some() || e.downcast_ref::<FileSkipped>().is_some() => batch.cancelled += 1,
+        Err(_) => batch.failed += 1,
+    }
+    if batch.is_finished() {
+        CANCELLED_BATCHES.lock().unwrap().remove(batch_id);
+        info!("[BATCH] {} complete: {} ok, {} failed, {} cancelled", batch_id, batch.completed, batch.failed, batch.cancelled);
+        let _ = app_handle.emit("batch_complete", serde_json::json!({
+            "batch_id": batch_id,
+            "total_files": batch.total_files,
+            "completed": batch.completed,
+            "failed": batch.failed,
+            "cancelled": batch.cancelled,
+            "target_ip": batch.target_ip,
+            "target_port": batch.target_port,
+            "created_at": batch.created_at,
+        }));
+    }
+}
+
+/// Accept a single-connection offer (text share, folder archive) outright for a trusted sender
+/// with auto-accept on, otherwise ask the user via `transfer_request`. Untrusted senders go through
+/// the same per-IP offer rate limit as files. `Err` carries the nack reason.
+async fn prompt_or_auto_accept(app_handle: &AppHandle, offer: &FileOffer, addr: std::net::SocketAddr) -> Result<(), &'static str> {
+    let transfer_id = &offer.transfer_id;
+    let trusted_id = trusted_sender_id(app_handle, offer, addr).await;
+    let settings = read_settings().await;
+    if trusted_id.is_none() && offer_rate_limited(addr.ip(), settings.offer_rate_limit.unwrap_or_default()) {
+        log::debug!("({addr}) Offer {} rate limited", transfer_id);
+        return Err("rate_limited");
+    }
+    let auto_accept = settings.auto_accept_trusted
+        && trusted_id.is_some()
+        && trusted_network_allows_auto_accept(app_handle, addr, transfer_id, trusted_id).await;
+    if auto_accept {
+        return Ok(());
+    }
+    let _pending = PendingOfferGuard::new();
+    let _ = app_handle.emit("transfer_request", serde_json::json!({
+        "offer": offer,
+        "ip": addr.ip().to_string(),
+        "port": addr.port(),
+        "direction": "receive"
+    }));
+    if wait_for_user_response(transfer_id).await {
+        Ok(())
     } else {
-        0.0
+        Err("user_rejected")
+    }
+}
+
+/// Wait for `respond_transfer` to answer `transfer_id` and take its answer out of TRANSFER_RESPONSES.
+async fn wait_for_user_response(transfer_id: &str) -> bool {
+    loop {
+        if let Some(a) = TRANSFER_RESPONSES.lock().await.remove(transfer_id) {
+            return a;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Receive a `kind: "text"` offer: ask (or auto-accept for trusted senders), read the text into memory
+/// and either copy it to the clipboard or hand it to the UI via `text_received`.
+async fn receive_text_share(app_handle: &AppHandle, socket: &mut TcpStream, offer: &FileOffer, addr: std::net::SocketAddr) {
+    let transfer_id = offer.transfer_id.clone();
+    if offer.file_size > MAX_TEXT_SHARE_BYTES {
+        warn!("({addr}) Refusing text share of {} bytes (limit {})", offer.file_size, MAX_TEXT_SHARE_BYTES);
+        let nack = serde_json::json!({ "accept": false, "error": "text_too_large", "device_uuid": *DEVICE_UUID });
+        let _ = socket.write_all((serde_json::to_string(&nack).unwrap() + "\n").as_bytes()).await;
+        let _ = socket.flush().await;
+        return;
+    }
+
+    let settings = read_settings().await;
+    let accept = prompt_or_auto_accept(app_handle, offer, addr).await;
+
+    let ack = match accept {
+        Ok(()) => serde_json::json!({ "accept": true, "device_uuid": *DEVICE_UUID }),
+        Err(reason) => serde_json::json!({ "accept": false, "error": reason, "device_uuid": *DEVICE_UUID }),
     };
+    if let Err(e) = socket.write_all((serde_json::to_string(&ack).unwrap() + "\n").as_bytes()).await {
+        error!("({addr}) Failed to write text share ack: {}", e);
+        return;
+    }
+    let _ = socket.flush().await;
+    if let Err(reason) = accept {
+        info!("({addr}) Text share {} rejected: {}", transfer_id, reason);
+        return;
+    }
 
-    // Nome dispositivo locale
-    let local_device = hostname::get()
-        .ok()
-        .and_then(|h| h.into_string().ok())
-        .unwrap_or_else(|| "Unknown Device".to_string());
+    let mut body = vec![0u8; offer.file_size as usize];
+    if let Err(e) = socket.read_exact(&mut body).await {
+        error!("({addr}) Failed to read text share body: {}", e);
+        tauri_log(app_handle, "error", format!("Failed to read text share from {}: {}", addr, e)).await;
+        return;
+    }
+    let text = String::from_utf8_lossy(&body).into_owned();
+    info!("({addr}) Received text share {} ({} bytes)", transfer_id, body.len());
 
-    let (from_device, to_device) = match transfer_type {
-        TransferType::Sent => (local_device.clone(), target_name.clone()),
-        TransferType::Received => (target_name.clone(), local_device.clone()),
+    if settings.auto_copy_received_text {
+        use tauri_plugin_clipboard_manager::ClipboardExt;
+        match app_handle.clipboard().write_text(text.clone()) {
+            Ok(_) => {
+                let _ = app_handle.emit("text_copied", serde_json::json!({
+                    "transfer_id": transfer_id,
+                    "ip": addr.ip().to_string(),
+                    "length": text.chars().count()
+                }));
+                if settings.notifications_enabled {
+                    let preview: String = text.chars().take(80).collect();
+                    let _ = app_handle.emit("transfer_notification", serde_json::json!({
+                        "title": "📋 Testo copiato negli appunti",
+                        "message": preview,
+                        "type": "received"
+                    }));
+                }
+                return;
+            }
+            Err(e) => {
+                warn!("({addr}) Clipboard write failed, handing text to the UI: {}", e);
+                tauri_log(app_handle, "warn", format!("Clipboard write failed for text share {}: {}", transfer_id, e)).await;
+            }
+        }
+    }
+    let _ = app_handle.emit("text_received", serde_json::json!({
+        "transfer_id": transfer_id,
+        "ip": addr.ip().to_string(),
+        "content": text
+    }));
+}
+
+// --- Folder transfers as one tar stream ---
+// Offer kind for a whole folder sent as a tar archive over one connection
+pub const FOLDER_TAR_KIND: &str = "folder-tar";
+// Several files over one connection: every offer but the last carries `keep_alive`
+pub const MULTI_FILE_CAPABILITY: &str = "multi-file";
+/// Optional protocol features this build supports, advertised in the heartbeat.
+pub const CAPABILITIES: &[&str] = &[FOLDER_TAR_KIND, MULTI_FILE_CAPABILITY, COMPRESSION_CAPABILITY, COMPRESSION_STREAM_CAPABILITY, PING_CAPABILITY];
+const TAR_CHUNK: usize = 256 * 1024;
+
+/// Blocking `Write` end of a channel, so the synchronous tar builder can feed an async socket.
+struct ChannelWriter(tokio::sync::mpsc::Sender<Vec<u8>>);
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "transfer stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Blocking `Read` end of a channel, so the synchronous tar extractor can consume an async socket.
+struct ChannelReader {
+    rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl std::io::Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Unpack a tar stream into `dest`. Only regular files and directories are extracted (no links),
+/// paths escaping `dest` are skipped, and more content than `max_bytes` aborts the extraction.
+fn extract_folder_tar(app_handle: &AppHandle, transfer_id: &str, reader: ChannelReader, dest: &std::path::Path, max_bytes: u64) -> std::io::Result<(usize, u64)> {
+    let mut archive = tar::Archive::new(reader);
+    let (mut entries, mut bytes) = (0usize, 0u64);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_dir() {
+            continue;
+        }
+        bytes += entry.size();
+        if bytes > max_bytes {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "archive larger than offered"));
+        }
+        let name = entry.path().map(|p| p.display().to_string()).unwrap_or_default();
+        if !entry.unpack_in(dest)? {
+            warn!("Skipping archive entry outside the destination: {}", name);
+            continue;
+        }
+        if kind.is_file() {
+            entries += 1;
+            let _ = app_handle.emit("folder_entry_progress", serde_json::json!({
+                "transfer_id": transfer_id,
+                "entry": name,
+                "entries": entries,
+                "received": bytes,
+                "total": max_bytes,
+                "percent": percent_of(bytes, max_bytes),
+            }));
+        }
+    }
+    Ok((entries, bytes))
+}
+
+/// Name of the folder a `kind: "folder-tar"` offer is extracted into: the sender's folder name.
+fn folder_tar_name(offered: &str) -> String {
+    std::path::Path::new(offered)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("AirShare folder")
+        .to_string()
+}
+
+/// Extract an accepted `kind: "folder-tar"` stream into `dest`, a new folder inside the chosen
+/// destination; the offer went through the same prompt and checks as a file before the ack.
+async fn receive_folder_tar(app_handle: &AppHandle, socket: &mut TcpStream, offer: &FileOffer, addr: std::net::SocketAddr, dest: PathBuf) {
+    let transfer_id = offer.transfer_id.clone();
+    if let Err(e) = tokio::fs::create_dir_all(&dest).await {
+        error!("({addr}) Failed to create {:?}: {}", dest, e);
+        return;
+    }
+    emit_transfer_accepted(app_handle, &transfer_id, "receive", &addr.ip().to_string(), offer.sender_uuid.as_deref(), &offer.file_name, offer.file_size);
+    let active = ActiveTransferGuard::register(&transfer_id, addr.ip().to_string(), "receive", &offer.file_name, offer.file_size);
+    info!("({addr}) Extracting folder {} into {:?}", offer.file_name, dest);
+    let started = Instant::now();
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+    let extractor = {
+        let app_handle = app_handle.clone();
+        let transfer_id = transfer_id.clone();
+        let dest = dest.clone();
+        let max_bytes = offer.file_size;
+        tokio::task::spawn_blocking(move || {
+            extract_folder_tar(&app_handle, &transfer_id, ChannelReader { rx, chunk: Vec::new(), pos: 0 }, &dest, max_bytes)
+        })
     };
+    let mut buffer = vec![0u8; TAR_CHUNK];
+    let mut stream_bytes: u64 = 0;
+    let mut cancelled = false;
+    loop {
+        if is_receive_cancelled(&transfer_id).await {
+            CANCELLED_RECEIVE.lock().await.remove(&transfer_id);
+            cancelled = true;
+            break;
+        }
+        if let Some(ref batch_id) = offer.batch_id {
+            if is_batch_cancelled(batch_id).await {
+                cancelled = true;
+                break;
+            }
+        }
+        let n = match socket.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                warn!("({addr}) Folder stream read error: {}", e);
+                cancelled = true;
+                break;
+            }
+        };
+        stream_bytes += n as u64;
+        active.update(stream_bytes);
+        throttle_receive(n as u64).await;
+        // The extractor stopped (bad archive, size exceeded): stop reading
+        if tx.send(buffer[..n].to_vec()).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    let result = extractor.await.unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+    let elapsed_ms = started.elapsed().as_millis();
+    match (cancelled, result) {
+        (false, Ok((entries, bytes))) => {
+            info!("({addr}) Folder {} received: {} files, {} bytes", offer.file_name, entries, bytes);
+            let receipt = serde_json::json!({ "receipt": { "transfer_id": transfer_id, "received": bytes, "sha256": "" } });
+            let _ = socket.write_all((serde_json::to_string(&receipt).unwrap() + "\n").as_bytes()).await;
+            let _ = socket.flush().await;
+            let _ = record_transfer(app_handle.clone(), offer.file_name.clone(), bytes, TransferType::Received, addr.ip().to_string(), elapsed_ms, TransferStatus::Completed, RecordExtras { peer_uuid: offer.sender_uuid.clone(), ..Default::default() }).await;
+            let _ = app_handle.emit("transfer_complete", serde_json::json!({
+                "transfer_id": transfer_id,
+                "path": dest,
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "direction": "receive",
+                "entries": entries
+            }));
+        }
+        (cancelled, result) => {
+            let reason = match result {
+                Err(e) => e.to_string(),
+                Ok(_) => "cancelled".to_string(),
+            };
+            warn!("({addr}) Folder transfer {} stopped: {}", transfer_id, reason);
+            let _ = tokio::fs::remove_dir_all(&dest).await;
+            let status = if cancelled { TransferStatus::Cancelled } else { TransferStatus::Failed };
+            let _ = record_transfer(app_handle.clone(), offer.file_name.clone(), offer.file_size, TransferType::Received, addr.ip().to_string(), elapsed_ms, status, RecordExtras { peer_uuid: offer.sender_uuid.clone(), ..Default::default() }).await;
+            let _ = app_handle.emit("transfer_failed", serde_json::json!({
+                "transfer_id": transfer_id,
+                "reason": if cancelled { "cancelled" } else { "invalid_archive" },
+                "error": reason,
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "direction": "receive"
+            }));
+        }
+    }
+}
 
-    let record = TransferRecord {
-        id: uuid::Uuid::new_v4().to_string(),
-        file_name: file_name.clone(),
-        file_size,
-        transfer_type: transfer_type.clone(),
-        status: status.clone(),
-        from_device,
-        to_device,
-        start_time: chrono::Utc::now().to_rfc3339(),
-        duration: (elapsed_ms / 1000) as u64,
-        speed: speed_mbps,
-        device_type: DeviceType::Desktop,
+/// Send a whole folder as one tar stream (`kind: "folder-tar"`). Only use this with peers that
+/// advertise the capability; others would treat the archive as a single file.
+pub async fn send_folder_tar(target_ip: String, target_port: u16, folder: PathBuf, app_handle: AppHandle, batch_id: Option<String>) -> anyhow::Result<()> {
+    let files = {
+        let folder = folder.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut out = Vec::new();
+            list_files_recursive(&folder, &mut out);
+            out
+        }).await?
+    };
+    let total_bytes: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let folder_name = folder.file_name().and_then(|n| n.to_str()).unwrap_or("folder").to_string();
+    let transfer_id = Uuid::new_v4().to_string();
+    let started = Instant::now();
+    let result = stream_folder_tar(target_ip.clone(), target_port, folder, app_handle.clone(), files, total_bytes, folder_name.clone(), transfer_id.clone(), batch_id).await;
+    // Cancelled and refused sends were recorded as such where they stopped
+    if let Err(ref e) = result {
+        if e.downcast_ref::<TransferAborted>().is_none() {
+            error!("[SEND] Folder {} to {} failed: {}", folder_name, target_ip, e);
+            let _ = record_transfer(app_handle.clone(), folder_name, total_bytes, TransferType::Sent, target_ip.clone(), started.elapsed().as_millis(), TransferStatus::Failed, RecordExtras::default()).await;
+            let _ = app_handle.emit("transfer_failed", serde_json::json!({
+                "transfer_id": transfer_id,
+                "reason": "send_failed",
+                "error": e.to_string(),
+                "ip": target_ip,
+                "port": target_port,
+                "direction": "send"
+            }));
+        }
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stream_folder_tar(
+    target_ip: String,
+    target_port: u16,
+    folder: PathBuf,
+    app_handle: AppHandle,
+    files: Vec<(PathBuf, u64, u64)>,
+    total_bytes: u64,
+    folder_name: String,
+    transfer_id: String,
+    batch_id: Option<String>,
+) -> anyhow::Result<()> {
+    let offer = FileOffer {
+        transfer_id: transfer_id.clone(),
+        batch_id: batch_id.clone(),
+        kind: Some(FOLDER_TAR_KIND.to_string()),
+        ..FileOffer::new(folder_name.clone(), total_bytes, "application/x-tar")
+    };
+    info!("[SEND] Folder {:?} as tar: {} files, {} bytes", folder, files.len(), total_bytes);
+    let mut stream = connect_file_server(&target_ip, target_port).await?;
+    stream.write_all((serde_json::to_string(&offer)? + "\n").as_bytes()).await?;
+
+    let ack = read_json_line(&mut stream).await?;
+    if !ack.get("accept").and_then(|v| v.as_bool()).unwrap_or(false) {
+        let err_msg = ack.get("error").and_then(|v| v.as_str()).unwrap_or("rejected");
+        return Err(TransferAborted(format!("Transfer rejected by peer: {}", err_msg)).into());
+    }
+    emit_transfer_accepted(&app_handle, &transfer_id, "send", &target_ip, ack.get("device_uuid").and_then(|v| v.as_str()), &folder_name, total_bytes);
+    let active = ActiveTransferGuard::register(&transfer_id, target_ip.clone(), "send", &folder_name, total_bytes);
+    let _batch_conn = batch_id.as_deref().map(BatchConnection::open);
+
+    let entry_count = files.len() as u64;
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+    let builder = {
+        let folder = folder.clone();
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let mut builder = tar::Builder::new(std::io::BufWriter::with_capacity(TAR_CHUNK, ChannelWriter(tx)));
+            builder.follow_symlinks(false);
+            for (path, _, _) in &files {
+                let Ok(rel) = path.strip_prefix(&folder) else { continue; };
+                builder.append_path_with_name(path, rel)?;
+            }
+            builder.into_inner()?.into_inner().map_err(|e| e.into_error())?;
+            Ok(())
+        })
+    };
+    let started = Instant::now();
+    let speed_unit = read_settings().await.speed_unit;
+    // Headers and block padding add up to ~1KiB per entry on top of the content
+    let stream_total = total_bytes + 1024 * entry_count;
+    let mut sent: u64 = 0;
+    let mut last_emit = Instant::now();
+    sync_send_limit().await;
+    while let Some(chunk) = rx.recv().await {
+        let batch_cancelled = match batch_id.as_deref() {
+            Some(batch_id) => is_batch_cancelled(batch_id).await,
+            None => false,
+        };
+        if batch_cancelled || is_send_cancelled(&target_ip, target_port).await || CANCELLED_SEND_IDS.lock().await.remove(&transfer_id) {
+            CANCELLED_TRANSFERS.lock().await.remove(&format!("{}:{}", target_ip, target_port));
+            drop(rx);
+            let _ = builder.await;
+            let _ = record_transfer(app_handle.clone(), folder_name, total_bytes, TransferType::Sent, target_ip, started.elapsed().as_millis(), TransferStatus::Cancelled, RecordExtras::default()).await;
+            return Err(TransferAborted("Transfer cancelled by user".to_string()).into());
+        }
+        stream.write_all(&chunk).await?;
+        sent += chunk.len() as u64;
+        throttle_send(chunk.len() as u64).await;
+        active.update(sent.min(total_bytes));
+        if last_emit.elapsed() >= Duration::from_millis(200) {
+            last_emit = Instant::now();
+            let elapsed_ms = started.elapsed().as_millis();
+            let speed = speed_mb_per_sec(sent, elapsed_ms);
+            let (eta_ms, eta_formatted) = calculate_eta(sent, stream_total, elapsed_ms);
+            let _ = app_handle.emit("transfer_progress", serde_json::json!({
+                "transfer_id": transfer_id,
+                "sent": sent,
+                "total": stream_total,
+                "percent": percent_of(sent, stream_total),
+                "batch_id": batch_id,
+                "file_name": folder_name,
+                "ip": target_ip,
+                "port": target_port,
+                "direction": "send",
+                "eta_ms": eta_ms,
+                "eta_formatted": eta_formatted,
+                "speed": speed,
+                "speed_formatted": format_speed(speed, speed_unit)
+            }));
+        }
+    }
+    builder.await??;
+    stream.flush().await?;
+    AsyncWriteExt::shutdown(&mut stream).await?;
+    let receipt = timeout(RECEIPT_TIMEOUT, read_receipt(&mut stream)).await.ok().flatten();
+    let delivered = receipt.as_ref().is_some_and(|r| r.transfer_id == transfer_id && r.received == total_bytes);
+    let elapsed_ms = started.elapsed().as_millis();
+    let _ = record_transfer(app_handle.clone(), folder_name, total_bytes, TransferType::Sent, target_ip.clone(), elapsed_ms, TransferStatus::Completed, RecordExtras { verified: None, delivery_confirmed: delivered, peer_uuid: ack.get("device_uuid").and_then(|v| v.as_str()).map(str::to_string) }).await;
+    let _ = app_handle.emit("transfer_complete", serde_json::json!({
+        "transfer_id": transfer_id,
+        "path": folder,
+        "ip": target_ip,
+        "port": target_port,
+        "direction": "send",
+        "delivery_confirmed": delivered
+    }));
+    Ok(())
+}
+
+/// Send a text snippet to a peer; delivered as a `kind: "text"` offer with the UTF-8 text as body.
+#[tauri::command]
+pub async fn send_text(ip: String, port: u16, text: String) -> Result<(), String> {
+    let body = text.into_bytes();
+    if body.len() as u64 > MAX_TEXT_SHARE_BYTES {
+        return Err(format!("text too large ({} bytes, limit {})", body.len(), MAX_TEXT_SHARE_BYTES));
+    }
+    let offer = FileOffer {
+        kind: Some("text".to_string()),
+        ..FileOffer::new("text.txt", body.len() as u64, "text/plain")
     };
+    let mut stream = connect_file_server(&ip, port).await.map_err(|e| e.to_string())?;
+    let header = serde_json::to_string(&offer).map_err(|e| e.to_string())? + "\n";
+    stream.write_all(header.as_bytes()).await.map_err(|e| e.to_string())?;
+
+    let ack = read_json_line(&mut stream).await.map_err(|e| e.to_string())?;
+    if !ack.get("accept").and_then(|v| v.as_bool()).unwrap_or(false) {
+        let err_msg = ack.get("error").and_then(|v| v.as_str()).unwrap_or("rejected");
+        return Err(format!("Transfer rejected by peer: {}", err_msg));
+    }
+    stream.write_all(&body).await.map_err(|e| e.to_string())?;
+    stream.flush().await.map_err(|e| e.to_string())?;
+    let _ = AsyncWriteExt::shutdown(&mut stream).await;
+    Ok(())
+}
+
+/// Send `paths` as one batch over a single connection (peers with MULTI_FILE_CAPABILITY only).
+/// Each file goes through send_file_with_progress's own offer, ack, body and receipt on the shared
+/// connection, so queueing, pause, cancel, encryption and history work as for separate sends;
+/// only the per-file connect is saved. Stops at the first failure or refusal that isn't a per-file skip.
+#[allow(clippy::too_many_arguments)]
+pub async fn send_files_multiplexed(
+    target_ip: String,
+    target_port: u16,
+    paths: Vec<PathBuf>,
+    app_handle: AppHandle,
+    batch_id: String,
+    overall_sent: std::sync::Arc<TokioMutex<u64>>,
+    overall_total: u64,
+    priority: Option<i32>,
+    encryption_pin: Option<String>,
+) -> anyhow::Result<()> {
+    let total_files = paths.len();
+    let mut conn = None;
+    info!("[SEND] {} files to {}:{} on one connection, batch {}", total_files, target_ip, target_port, batch_id);
+    for (index, path) in paths.iter().enumerate() {
+        let result = send_file_over(
+            &mut conn,
+            index + 1 < total_files,
+            target_ip.clone(),
+            target_port,
+            path.clone(),
+            app_handle.clone(),
+            Some(index),
+            Some(total_files),
+            None,
+            Some(overall_sent.clone()),
+            Some(overall_total),
+            Some(batch_id.clone()),
+            None,
+            priority,
+            encryption_pin.clone(),
+        ).await;
+        record_batch_outcome(&app_handle, &batch_id, &result).await;
+        match result {
+            Ok(()) => {}
+            Err(e) if e.downcast_ref::<FileSkipped>().is_some() => info!("[SEND] {:?}: {}", path, e),
+            Err(e) => {
+                // The connection is in an unknown state after a failure, so the rest isn't sent
+                let unsent = &paths[index + 1..];
+                record_unsent_cancelled(&app_handle, &target_ip, unsent).await;
+                for _ in unsent {
+                    record_batch_outcome(&app_handle, &batch_id, &Err(TransferAborted(e.to_string()).into())).await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// --- Optional body compression ---
+// Peers advertising this decompress offers that carry `compression` and a `compressed_size`
+pub const COMPRESSION_CAPABILITY: &str = "compression";
+// Peers advertising this also take compressed bodies streamed as frames, without a `compressed_size`
+pub const COMPRESSION_STREAM_CAPABILITY: &str = "compression-stream";
+/// Accepted values of `FileOffer::compression` and of the compression setting.
+pub const SUPPORTED_COMPRESSIONS: &[&str] = &["zstd", "gzip"];
+const ZSTD_LEVEL: i32 = 3;
+// Largest frame of a streamed compressed body
+const MAX_COMPRESSED_FRAME: usize = 1024 * 1024;
+// Most decompressed output held at once per receive
+const MAX_INFLATE_STEP: usize = 4 * 1024 * 1024;
+// Deflate expands at most ~1032:1, so gzip input fed this much at a time stays under MAX_INFLATE_STEP
+const GZIP_FEED_STEP: usize = 4 * 1024;
+
+fn peer_has_capability(app_handle: &AppHandle, ip: &str, capability: &str) -> bool {
+    use tauri::Manager;
+    let devices = app_handle.state::<crate::SharedDevices>();
+    let devs = devices.lock().unwrap();
+    devs.iter().any(|e| e.device.ip == ip && e.device.capabilities.iter().any(|c| c == capability))
+}
+
+/// The compression to stream a body to `target_ip` with. None (send as-is) when the setting is
+/// off or the peer can't take streamed bodies; encrypted bodies need their size up front, so
+/// they go uncompressed too.
+async fn compression_for_peer(app_handle: &AppHandle, target_ip: &str, encrypted: bool) -> Option<&'static str> {
+    let setting = read_settings().await.compression?;
+    let kind = SUPPORTED_COMPRESSIONS.iter().copied().find(|k| *k == setting)?;
+    if encrypted || !peer_has_capability(app_handle, target_ip, COMPRESSION_STREAM_CAPABILITY) {
+        return None;
+    }
+    Some(kind)
+}
+
+/// Append `data` as length-prefixed frames of at most MAX_COMPRESSED_FRAME bytes.
+fn push_frames(out: &mut Vec<u8>, data: &[u8]) {
+    for frame in data.chunks(MAX_COMPRESSED_FRAME) {
+        out.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        out.extend_from_slice(frame);
+    }
+}
+
+/// Streaming compressor for a sent body: file chunks in, framed compressed bytes out.
+enum BodyEncoder {
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+}
+
+impl BodyEncoder {
+    fn new(kind: &str) -> std::io::Result<Self> {
+        match kind {
+            "zstd" => Ok(Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)),
+            "gzip" => Ok(Self::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()))),
+            other => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported compression {}", other))),
+        }
+    }
+
+    /// Compress one chunk and return the frames it completed, if any.
+    fn feed(&mut self, chunk: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Write;
+        let compressed = match self {
+            Self::Zstd(e) => {
+                e.write_all(chunk)?;
+                std::mem::take(e.get_mut())
+            }
+            Self::Gzip(e) => {
+                e.write_all(chunk)?;
+                std::mem::take(e.get_mut())
+            }
+        };
+        let mut out = Vec::new();
+        push_frames(&mut out, &compressed);
+        Ok(out)
+    }
+
+    /// The last frames of the body, ending with the empty frame that terminates it.
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        let tail = match self {
+            Self::Zstd(e) => e.finish()?,
+            Self::Gzip(e) => e.finish()?,
+        };
+        let mut out = Vec::new();
+        push_frames(&mut out, &tail);
+        out.extend_from_slice(&0u32.to_be_bytes());
+        Ok(out)
+    }
+}
+
+/// Splits a streamed compressed body back into frame payloads. `wanted` tells the receive loop
+/// how much to read next, so it never reads past the terminating frame into the next offer.
+#[derive(Default)]
+struct BodyFrames {
+    header: Vec<u8>,
+    remaining: usize,
+    ended: bool,
+}
+
+impl BodyFrames {
+    fn wanted(&self) -> usize {
+        if self.ended {
+            0
+        } else if self.remaining > 0 {
+            self.remaining
+        } else {
+            4 - self.header.len()
+        }
+    }
+
+    /// Account for bytes read (at most `wanted()`); returns the payload among them.
+    fn feed<'a>(&mut self, bytes: &'a [u8]) -> std::io::Result<&'a [u8]> {
+        if self.remaining > 0 {
+            self.remaining -= bytes.len();
+            return Ok(bytes);
+        }
+        self.header.extend_from_slice(bytes);
+        if self.header.len() == 4 {
+            let len = u32::from_be_bytes([self.header[0], self.header[1], self.header[2], self.header[3]]) as usize;
+            self.header.clear();
+            if len > MAX_COMPRESSED_FRAME {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} byte frame", len)));
+            }
+            self.remaining = len;
+            self.ended = len == 0;
+        }
+        Ok(&[])
+    }
+}
+
+/// Streaming decompressor for a received body. Input is queued with `push` and drained with
+/// `pull`, which hands back at most MAX_INFLATE_STEP bytes at a time, so a chunk that inflates
+/// hugely never sits in memory whole.
+struct BodyDecoder {
+    codec: BodyCodec,
+    input: Vec<u8>,
+}
+
+enum BodyCodec {
+    // `more` when the last step filled its buffer and output may still be pending;
+    // `frame_done` once zstd reported the end of the frame
+    Zstd { raw: zstd::stream::raw::Decoder<'static>, more: bool, frame_done: bool },
+    Gzip(Box<flate2::write::GzDecoder<Vec<u8>>>),
+}
+
+impl BodyDecoder {
+    fn new(kind: &str) -> std::io::Result<Self> {
+        let codec = match kind {
+            "zstd" => BodyCodec::Zstd { raw: zstd::stream::raw::Decoder::new()?, more: false, frame_done: false },
+            "gzip" => BodyCodec::Gzip(Box::new(flate2::write::GzDecoder::new(Vec::new()))),
+            other => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported compression {}", other))),
+        };
+        Ok(BodyDecoder { codec, input: Vec::new() })
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.input.extend_from_slice(chunk);
+    }
+
+    /// Decompress the next step of queued input; None once it is used up.
+    fn pull(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        use std::io::Write;
+        use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+        match &mut self.codec {
+            BodyCodec::Zstd { raw, more, frame_done } => {
+                if self.input.is_empty() && !*more {
+                    return Ok(None);
+                }
+                let mut out = vec![0u8; MAX_INFLATE_STEP];
+                let mut src = InBuffer::around(&self.input);
+                let mut dst = OutBuffer::around(&mut out[..]);
+                loop {
+                    *frame_done = raw.run(&mut src, &mut dst)? == 0;
+                    if dst.pos() == dst.capacity() || src.pos() == self.input.len() {
+                        break;
+                    }
+                }
+                *more = dst.pos() == dst.capacity();
+                let (consumed, produced) = (src.pos(), dst.pos());
+                self.input.drain(..consumed);
+                out.truncate(produced);
+                Ok(Some(out))
+            }
+            BodyCodec::Gzip(d) => {
+                if self.input.is_empty() {
+                    return Ok(None);
+                }
+                let step = self.input.len().min(GZIP_FEED_STEP);
+                d.write_all(&self.input[..step])?;
+                self.input.drain(..step);
+                Ok(Some(std::mem::take(d.get_mut())))
+            }
+        }
+    }
+
+    /// Remaining output once the whole body was pulled. Fails on a truncated stream: zstd must
+    /// have reached the end of its frame, gzip checks its CRC trailer.
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self.codec {
+            BodyCodec::Zstd { frame_done: true, .. } => Ok(Vec::new()),
+            BodyCodec::Zstd { .. } => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "zstd frame is incomplete")),
+            BodyCodec::Gzip(d) => (*d).finish(),
+        }
+    }
+}
+
+// --- Opt-in PIN encryption ---
+// The receiver shows a pairing secret (the "PIN"), the sender types it; both derive an
+// AES-256-GCM key from it and the salt in the offer. The salt travels in the clear, so the secret
+// carries 122 random bits rather than a few digits that could be brute-forced offline from one
+// recorded transfer. Each ENCRYPTION_CHUNK of body is sealed on its own, so a wrong PIN or a
+// tampered chunk fails the transfer at that chunk. The file's sha256 would identify known
+// content, so encrypted offers and receipts only carry it keyed (see BodyCipher::keyed_digest).
+pub const ENCRYPTION_AES_GCM: &str = "aes-256-gcm";
+const ENCRYPTION_CHUNK: usize = 64 * 1024;
+const GCM_TAG_LEN: u64 = 16;
+const PIN_KDF_ROUNDS: u32 = 200_000;
+
+static RECEIVE_PIN: Lazy<std::sync::Mutex<Option<String>>> = Lazy::new(|| std::sync::Mutex::new(None));
+
+// A v4 uuid in dash-separated groups of four hex digits
+fn new_pin() -> String {
+    let hex = Uuid::new_v4().simple().to_string();
+    hex.as_bytes().chunks(4).map(|g| String::from_utf8_lossy(g)).collect::<Vec<_>>().join("-")
+}
+
+/// The PIN as both sides feed it to the KDF: dashes, spaces and case don't matter when typing it.
+fn normalize_pin(pin: &str) -> String {
+    pin.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_lowercase()
+}
+
+/// PIN an encrypted sender has to type, shown on this (receiving) device; created on first call.
+#[tauri::command]
+pub fn get_encryption_pin() -> String {
+    RECEIVE_PIN.lock().unwrap().get_or_insert_with(new_pin).clone()
+}
+
+/// Replace the PIN, e.g. once the person it was shown to shouldn't send encrypted anymore.
+#[tauri::command]
+pub fn reset_encryption_pin() -> String {
+    let pin = new_pin();
+    *RECEIVE_PIN.lock().unwrap() = Some(pin.clone());
+    info!("Encryption PIN reset");
+    pin
+}
 
-    save_recent_transfer(&app_handle, &record)
-        .await
-        .map_err(|e| format!("failed to save recent transfer: {}", e))?;
+/// Seals (sender) or opens (receiver) a body chunk by chunk, carrying partial chunks between calls.
+struct BodyCipher {
+    cipher: aes_gcm::Aes256Gcm,
+    // Second half of the derived key, for keyed_digest
+    digest_key: [u8; 32],
+    nonce_prefix: [u8; 8],
+    counter: u32,
+    // Binds every chunk to this transfer
+    aad: Vec<u8>,
+    pending: Vec<u8>,
+    // Plaintext bytes not yet sealed/opened
+    remaining: u64,
+}
 
-    // Invia notifica se il trasferimento è completato
-    if matches!(status, TransferStatus::Completed) {
-        let (from, to) = match transfer_type {
-            TransferType::Sent => (local_device, target_name),
-            TransferType::Received => (target_name, local_device),
+impl BodyCipher {
+    async fn for_offer(offer: &FileOffer, pin: &str) -> anyhow::Result<Self> {
+        use aes_gcm::KeyInit;
+        let salt = offer.encryption_salt.as_deref().and_then(hex_decode).filter(|s| s.len() >= 16);
+        let nonce = offer.encryption_nonce.as_deref().and_then(hex_decode).and_then(|n| <[u8; 8]>::try_from(n).ok());
+        let (Some(salt), Some(nonce_prefix)) = (salt, nonce) else {
+            anyhow::bail!("missing or malformed encryption salt/nonce");
         };
-        let _ = send_completion_notification(&app_handle, &file_name, &from, &to, &transfer_type, file_size).await;
+        let pin = normalize_pin(pin);
+        let key = tokio::task::spawn_blocking(move || {
+            let mut key = [0u8; 64];
+            pbkdf2::pbkdf2_hmac::<sha2::Sha256>(pin.as_bytes(), &salt, PIN_KDF_ROUNDS, &mut key);
+            key
+        })
+        .await?;
+        let mut digest_key = [0u8; 32];
+        digest_key.copy_from_slice(&key[32..]);
+        Ok(Self {
+            cipher: aes_gcm::Aes256Gcm::new_from_slice(&key[..32])?,
+            digest_key,
+            nonce_prefix,
+            counter: 0,
+            aad: offer.transfer_id.as_bytes().to_vec(),
+            pending: Vec::new(),
+            remaining: offer.body_size(),
+        })
     }
 
-    Ok(())
-}
+    /// HMAC of a file's sha256 under this transfer's key: what encrypted offers and receipts carry
+    /// instead of the hash, so both ends can still compare it without revealing it.
+    fn keyed_digest(&self, sha256: &str) -> String {
+        use hmac::Mac;
+        let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(&self.digest_key).expect("HMAC takes any key length");
+        mac.update(sha256.to_lowercase().as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
 
-#[tauri::command]
-pub async fn get_recent_transfers() -> Result<Vec<TransferRecord>, String> {
-    let mut dir = dirs::data_dir()
-        .ok_or_else(|| "impossibile ottenere data_dir".to_string())?;
-    dir.push("AirShare");
-    dir.push("recent_transfers.json");
-    match tokio::fs::read(&dir).await {
-        Ok(bytes) if !bytes.is_empty() => {
-            serde_json::from_slice::<Vec<TransferRecord>>(&bytes)
-                .map_err(|e| format!("Failed to parse transfers: {}", e))
-        }
-        Ok(_) => Ok(Vec::new()),
-        Err(e) => Err(format!("Failed to read file: {}", e)),
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.nonce_prefix);
+        nonce[8..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
     }
-}
 
-async fn delete_transfer_by_id(transfer_id: &str) -> anyhow::Result<()> {
-    let _guard = RECENTS_LOCK.lock().await;
-    let mut dir = dirs::data_dir()
-        .ok_or_else(|| anyhow::anyhow!("impossibile ottenere data_dir"))?;
-    dir.push("AirShare");
-    
-    if !dir.exists() {
-        return Ok(());
+    fn next_chunk_len(&self) -> usize {
+        (ENCRYPTION_CHUNK as u64).min(self.remaining) as usize
     }
-    
-    let file_path = dir.join("recent_transfers.json");
-    
-    let existing: Vec<TransferRecord> = match tokio::fs::read(&file_path).await {
-        Ok(bytes) if !bytes.is_empty() => {
-            match serde_json::from_slice::<Vec<TransferRecord>>(&bytes) {
-                Ok(v) => v,
-                Err(_) => Vec::new(),
-            }
+
+    /// Add plaintext and return the sealed chunks it completed.
+    fn seal(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        use aes_gcm::aead::{Aead, Payload};
+        self.pending.extend_from_slice(data);
+        let mut out = Vec::new();
+        while self.remaining > 0 && self.pending.len() >= self.next_chunk_len() {
+            let len = self.next_chunk_len();
+            let nonce = self.next_nonce();
+            let sealed = self
+                .cipher
+                .encrypt(aes_gcm::Nonce::from_slice(&nonce), Payload { msg: &self.pending[..len], aad: &self.aad })
+                .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+            out.extend_from_slice(&sealed);
+            self.pending.drain(..len);
+            self.remaining -= len as u64;
         }
-        _ => Vec::new(),
+        Ok(out)
+    }
+
+    /// Add wire bytes and return the plaintext of the chunks they completed; fails on a bad tag.
+    fn open(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        use aes_gcm::aead::{Aead, Payload};
+        self.pending.extend_from_slice(data);
+        let mut out = Vec::new();
+        while self.remaining > 0 && self.pending.len() >= self.next_chunk_len() + GCM_TAG_LEN as usize {
+            let len = self.next_chunk_len();
+            let frame = len + GCM_TAG_LEN as usize;
+            let nonce = self.next_nonce();
+            let plain = self
+                .cipher
+                .decrypt(aes_gcm::Nonce::from_slice(&nonce), Payload { msg: &self.pending[..frame], aad: &self.aad })
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("authentication failed at chunk {}", self.counter - 1)))?;
+            out.extend_from_slice(&plain);
+            self.pending.drain(..frame);
+            self.remaining -= len as u64;
+        }
+        Ok(out)
+    }
+}
+
+// --- Identity query: describe this device without transferring anything ---
+// Offer kind asking the server for its PeerIdentity; no body in either direction
+pub const IDENTITY_KIND: &str = "identity";
+const IDENTITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    pub name: String,
+    pub device_uuid: String,
+    pub app_version: String,
+    pub capabilities: Vec<String>,
+    pub accepting: bool,
+}
+
+async fn reply_identity(socket: &mut TcpStream, addr: std::net::SocketAddr) {
+    let hostname = hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "Unknown".to_string());
+    let identity = PeerIdentity {
+        name: crate::normalize_device_name(&hostname),
+        device_uuid: DEVICE_UUID.clone(),
+        app_version: APP_VERSION.to_string(),
+        capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        accepting: is_accepting_transfers().await,
     };
-    
-    let updated: Vec<TransferRecord> = existing.into_iter()
-        .filter(|t| t.id != transfer_id)
-        .collect();
-    
-    let json = serde_json::to_vec_pretty(&updated)?;
-    let tmp_path = file_path.with_extension("json.tmp");
-    tokio::fs::write(&tmp_path, &json).await?;
-    tokio::fs::rename(&tmp_path, &file_path).await?;
-    
-    Ok(())
+    info!("({addr}) Answering identity query");
+    let reply = serde_json::json!({ "accept": false, "identity": identity });
+    let _ = socket.write_all((serde_json::to_string(&reply).unwrap() + "\n").as_bytes()).await;
+    let _ = socket.flush().await;
 }
 
+/// Ask a peer for its name, version, capabilities and accepting state, for diagnosing failed transfers.
+/// Builds without identity support treat the query as a file offer, so no answer within
+/// IDENTITY_TIMEOUT is reported as an older version.
 #[tauri::command]
-pub async fn delete_recent_transfer(transfer_id: String) -> Result<(), String> {
-    delete_transfer_by_id(&transfer_id)
+pub async fn query_peer_identity(ip: String, port: u16) -> Result<PeerIdentity, String> {
+    let offer = FileOffer {
+        kind: Some(IDENTITY_KIND.to_string()),
+        ..FileOffer::new(".airshare-identity", 0, "application/octet-stream")
+    };
+    let query = async {
+        let mut stream = connect_file_server(&ip, port).await.map_err(|e| e.to_string())?;
+        let header = serde_json::to_string(&offer).map_err(|e| e.to_string())? + "\n";
+        stream.write_all(header.as_bytes()).await.map_err(|e| e.to_string())?;
+        let mut reply = read_json_line(&mut stream).await.map_err(|e| e.to_string())?;
+        match reply.get_mut("identity").map(serde_json::Value::take) {
+            Some(identity) => serde_json::from_value(identity).map_err(|e| e.to_string()),
+            None => {
+                let err_msg = reply.get("error").and_then(|v| v.as_str()).unwrap_or("no identity in reply");
+                Err(format!("Peer did not describe itself: {}", err_msg))
+            }
+        }
+    };
+    timeout(IDENTITY_TIMEOUT, query)
         .await
-        .map_err(|e| format!("failed to delete transfer: {}", e))
+        .map_err(|_| "Peer did not answer the identity query (older AirShare version?)".to_string())?
 }
 
-/// Get file information for a given file path
+// --- Latency ping: a header-only round trip to a peer's file server ---
+// Peers answering `{"type":"ping"}` right away; older ones treat it as a bad offer
+pub const PING_CAPABILITY: &str = "ping";
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+// Last measured round trip per peer IP, shown as Device.latency_ms
+static PEER_LATENCY: Lazy<std::sync::Mutex<HashMap<String, u64>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// A control line sent instead of a FileOffer; only `type` is looked at.
+#[derive(Deserialize)]
+struct ControlHeader {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+async fn reply_ping(socket: &mut TcpStream) {
+    let reply = serde_json::json!({ "accept": false, "type": "pong" });
+    let _ = socket.write_all((serde_json::to_string(&reply).unwrap() + "\n").as_bytes()).await;
+    let _ = socket.flush().await;
+}
+
+/// Round trip from sending the ping line to the server's answer, in milliseconds. Connection
+/// setup isn't counted. The result is kept for get_devices.
 #[tauri::command]
-pub fn get_file_info(file_path: String) -> Result<FileInfo, String> {
-    match std::fs::metadata(&file_path) {
-        Ok(metadata) => {
-            let name = std::path::Path::new(&file_path)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown")
-                .to_string();
-                
-            Ok(FileInfo {
-                size: metadata.len(),
-                name,
-                is_file: metadata.is_file(),
-            })
+pub async fn ping_device(ip: String, port: u16) -> Result<u64, String> {
+    let ping = async {
+        let mut stream = connect_file_server(&ip, port).await.map_err(|e| e.to_string())?;
+        let started = Instant::now();
+        stream.write_all(b"{\"type\":\"ping\"}\n").await.map_err(|e| e.to_string())?;
+        loop {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.map_err(|e| e.to_string())?;
+            if byte[0] == b'\n' {
+                break;
+            }
         }
-        Err(e) => Err(format!("Failed to get file info: {}", e)),
+        Ok::<_, String>(started.elapsed().as_millis() as u64)
+    };
+    let latency_ms = timeout(PING_TIMEOUT, ping)
+        .await
+        .map_err(|_| format!("No answer within {}s", PING_TIMEOUT.as_secs()))??;
+    PEER_LATENCY.lock().unwrap().insert(ip, latency_ms);
+    Ok(latency_ms)
+}
+
+/// Last round trip measured to `ip`, if any.
+pub fn peer_latency(ip: &str) -> Option<u64> {
+    PEER_LATENCY.lock().unwrap().get(ip).copied()
+}
+
+/// Whether `dir` is (inside) one of AirShare's own data/config folders, where a crafted file name
+/// could overwrite settings.json, trusted_macs.json and the like.
+fn is_forbidden_destination(app_handle: &AppHandle, dir: &std::path::Path) -> bool {
+    use tauri::Manager;
+    let paths = app_handle.path();
+    let forbidden = [
+        dirs::data_dir().map(|d| d.join("AirShare")),
+        dirs::config_dir().map(|d| d.join("AirShare")),
+        paths.app_data_dir().ok(),
+        paths.app_config_dir().ok(),
+        paths.app_local_data_dir().ok(),
+    ];
+    // Canonicalize so "..", symlinks and case-insensitive spellings can't sneak past
+    let Ok(dir) = dir.canonicalize() else { return false; };
+    forbidden
+        .into_iter()
+        .flatten()
+        .filter_map(|f| f.canonicalize().ok())
+        .any(|f| dir.starts_with(&f))
+}
+
+/// Clean up after the destination stopped accepting writes mid-receive (e.g. USB drive unplugged):
+/// remove the partial file if still reachable, record the failure and tell the UI.
+/// The batch's saved folder is dropped so the remaining files prompt again instead of failing the same way.
+#[allow(clippy::too_many_arguments)]
+async fn handle_destination_unavailable(
+    app_handle: &AppHandle,
+    transfer_id: &str,
+    offer: &FileOffer,
+    addr: std::net::SocketAddr,
+    temp_path: &std::path::Path,
+    batch_id: &str,
+    elapsed_ms: u128,
+    err: &std::io::Error,
+) {
+    discard_partial_receive(temp_path, batch_id, addr).await;
+    let _ = add_recent_transfer(
+        app_handle.clone(),
+        offer.file_name.clone(),
+        offer.file_size,
+        TransferType::Received,
+        addr.ip().to_string(),
+        addr.ip().to_string(),
+        elapsed_ms,
+        TransferStatus::Failed,
+        offer.sender_uuid.clone(),
+    ).await;
+    let _ = app_handle.emit("transfer_failed", destination_unavailable_event(transfer_id, addr, temp_path, err));
+    tauri_log(app_handle, "error", format!("Destination unavailable for transfer {}: {}", transfer_id, err)).await;
+}
+
+/// A received body that can't be trusted: drop the file and report it as failed.
+async fn fail_corrupt_receive(
+    app_handle: &AppHandle,
+    transfer_id: &str,
+    offer: &FileOffer,
+    addr: std::net::SocketAddr,
+    temp_path: &std::path::Path,
+    elapsed_ms: u128,
+    reason: &str,
+) {
+    let _ = tokio::fs::remove_file(temp_path).await;
+    let _ = app_handle.emit("transfer_failed", serde_json::json!({
+        "transfer_id": transfer_id,
+        "reason": reason,
+        "file_name": offer.file_name,
+        "ip": addr.ip().to_string(),
+        "port": addr.port(),
+        "direction": "receive"
+    }));
+    let _ = record_transfer(
+        app_handle.clone(),
+        offer.file_name.clone(),
+        offer.file_size,
+        TransferType::Received,
+        addr.ip().to_string(),
+        elapsed_ms,
+        TransferStatus::Failed,
+        RecordExtras { verified: Some(false), peer_uuid: offer.sender_uuid.clone(), ..Default::default() },
+    ).await;
+}
+
+/// Remove the partial file and forget the batch's save directory, which is gone for the
+/// remaining files too.
+async fn discard_partial_receive(temp_path: &std::path::Path, batch_id: &str, addr: std::net::SocketAddr) {
+    if let Err(e) = tokio::fs::remove_file(temp_path).await {
+        warn!("({addr}) Could not remove partial file {:?}: {}", temp_path, e);
     }
+    let mut map = BATCH_RESPONSES.lock().await;
+    map.remove(batch_id);
 }
 
-/// Emit a backend_log event to the frontend with a level and message
-pub async fn tauri_log(app_handle: &AppHandle, level: &str, message: impl Into<String>) {
-    let payload = serde_json::json!({
-        "level": level,
-        "message": message.into(),
-        "ts": chrono::Utc::now().to_rfc3339(),
-    });
-    let _ = app_handle.emit("backend_log", payload);
+fn destination_unavailable_event(
+    transfer_id: &str,
+    addr: std::net::SocketAddr,
+    temp_path: &std::path::Path,
+    err: &std::io::Error,
+) -> serde_json::Value {
+    serde_json::json!({
+        "transfer_id": transfer_id,
+        "reason": "destination_unavailable",
+        "error": err.to_string(),
+        "path": temp_path,
+        "ip": addr.ip().to_string(),
+        "port": addr.port(),
+        "direction": "receive"
+    })
 }
 
-/// Start a TCP file server for incoming file transfers.
-pub async fn start_file_server(app_handle: tauri::AppHandle) -> anyhow::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:40124").await?;
-    info!("File server listening on 0.0.0.0:40124");
-    tauri_log(&app_handle, "info", "File server listening on 0.0.0.0:40124").await;
-    info!("Entering file server loop");
-    
-    // Log delle interfacce di rete disponibili per debug
-    if let Ok(addrs) = get_if_addrs::get_if_addrs() {
-        info!("Available network interfaces:");
-        for iface in addrs {
-            if !iface.is_loopback() {
-                info!("  - {}: {}", iface.name, iface.ip());
+#[cfg(test)]
+mod destination_unavailable_tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A drive that is unplugged after `capacity` bytes.
+    struct UnpluggedDisk {
+        written: u64,
+        capacity: u64,
+    }
+
+    impl tokio::io::AsyncWrite for UnpluggedDisk {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            if self.written + buf.len() as u64 > self.capacity {
+                return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::NotFound, "device removed")));
             }
+            self.written += buf.len() as u64;
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
         }
     }
-    loop {
-        let (mut socket, addr) = match listener.accept().await {
-            Ok(res) => {
-                info!("Accepted new connection from {}", res.1);
-                tauri_log(&app_handle, "info", format!("Accepted new connection from {}", res.1)).await;
-                res
-            },
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
-                tauri_log(&app_handle, "error", format!("Failed to accept connection: {}", e)).await;
-                return Err(e.into());
+
+    #[tokio::test]
+    async fn mid_stream_write_error_fails_the_transfer_and_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("airshare-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let temp_path = dir.join("video.mp4");
+        std::fs::write(&temp_path, vec![0u8; 1024]).unwrap();
+        let batch_id = format!("batch-{}", Uuid::new_v4());
+        BATCH_RESPONSES.lock().await.insert(batch_id.clone(), (true, Some(dir.clone())));
+        let addr: std::net::SocketAddr = "192.168.1.20:8080".parse().unwrap();
+
+        let mut sink = ReceiveSink::new(UnpluggedDisk { written: 0, capacity: 1024 * 1024 }, MIN_RECEIVE_HIGH_WATER_MARK);
+        let chunk = vec![1u8; 64 * 1024];
+        let mut chunks_written = 0;
+        let err = loop {
+            match sink.write(&chunk).await {
+                Ok(()) => chunks_written += 1,
+                Err(e) => break e,
             }
+            assert!(chunks_written < 64, "the write error never surfaced");
         };
+        assert!(chunks_written > 0, "the disk failed before any data reached it");
+        drop(sink);
 
-        if let Err(e) = socket.set_nodelay(true) {
-            warn!("Failed to set TCP_NODELAY on {}: {}", addr, e);
-            tauri_log(&app_handle, "warn", format!("Failed to set TCP_NODELAY on {}: {}", addr, e)).await;
-        }
+        discard_partial_receive(&temp_path, &batch_id, addr).await;
+        let event = destination_unavailable_event("t1", addr, &temp_path, &err);
 
-        let app_handle = app_handle.clone();
-        tokio::spawn(async move {
-            // Read header JSON until newline
-            let mut header_buf = Vec::new();
-            info!("({addr}) Waiting for header JSON line (ending with \\n)...");
-            loop {
-                let mut byte = [0u8; 1];
-                if let Err(e) = socket.read_exact(&mut byte).await {
-                    error!("({addr}) Failed to read header byte (client closed early?): {}", e);
-                    // Could not read header at all -> nothing we can do; no ack to send
-                    return;
-                }
-                if byte[0] == b'\n' {
-                    break;
-                }
-                header_buf.push(byte[0]);
-                // Limit header size for safety
-                if header_buf.len() > 16 * 1024 {
-                    error!("({addr}) Header too large (>16KiB) without newline. Sending negative ack and closing.");
-                    let nack = serde_json::json!({ "accept": false, "error": "header too large or missing newline" });
-                    let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
-                    if let Err(e) = socket.write_all(nack_str.as_bytes()).await {
-                        error!("({addr}) Failed to write negative ack: {}", e);
-                    } else {
-                        let _ = socket.flush().await;
-                        info!("({addr}) Negative ack sent for oversized header.");
-                    }
-                    return;
-                }
-            }
-            info!("({addr}) Read header bytes, length: {}", header_buf.len());
-            let header_str = match String::from_utf8(header_buf) {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("({addr}) Invalid header utf8: {}. Sending negative ack.", e);
-                    tauri_log(&app_handle, "error", format!("Invalid header utf8 from {}: {}", addr, e)).await;
-                    let nack = serde_json::json!({ "accept": false, "error": "invalid utf8 in header" });
-                    let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
-                    if let Err(w) = socket.write_all(nack_str.as_bytes()).await {
-                        error!("({addr}) Failed to write negative ack: {}", w);
-                    } else {
-                        let _ = socket.flush().await;
-                        info!("({addr}) Negative ack sent due to UTF-8 error.");
-                    }
-                    return;
-                }
-            };
-            info!("({addr}) Received header line: {}", header_str);
+        assert!(!temp_path.exists());
+        assert!(!BATCH_RESPONSES.lock().await.contains_key(&batch_id));
+        assert_eq!(event["reason"], "destination_unavailable");
+        assert_eq!(event["transfer_id"], "t1");
+        assert_eq!(event["direction"], "receive");
+        assert_eq!(event["error"], "device removed");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
 
-            let offer: FileOffer = match serde_json::from_str(&header_str) {
-                Ok(o) => o,
-                Err(e) => {
-                    error!("({addr}) Invalid header JSON: {}. Sending negative ack.", e);
-                    tauri_log(&app_handle, "error", format!("Invalid header JSON from {}: {}", addr, e)).await;
-                    let nack = serde_json::json!({ "accept": false, "error": "invalid json" });
-                    let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
-                    if let Err(w) = socket.write_all(nack_str.as_bytes()).await {
-                        error!("({addr}) Failed to write negative ack: {}", w);
-                    } else {
-                        let _ = socket.flush().await;
-                        info!("({addr}) Negative ack sent due to JSON parse error.");
-                    }
-                    return;
-                }
-            };
-            info!(
-                "[RECV] Parsed FileOffer | transfer_id={} batch_id={:?} file_name={}",
-                offer.transfer_id, offer.batch_id, offer.file_name
-            );
-            // Emit the full header JSON line to the frontend for debugging
-            tauri_log(&app_handle, "debug", format!("[RECV] Full FileOffer JSON: {}", header_str)).await;
-            // Determine batch_id (use transfer_id if not present)
-            let batch_id = offer.batch_id.clone().unwrap_or_else(|| offer.transfer_id.clone());
-            info!("({addr}) Parsed file offer: {:?}, batch_id: {}", offer, batch_id);
-            tauri_log(&app_handle, "info", format!("Parsed file offer from {}: {} ({} bytes)", addr, offer.file_name, offer.file_size)).await;
-
-            let transfer_id = offer.transfer_id.clone();
-            // Record transfer -> ip for potential trust saving
-            {
-                let mut tmap = TRANSFER_IPS.lock().await;
-                // prefer sender_mac advertised by sender, fallback to ip string
-                if let Some(ref mac) = offer.sender_mac {
-                    tmap.insert(transfer_id.clone(), mac.to_lowercase());
-                } else {
-                    tmap.insert(transfer_id.clone(), addr.ip().to_string());
-                }
-            }
-            let mut accept: bool;
-            let mut save_dir: Option<PathBuf>;
-            let mut is_batch_first = false;
+// --- Send scheduling: a fixed number of concurrent sends per peer, queued by priority ---
+// Per peer, so a slow or stalled device doesn't hold up sends to everyone else
+const MAX_CONCURRENT_SENDS_PER_PEER: usize = 2;
 
-            // Check if we already have a batch response
-            {
-                let map = BATCH_RESPONSES.lock().await;
-                if let Some((a, d)) = map.get(&batch_id) {
-                    info!("({addr}) [BATCH] Existing batch_id {} found. Reusing accept/dir for new connection.", batch_id);
-                    tauri_log(&app_handle, "info", format!("[BATCH] Existing batch_id {} found. Reusing accept/dir for new connection from {}.", batch_id, addr)).await;
-                    accept = *a;
-                    save_dir = d.clone();
-                } else {
-                    info!("({addr}) [BATCH] No entry for batch_id {}. Checking auto-accept/trust or asking user.", batch_id);
-                    tauri_log(&app_handle, "info", format!("[BATCH] No entry for batch_id {}. Checking auto-accept/trust or asking user from {}.", batch_id, addr)).await;
-                    is_batch_first = true;
-                    accept = false;
-                    save_dir = None;
-                }
-            }
+struct QueuedSend {
+    transfer_id: String,
+    batch_id: Option<String>,
+    peer: String,
+    priority: i32,
+    seq: u64,
+    wake: tokio::sync::oneshot::Sender<()>,
+}
 
-            if is_batch_first {
-                // Check if auto-accept is enabled and IP is trusted
-                let maybe_mac = offer.sender_mac.clone().map(|s| s.to_lowercase());
-                let auto_enabled = read_settings().await.auto_accept_trusted;
-                let trusted = read_trusted_macs().await;
-                let should_auto_accept = auto_enabled && maybe_mac.as_ref().map_or(false, |m| trusted.iter().any(|t| t == m));
-                if should_auto_accept {
-                    info!("({addr}) ✅ Auto-accept enabled for trusted MAC: {}", maybe_mac.clone().unwrap_or_default());
-                    tauri_log(&app_handle, "info", format!("✅ Auto-accept enabled for trusted MAC: {}", maybe_mac.clone().unwrap_or_default())).await;
-                    
-                    accept = true;
-                    
-                    // Emit notification event to frontend
-                    let _ = app_handle.emit(
-                        "transfer_auto_accepted",
-                        serde_json::json!({
-                            "transfer_id": transfer_id,
-                            "file_name": offer.file_name,
-                            "file_size": offer.file_size,
-                            "ip": addr.ip().to_string(),
-                            "device_name": maybe_mac.clone().unwrap_or_default(),
-                        }),
-                    );
-                    
-                    // Ask only for destination folder (auto-accept)
-                    use std::sync::Arc;
-                    use tokio::sync::Mutex;
-                    let save_dir_result: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
-                    let save_dir_clone = save_dir_result.clone();
-                    
-                    FileDialogBuilder::new(app_handle.dialog().clone())
-                        .set_title("Scegli la cartella di destinazione per il file dal dispositivo fidato")
-                        .pick_folder(move |path| {
-                            let save_dir_clone = save_dir_clone.clone();
-                            tauri::async_runtime::spawn(async move {
-                                let mut result = save_dir_clone.lock().await;
-                                *result = path.and_then(|p| p.as_path().map(|path| PathBuf::from(path)));
-                            });
-                        });
-                    
-                    info!("({addr}) Auto-accept: Waiting for user to select destination folder...");
-                    tauri_log(&app_handle, "info", format!("Auto-accept: Waiting for destination folder selection for {}", addr.ip())).await;
-                    
-                    // Wait for folder selection with timeout
-                    let timeout_duration = tokio::time::Duration::from_secs(300); // 5 minuti timeout
-                    let start_time = tokio::time::Instant::now();
-                    
-                    let chosen_dir = loop {
-                        if start_time.elapsed() > timeout_duration {
-                            error!("({addr}) Timeout waiting for folder selection");
-                            tauri_log(&app_handle, "error", format!("Timeout waiting for folder selection from {}", addr)).await;
-                            
-                            // Send rejection
-                            let nack = serde_json::json!({ "accept": false, "error": "timeout_folder_selection" });
-                            let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
-                            let _ = socket.write_all(nack_str.as_bytes()).await;
-                            let _ = socket.flush().await;
-                            return;
-                        }
-                        
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                        let result = save_dir_result.lock().await;
-                        if result.is_some() {
-                            break result.clone();
-                        }
-                    };
-                    
-                    save_dir = chosen_dir;
-                    
-                    if save_dir.is_none() {
-                        info!("({addr}) User cancelled folder selection for auto-accepted transfer");
-                        tauri_log(&app_handle, "info", format!("User cancelled folder selection for auto-accepted transfer from {}", addr)).await;
-                        
-                        // Send rejection
-                        let nack = serde_json::json!({ "accept": false, "error": "user_cancelled_folder" });
-                        let nack_str = serde_json::to_string(&nack).unwrap() + "\n";
-                        if let Err(e) = socket.write_all(nack_str.as_bytes()).await {
-                            error!("({addr}) Failed to write cancellation ack: {}", e);
-                        } else {
-                            let _ = socket.flush().await;
-                        }
-                        return;
-                    }
-                    
-                    // Save to BATCH_RESPONSES
-                    {
-                        let mut map = BATCH_RESPONSES.lock().await;
-                        map.insert(batch_id.clone(), (accept, save_dir.clone()));
-                        info!("({addr}) [BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = true (auto-accept) and save_dir = {:?}", batch_id, save_dir);
-                        tauri_log(&app_handle, "info", format!("[BATCH] Saved batch_id {} to BATCH_RESPONSES (auto-accept)", batch_id)).await;
-                    }
-                } else {
-                    // NOT auto-accept: show normal prompt
-                    info!("({addr}) Auto-accept disabled or IP not trusted. Showing normal prompt.");
-                    tauri_log(&app_handle, "info", format!("Auto-accept disabled or IP not trusted for {}. Showing prompt.", addr.ip())).await;
-                    
-                    // Emit event to frontend (include source address info)
-                    info!("({addr}) Emitting transfer_request event for batch_id: {}", batch_id);
-                    tauri_log(&app_handle, "info", format!("Emitting transfer_request for {} from {}", transfer_id, addr)).await;
-                    let _ = app_handle.emit(
-                        "transfer_request",
-                        serde_json::json!({
-                            "offer": offer,
-                            "ip": addr.ip().to_string(),
-                            "port": addr.port(),
-                            "direction": "receive"
-                        }),
-                    );
-                    info!("({addr}) Waiting for user confirmation for transfer_id: {}", transfer_id);
-                    tauri_log(&app_handle, "info", format!("Waiting for user confirmation for transfer_id: {}", transfer_id)).await;
-                    
-                    // Wait for user response
-                    accept = loop {
-                        let map = TRANSFER_RESPONSES.lock().await;
-                        if let Some(&a) = map.get(&transfer_id) {
-                            break a;
-                        }
-                        drop(map);
-                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    };
-                    
-                    info!("({addr}) User responded with accept = {} for transfer_id: {}", accept, transfer_id);
-                    tauri_log(&app_handle, "info", format!("User responded with accept = {} for transfer_id: {}", accept, transfer_id)).await;
-                    
-                    // Remove transfer_id from TRANSFER_RESPONSES
-                    {
-                        let mut map = TRANSFER_RESPONSES.lock().await;
-                        map.remove(&transfer_id);
-                    }
-                    
-                    // If accepted, ask for folder; if user chose to trust, front-end will call respond_transfer with trust=true
-                    if accept {
-                        use std::sync::Arc;
-                        use tokio::sync::Mutex;
-                        let save_dir_result: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
-                        let save_dir_clone = save_dir_result.clone();
-                        FileDialogBuilder::new(app_handle.dialog().clone())
-                            .set_title("Scegli la cartella di destinazione per il file")
-                            .pick_folder(move |path| {
-                                let save_dir_clone = save_dir_clone.clone();
-                                tauri::async_runtime::spawn(async move {
-                                    let mut result = save_dir_clone.lock().await;
-                                    *result = path.and_then(|p| p.as_path().map(|path| PathBuf::from(path)));
-                                });
-                            });
-                        info!("({addr}) Waiting for user to select destination folder for batch_id: {}", batch_id);
-                        tauri_log(&app_handle, "info", format!("Waiting for user to select destination folder for batch_id: {}", batch_id)).await;
-                        
-                        let chosen_dir = loop {
-                            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                            let result = save_dir_result.lock().await;
-                            if result.is_some() {
-                                break result.clone();
-                            }
-                        };
-                        save_dir = chosen_dir;
-                        info!("({addr}) User selected destination folder for batch_id: {}: {:?}", batch_id, save_dir);
-                        tauri_log(&app_handle, "info", format!("User selected destination folder for batch_id: {}: {:?}", batch_id, save_dir)).await;
-                    }
-                    
-                    // Save to BATCH_RESPONSES (even if rejected, to avoid repeated asks)
-                    {
-                        let mut map = BATCH_RESPONSES.lock().await;
-                        map.insert(batch_id.clone(), (accept, save_dir.clone()));
-                        info!("({addr}) [BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = {} and save_dir = {:?}", batch_id, accept, save_dir);
-                        tauri_log(&app_handle, "info", format!("[BATCH] Saved batch_id {} to BATCH_RESPONSES with accept = {} and save_dir = {:?}", batch_id, accept, save_dir)).await;
-                    }
-                }
-            }
-            // Send ack JSON (expanded for potential error reporting)
-            let ack = if accept {
-                serde_json::json!({ "accept": true })
-            } else {
-                serde_json::json!({ "accept": false, "error": "user_rejected" })
-            };
-            let ack_str = serde_json::to_string(&ack).unwrap() + "\n";
-            match socket.write_all(ack_str.as_bytes()).await {
-                Ok(_) => {
-                    info!("({addr}) Sent ack to client: {}", ack_str.trim_end());
-                    tauri_log(&app_handle, "info", format!("Sent ack to {} for transfer {}", addr, transfer_id)).await;
-                    if let Err(e) = socket.flush().await {
-                        warn!("({addr}) Flush after ack failed: {}", e);
-                        tauri_log(&app_handle, "warn", format!("Flush after ack failed for {}: {}", addr, e)).await;
-                    }
-                }
-                Err(e) => {
-                    error!("({addr}) Failed to write ack: {}", e);
-                    tauri_log(&app_handle, "error", format!("Failed to write ack to {}: {}", addr, e)).await;
-                    // On error, cleanup batch entry if we just created it
-                    if is_batch_first {
-                        let mut map = BATCH_RESPONSES.lock().await;
-                        map.remove(&batch_id);
-                    }
-                    return;
-                }
-            }
-            if !accept {
-                info!("({addr}) Transfer rejected by user.");
-                // On reject, cleanup batch entry if we just created it
-                if is_batch_first {
-                    let mut map = BATCH_RESPONSES.lock().await;
-                    map.remove(&batch_id);
-                }
-                return;
-            }
-            // Retrieve save_dir from batch map (in case not first)
-            let actual_save_dir = {
-                let map = BATCH_RESPONSES.lock().await;
-                map.get(&batch_id).and_then(|(_, dir)| dir.clone())
-            };
-            let save_dir = match actual_save_dir {
-                Some(path) => path,
-                None => {
-                    info!("({addr}) Trasferimento annullato dall'utente.");
-                    let _ = app_handle.emit("transfer_rejected", serde_json::json!({
-                        "transfer_id": transfer_id,
-                        "reason": "user_cancelled"
-                    }));
-                    // On cancel, cleanup batch entry if we just created it
-                    if is_batch_first {
-                        let mut map = BATCH_RESPONSES.lock().await;
-                        map.remove(&batch_id);
-                    }
-                    return;
-                }
-            };
+#[derive(Default)]
+struct SendScheduler {
+    // Running sends by peer IP; peers with none are removed
+    running: HashMap<String, usize>,
+    next_seq: u64,
+    waiting: Vec<QueuedSend>,
+}
 
-            let temp_path = save_dir.join(&offer.file_name);
-            if let Err(e) = tokio::fs::create_dir_all(&save_dir).await {
-                error!("({addr}) Failed to create selected directory: {}", e);
-                tauri_log(&app_handle, "error", format!("Failed to create selected directory {}: {}", save_dir.display(), e)).await;
-                // On error, cleanup batch entry if we just created it
-                if is_batch_first {
-                    let mut map = BATCH_RESPONSES.lock().await;
-                    map.remove(&batch_id);
-                }
+impl SendScheduler {
+    /// Hand a slot freed for `peer` to its highest-priority waiter (FIFO within a priority).
+    fn release(&mut self, peer: &str) {
+        while let Some(idx) = self.waiting
+            .iter()
+            .enumerate()
+            .filter(|(_, q)| q.peer == peer)
+            .max_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).then(b.seq.cmp(&a.seq)))
+            .map(|(i, _)| i)
+        {
+            let next = self.waiting.remove(idx);
+            // A waiter whose send was dropped no longer listens; try the next one
+            if next.wake.send(()).is_ok() {
                 return;
             }
-            info!("({addr}) Creating destination file at {:?}", temp_path);
-            let mut file = match fs::File::create(&temp_path).await {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("({addr}) Failed to create file: {}", e);
-                    tauri_log(&app_handle, "error", format!("Failed to create file {}: {}", temp_path.display(), e)).await;
-                    return;
-                }
-            };
-            
-            // Receive exactly offer.file_size bytes
-            let mut received: u64 = 0;
-            let mut buffer = vec![0u8; 64 * 1024];
-            let mut last_log = Instant::now();
-            let transfer_start = Instant::now();
-            info!("({addr}) Beginning binary receive of {} bytes for transfer {}", offer.file_size, transfer_id);
-            while received < offer.file_size {
-                // Check if transfer was cancelled
-                if is_receive_cancelled(&transfer_id).await {
-                    error!("({addr}) Receive transfer was cancelled by user");
-                    tauri_log(&app_handle, "warn", format!("Receive transfer {} was cancelled", transfer_id)).await;
-                    let _ = add_recent_transfer(
-                        app_handle.clone(),
-                        offer.file_name.clone(),
-                        offer.file_size,
-                        TransferType::Received,
-                        addr.ip().to_string(),
-                        addr.ip().to_string(),
-                        transfer_start.elapsed().as_millis(),
-                        TransferStatus::Cancelled,
-                    ).await;
-                    // Cleanup cancelled state and temp file
-                    let mut cancelled = CANCELLED_RECEIVE.lock().await;
-                    cancelled.remove(&transfer_id);
-                    let _ = tokio::fs::remove_file(&temp_path).await;
-                    return;
-                }
-
-                let to_read = std::cmp::min(buffer.len() as u64, offer.file_size - received) as usize;
-                let n = match socket.read(&mut buffer[..to_read]).await {
-                    Ok(0) => {
-                        error!(
-                            "({addr}) Peer closed connection early at {} / {} bytes for transfer {}",
-                            received, offer.file_size, transfer_id
-                        );
-                        return;
-                    }
-                    Ok(n) => n,
-                    Err(e) => {
-                        error!("({addr}) Error receiving file: {}", e);
-                        return;
-                    }
-                };
-                if let Err(e) = file.write_all(&buffer[..n]).await {
-                    error!("({addr}) File write error: {}", e);
-                    tauri_log(&app_handle, "error", format!("File write error {}: {}", temp_path.display(), e)).await;
-                    return;
-                }
-                received += n as u64;
-                
-                // Calcola ETA per il progresso
-                let elapsed_ms = transfer_start.elapsed().as_millis();
-                let (eta_ms, eta_formatted) = calculate_eta(received, offer.file_size, elapsed_ms);
-                
-                // Emit progress con ETA
-                let progress = serde_json::json!({
-                    "transfer_id": transfer_id,
-                    "received": received,
-                    "total": offer.file_size,
-                    "percent": (received as f64 / offer.file_size as f64) * 100.0,
-                    "ip": addr.ip().to_string(),
-                    "port": addr.port(),
-                    "direction": "receive",
-                    "eta_ms": eta_ms,
-                    "eta_formatted": eta_formatted
-                });
-                let _ = app_handle.emit("transfer_progress", progress);
-                info!("({addr}) Received {} / {} bytes", received, offer.file_size);
-
-                // Throttled log once per second for frontend debugging context
-                if last_log.elapsed().as_secs_f64() >= 1.0 {
-                    let percent = (received as f64 / offer.file_size as f64) * 100.0;
-                    let (_, eta_formatted) = calculate_eta(received, offer.file_size, elapsed_ms);
-                    info!(
-                        "recv progress | id={} ip={} port={} received={} total={} percent={:.1} eta={}",
-                        transfer_id,
-                        addr.ip(),
-                        addr.port(),
-                        received,
-                        offer.file_size,
-                        percent,
-                        eta_formatted
-                    );
-                    tauri_log(&app_handle, "info", format!(
-                        "recv progress | id={} ip={} port={} received={} total={} percent={:.1} eta={}",
-                        transfer_id, addr.ip(), addr.port(), received, offer.file_size, percent, eta_formatted
-                    )).await;
-                    last_log = Instant::now();
-                }
+        }
+        if let Some(n) = self.running.get_mut(peer) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                self.running.remove(peer);
             }
+        }
+    }
+}
 
-            if let Err(e) = file.sync_all().await {
-                warn!("({addr}) Failed to fsync file {:?}: {}", temp_path, e);
-            }
+static SEND_SCHEDULER: Lazy<std::sync::Mutex<SendScheduler>> = Lazy::new(|| std::sync::Mutex::new(SendScheduler::default()));
 
-            // Funzione di dialogo rimossa come richiesto
+/// A running send's slot for a peer, handed on to that peer's next queued send on drop.
+struct SendSlot(String);
 
-            let _ = app_handle.emit("transfer_complete", serde_json::json!({
-                "transfer_id": transfer_id,
-                "path": temp_path,
-                "ip": addr.ip().to_string(),
-                "port": addr.port(),
-                "direction": "receive"
-            }));
-            info!("({addr}) File transfer complete: {:?}", temp_path);
-            tauri_log(&app_handle, "info", format!("receive complete | id={} ip={} port={} path={}", transfer_id, addr.ip(), addr.port(), temp_path.display())).await;
+impl Drop for SendSlot {
+    fn drop(&mut self) {
+        SEND_SCHEDULER.lock().unwrap().release(&self.0);
+    }
+}
 
-            // Registra nella cronologia (ricezione completata)
-            let _ = add_recent_transfer(
-                app_handle.clone(),
-                offer.file_name.clone(),
-                offer.file_size,
-                TransferType::Received,
-                addr.ip().to_string(),
-                addr.ip().to_string(),
-                transfer_start.elapsed().as_millis(),
-                TransferStatus::Completed,
-            ).await;
+/// A queued send's wake-up. On drop it leaves the queue, so `queued` in get_transfer_status only
+/// counts sends still waiting; if it was handed a slot without noticing, the slot is passed on.
+struct SlotWaiter {
+    rx: tokio::sync::oneshot::Receiver<()>,
+    peer: String,
+    seq: u64,
+}
 
-            // --- PATCH: Do NOT remove batch entry here. Removal must be done only when all files in the batch are complete. ---
-            // The entry for batch_id will persist until explicit cleanup logic is added (not here).
+impl Drop for SlotWaiter {
+    fn drop(&mut self) {
+        let mut sched = SEND_SCHEDULER.lock().unwrap();
+        if self.rx.try_recv().is_ok() {
+            sched.release(&self.peer);
+        } else {
+            sched.waiting.retain(|q| q.seq != self.seq);
+        }
+    }
+}
 
-            // Gracefully shutdown write half (if any) to signal proper end
-            if let Err(e) = AsyncWriteExt::shutdown(&mut socket).await {
-                warn!("({addr}) Socket shutdown after receive failed: {}", e);
-                tauri_log(&app_handle, "warn", format!("Socket shutdown after receive failed for {}: {}", addr, e)).await;
-            }
+/// Wait for a send slot to `peer`; `None` if the send was dropped from the queue (its batch was cancelled).
+async fn acquire_send_slot(app_handle: &AppHandle, peer: &str, transfer_id: &str, batch_id: Option<&str>, file_name: &str, priority: i32) -> Option<SendSlot> {
+    let mut wait = {
+        let mut sched = SEND_SCHEDULER.lock().unwrap();
+        let running = sched.running.entry(peer.to_string()).or_insert(0);
+        if *running < MAX_CONCURRENT_SENDS_PER_PEER {
+            *running += 1;
+            return Some(SendSlot(peer.to_string()));
+        }
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let seq = sched.next_seq;
+        sched.next_seq += 1;
+        sched.waiting.push(QueuedSend {
+            transfer_id: transfer_id.to_string(),
+            batch_id: batch_id.map(str::to_string),
+            peer: peer.to_string(),
+            priority,
+            seq,
+            wake: tx,
         });
+        SlotWaiter { rx, peer: peer.to_string(), seq }
+    };
+    info!("[SEND] {} queued with priority {}", transfer_id, priority);
+    let _ = app_handle.emit("transfer_queued", serde_json::json!({
+        "transfer_id": transfer_id,
+        "file_name": file_name,
+        "priority": priority
+    }));
+    // Fails only when cancel_batch removed this entry, in which case no slot was handed over
+    (&mut wait.rx).await.ok().map(|_| SendSlot(peer.to_string()))
+}
+
+/// Change the priority of a send still waiting for a slot; higher runs first. In-flight sends are unaffected.
+#[tauri::command]
+pub fn set_transfer_priority(transfer_id: String, priority: i32) -> Result<(), String> {
+    let mut sched = SEND_SCHEDULER.lock().unwrap();
+    match sched.waiting.iter_mut().find(|q| q.transfer_id == transfer_id) {
+        Some(q) => {
+            q.priority = priority;
+            Ok(())
+        }
+        None => Err(format!("transfer {} is not queued", transfer_id)),
+    }
+}
+
+// The receiver fsyncs before answering, so allow for a slow disk
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct DeliveryReceipt {
+    transfer_id: String,
+    received: u64,
+    sha256: String,
+}
+
+/// Read the receiver's `{"receipt": {...}}` line; None if the peer closed without one.
+async fn read_receipt(stream: &mut TcpStream) -> Option<DeliveryReceipt> {
+    let mut value = read_json_line(stream).await.ok()?;
+    serde_json::from_value(value.get_mut("receipt")?.take()).ok()
+}
+
+// Longest ack, receipt or reply line accepted from a peer
+const MAX_JSON_LINE: usize = 8 * 1024;
+
+/// Read one newline-terminated JSON line (an ack, receipt or reply) from a peer.
+pub(crate) async fn read_json_line(stream: &mut TcpStream) -> anyhow::Result<serde_json::Value> {
+    let mut line = Vec::new();
+    loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+        if line.len() > MAX_JSON_LINE {
+            anyhow::bail!("line too large (>{} bytes) without newline", MAX_JSON_LINE);
+        }
     }
+    Ok(serde_json::from_slice(&line)?)
 }
 
 /// Send a file to a peer over TCP.
@@ -964,6 +5813,7 @@ pub async fn send_file(
     path: PathBuf,
     app_handle: tauri::AppHandle,
     batch_id: Option<String>,
+    collision_hint: Option<CollisionPolicy>,
 ) -> anyhow::Result<()> {
     send_file_with_progress(
         target_ip,
@@ -976,6 +5826,9 @@ pub async fn send_file(
         None,
         None,
         batch_id,
+        collision_hint,
+        None,
+        None,
     ).await
 }
 
@@ -992,6 +5845,48 @@ pub async fn send_file_with_progress(
     overall_sent: Option<std::sync::Arc<TokioMutex<u64>>>,
     overall_total: Option<u64>,
     batch_id: Option<String>,
+    collision_hint: Option<CollisionPolicy>,
+    priority: Option<i32>,
+    encryption_pin: Option<String>,
+) -> anyhow::Result<()> {
+    send_file_over(
+        &mut None,
+        false,
+        target_ip,
+        target_port,
+        path,
+        app_handle,
+        file_index,
+        total_files,
+        file_name,
+        overall_sent,
+        overall_total,
+        batch_id,
+        collision_hint,
+        priority,
+        encryption_pin,
+    ).await
+}
+
+/// Body of send_file_with_progress. Connects unless `conn` already holds a connection to the peer;
+/// with `keep_alive` the offer announces another file and the connection is left open in `conn`.
+#[allow(clippy::too_many_arguments)]
+async fn send_file_over(
+    conn: &mut Option<TcpStream>,
+    keep_alive: bool,
+    target_ip: String,
+    target_port: u16,
+    path: PathBuf,
+    app_handle: tauri::AppHandle,
+    file_index: Option<usize>,
+    total_files: Option<usize>,
+    file_name: Option<String>,
+    overall_sent: Option<std::sync::Arc<TokioMutex<u64>>>,
+    overall_total: Option<u64>,
+    batch_id: Option<String>,
+    collision_hint: Option<CollisionPolicy>,
+    priority: Option<i32>,
+    encryption_pin: Option<String>,
 ) -> anyhow::Result<()> {
     let overall_start = Instant::now();
     let default_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
@@ -1002,6 +5897,13 @@ pub async fn send_file_with_progress(
         String::new()
     };
     
+    if let Some(ref id) = batch_id {
+        if is_batch_cancelled(id).await {
+            info!("[SEND] Skipping {:?}, batch {} was cancelled", path, id);
+            record_unsent_cancelled(&app_handle, &target_ip, std::slice::from_ref(&path)).await;
+            return Err(TransferAborted("Batch cancelled".to_string()).into());
+        }
+    }
     info!("Starting file send to {}:{} with path {:?}{}", target_ip, target_port, path, file_info);
     tauri_log(&app_handle, "info", format!("send start | ip={} port={} path={} file={}{}", target_ip, target_port, path.display(), display_name, file_info)).await;
     let metadata = fs::metadata(&path).await?;
@@ -1009,16 +5911,41 @@ pub async fn send_file_with_progress(
     let actual_file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
     let mime = mime_guess::from_path(&path).first_or_octet_stream().to_string();
     let transfer_id = Uuid::new_v4().to_string();
+    // Lets the receiver verify the file and skip it if it already has it
+    let sha256 = match cached_sha256(&path).await {
+        Ok(h) => Some(h),
+        Err(e) => {
+            warn!("Failed to hash {:?}, sending without checksum: {}", path, e);
+            None
+        }
+    };
+    let compression = compression_for_peer(&app_handle, &target_ip, encryption_pin.is_some()).await;
     // Costruisci FileOffer e assicurati che batch_id sia sempre valorizzato (mai null nel JSON)
-    let offer = FileOffer {
+    let mut offer = FileOffer {
         transfer_id: transfer_id.clone(),
-        file_name: actual_file_name.clone(),
-        file_size,
-        mime,
         batch_id: batch_id.clone(),
-        sha256: None,
-        sender_mac: get_local_mac(),
+        sha256,
+        collision_hint,
+        keep_alive,
+        compression: compression.map(str::to_string),
+        // Streamed as frames, so the compressed size is never known up front
+        compressed_size: None,
+        encryption: encryption_pin.as_ref().map(|_| ENCRYPTION_AES_GCM.to_string()),
+        encryption_salt: encryption_pin.as_ref().map(|_| Uuid::new_v4().simple().to_string()),
+        encryption_nonce: encryption_pin.as_ref().map(|_| Uuid::new_v4().simple().to_string()[..16].to_string()),
+        file_index,
+        total_files,
+        ..FileOffer::new(actual_file_name.clone(), file_size, mime)
+    };
+    let body_size = offer.body_size();
+    let wire_size = offer.wire_size();
+    let mut body_cipher = match encryption_pin {
+        Some(ref pin) => Some(BodyCipher::for_offer(&offer, pin).await?),
+        None => None,
     };
+    if let Some(ref c) = body_cipher {
+        offer.sha256 = offer.sha256.as_deref().map(|h| c.keyed_digest(h));
+    }
 
     // Log esplicito con il JSON completo dell'oggetto FileOffer
     let offer_json = serde_json::to_string(&offer).unwrap_or_else(|_| "<error serializing offer>".to_string());
@@ -1039,44 +5966,65 @@ pub async fn send_file_with_progress(
         tauri_log(&app_handle, "warn", format!("[SEND] ⚠️ Nessun batch_id fornito per il trasferimento {}", transfer_id)).await;
     }
     let addr = format!("{}:{}", target_ip, target_port);
-    info!("Connecting to target address: {}", addr);
-    tauri_log(&app_handle, "info", format!("Connecting to {}", addr)).await;
-    
-    // Log delle interfacce locali per debug
-    if let Ok(addrs) = get_if_addrs::get_if_addrs() {
-        info!("Local network interfaces:");
-        for iface in addrs {
-            if !iface.is_loopback() {
-                info!("  - {}: {}", iface.name, iface.ip());
-            }
-        }
-    }
-    
-    let mut stream = match TcpStream::connect(&addr).await {
-        Ok(s) => {
-            info!("Successfully connected to {}", addr);
-            s
+    let mut crumbs = Breadcrumbs::new(&transfer_id, "send", &addr, &actual_file_name);
+    // Held until this send finishes; waits here while all send slots are busy
+    let Some(_slot) = acquire_send_slot(&app_handle, &target_ip, &transfer_id, batch_id.as_deref(), &actual_file_name, priority.unwrap_or(0)).await else {
+        info!("[SEND] {} dropped from the queue, batch cancelled", transfer_id);
+        record_unsent_cancelled(&app_handle, &target_ip, std::slice::from_ref(&path)).await;
+        return Err(TransferAborted("Batch cancelled".to_string()).into());
+    };
+    crumbs.add("send slot acquired");
+    let _batch_conn = batch_id.as_deref().map(BatchConnection::open);
+    let stream = match conn {
+        Some(stream) => {
+            crumbs.add("reusing kept-alive connection");
+            stream
         }
-        Err(e) => {
-            error!("Failed to connect to target {}: {}", addr, e);
-            tauri_log(&app_handle, "error", format!("Failed to connect to {}: {}", addr, e)).await;
-            let _ = add_recent_transfer(
-                app_handle.clone(),
-                actual_file_name.clone(),
-                file_size,
-                TransferType::Sent,
-                target_ip.clone(),
-                target_ip.clone(),
-                overall_start.elapsed().as_millis(),
-                TransferStatus::Failed,
-            ).await;
-            return Err(e.into());
+        None => {
+            info!("Connecting to target address: {}", addr);
+            tauri_log(&app_handle, "info", format!("Connecting to {}", addr)).await;
+
+            // Log delle interfacce locali per debug
+            if let Ok(addrs) = get_if_addrs::get_if_addrs() {
+                info!("Local network interfaces:");
+                for iface in addrs {
+                    if !iface.is_loopback() {
+                        info!("  - {}: {}", iface.name, iface.ip());
+                    }
+                }
+            }
+
+            let connected = match connect_file_server(&target_ip, target_port).await {
+                Ok(s) => {
+                    info!("Successfully connected to {}", addr);
+                    crumbs.add("connected");
+                    s
+                }
+                Err(e) => {
+                    error!("Failed to connect to target {}: {}", addr, e);
+                    tauri_log(&app_handle, "error", format!("Failed to connect to {}: {}", addr, e)).await;
+                    crumbs.fail(format!("connect failed: {}", e)).await;
+                    let _ = add_recent_transfer(
+                        app_handle.clone(),
+                        actual_file_name.clone(),
+                        file_size,
+                        TransferType::Sent,
+                        target_ip.clone(),
+                        target_ip.clone(),
+                        overall_start.elapsed().as_millis(),
+                        TransferStatus::Failed,
+                        None,
+                    ).await;
+                    return Err(e.into());
+                }
+            };
+            if let Err(e) = connected.set_nodelay(true) {
+                warn!("Failed to set TCP_NODELAY on client socket to {}: {}", addr, e);
+                tauri_log(&app_handle, "warn", format!("Failed to set TCP_NODELAY on {}: {}", addr, e)).await;
+            }
+            conn.insert(connected)
         }
     };
-    if let Err(e) = stream.set_nodelay(true) {
-        warn!("Failed to set TCP_NODELAY on client socket to {}: {}", addr, e);
-        tauri_log(&app_handle, "warn", format!("Failed to set TCP_NODELAY on {}: {}", addr, e)).await;
-    }
 
     // Send header JSON + newline
     let header_line = serde_json::to_string(&offer)? + "\n";
@@ -1084,6 +6032,7 @@ pub async fn send_file_with_progress(
     if let Err(e) = stream.write_all(header_line.as_bytes()).await {
         error!("Failed to send header: {}", e);
         tauri_log(&app_handle, "error", format!("Failed to send header to {}: {}", addr, e)).await;
+        crumbs.fail(format!("failed to send header: {}", e)).await;
         let _ = add_recent_transfer(
             app_handle.clone(),
             actual_file_name.clone(),
@@ -1093,6 +6042,7 @@ pub async fn send_file_with_progress(
             target_ip.clone(),
             overall_start.elapsed().as_millis(),
             TransferStatus::Failed,
+            None,
         ).await;
         return Err(e.into());
     }
@@ -1101,17 +6051,18 @@ pub async fn send_file_with_progress(
         tauri_log(&app_handle, "warn", format!("Flush after sending header failed for {}: {}", addr, e)).await;
     } else {
         info!("Header sent and flushed.");
+        crumbs.add("handshake sent");
         tauri_log(&app_handle, "info", "Header sent and flushed.").await;
     }
 
     // Await ack line strictly before sending any binary
     info!("Waiting for ack line from server...");
-    let mut ack_buf = Vec::new();
-    loop {
-        let mut byte = [0u8; 1];
-        if let Err(e) = stream.read_exact(&mut byte).await {
-            error!("Failed to read ack byte: {}", e);
+    let ack_json = match read_json_line(stream).await {
+        Ok(val) => val,
+        Err(e) => {
+            error!("Failed to read ack: {}", e);
             tauri_log(&app_handle, "error", format!("Failed to read ack from {}: {}", addr, e)).await;
+            crumbs.fail(format!("failed to read ack: {}", e)).await;
             let _ = add_recent_transfer(
                 app_handle.clone(),
                 actual_file_name.clone(),
@@ -1121,37 +6072,23 @@ pub async fn send_file_with_progress(
                 target_ip.clone(),
                 overall_start.elapsed().as_millis(),
                 TransferStatus::Failed,
+                None,
             ).await;
-            return Err(e.into());
-        }
-        if byte[0] == b'\n' {
-            break;
-        }
-        ack_buf.push(byte[0]);
-        if ack_buf.len() > 8 * 1024 {
-            error!("Ack too large (>8KiB) without newline");
-            anyhow::bail!("Ack too large");
-        }
-    }
-    let ack_str = match String::from_utf8(ack_buf) {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Invalid ack utf8: {}", e);
-            tauri_log(&app_handle, "error", format!("Invalid ack utf8 from {}: {}", addr, e)).await;
-            return Err(e.into());
+            return Err(e);
         }
     };
-    info!("Received ack line: {}", ack_str);
-    tauri_log(&app_handle, "info", format!("Received ack from {}: {}", addr, ack_str)).await;
-    let ack_json: serde_json::Value = match serde_json::from_str(&ack_str) {
-        Ok(val) => val,
-        Err(e) => {
-            error!("Invalid ack JSON: {}", e);
-            tauri_log(&app_handle, "error", format!("Invalid ack JSON from {}: {}", addr, e)).await;
-            return Err(e.into());
+    info!("Received ack line: {}", ack_json);
+    tauri_log(&app_handle, "info", format!("Received ack from {}: {}", addr, ack_json)).await;
+    let accepted = ack_json.get("accept").and_then(|v| v.as_bool()).unwrap_or(false);
+    crumbs.add(format!("ack received (accept={})", accepted));
+    // Profiles are keyed by the peer's device id, or its IP for old peers
+    let peer_key = match ack_json.get("device_uuid").and_then(|v| v.as_str()) {
+        Some(peer_uuid) => {
+            crumbs.add(format!("peer device id {}", peer_uuid));
+            peer_uuid.to_string()
         }
+        None => target_ip.clone(),
     };
-    let accepted = ack_json.get("accept").and_then(|v| v.as_bool()).unwrap_or(false);
     if !accepted {
         let err_msg = ack_json.get("error").and_then(|v| v.as_str()).unwrap_or("rejected");
         error!("Transfer rejected by peer: {}", err_msg);
@@ -1165,17 +6102,56 @@ pub async fn send_file_with_progress(
             target_ip.clone(),
             overall_start.elapsed().as_millis(),
             TransferStatus::Cancelled,
+            None,
+        ).await;
+        // The receiver keeps a kept-alive connection open past these, for the rest of the batch
+        if keep_alive && matches!(err_msg, "file_exists" | "exceeds_user_limit") {
+            return Err(FileSkipped(err_msg.to_string()).into());
+        }
+        return Err(TransferAborted(format!("Transfer rejected by peer: {}", err_msg)).into());
+    }
+    emit_transfer_accepted(&app_handle, &transfer_id, "send", &target_ip, ack_json.get("device_uuid").and_then(|v| v.as_str()), &actual_file_name, file_size);
+    let lifecycle = FileLifecycle::start(&app_handle, &transfer_id, "send", batch_id.as_deref(), display_name, file_index, total_files);
+    if ack_json.get("already_have").and_then(|v| v.as_bool()).unwrap_or(false) {
+        info!("Peer already has {} (transfer_id={}), skipping body", actual_file_name, transfer_id);
+        tauri_log(&app_handle, "info", format!("send deduped | id={} ip={} file={}", transfer_id, target_ip, actual_file_name)).await;
+        if let Some(ref overall_sent) = overall_sent {
+            *overall_sent.lock().await += file_size;
+        }
+        if !keep_alive {
+            let _ = AsyncWriteExt::shutdown(stream).await;
+        }
+        let _ = app_handle.emit("transfer_complete", serde_json::json!({
+            "transfer_id": transfer_id,
+            "path": path,
+            "ip": target_ip,
+            "port": target_port,
+            "direction": "send",
+            "deduped": true
+        }));
+        let _ = record_transfer(
+            app_handle.clone(),
+            actual_file_name.clone(),
+            file_size,
+            TransferType::Sent,
+            target_ip.clone(),
+            overall_start.elapsed().as_millis(),
+            TransferStatus::Completed,
+            RecordExtras { verified: Some(true), delivery_confirmed: true, peer_uuid: ack_json.get("device_uuid").and_then(|v| v.as_str()).map(str::to_string) },
         ).await;
-        anyhow::bail!("Transfer rejected by peer: {}", err_msg);
+        lifecycle.finish("completed");
+        return Ok(());
     }
     info!("Ack accepted by server. Beginning binary transfer of {} bytes (transfer_id={})", file_size, transfer_id);
     tauri_log(&app_handle, "info", format!("Ack accepted | id={} size={}", transfer_id, file_size)).await;
 
     // Send file in chunks
+    let mut encoder = compression.map(BodyEncoder::new).transpose()?;
     let mut file = match fs::File::open(&path).await {
         Ok(f) => f,
         Err(e) => {
             error!("Failed to open file: {}", e);
+            crumbs.fail(format!("failed to open file: {}", e)).await;
             let _ = add_recent_transfer(
                 app_handle.clone(),
                 actual_file_name.clone(),
@@ -1185,17 +6161,31 @@ pub async fn send_file_with_progress(
                 target_ip.clone(),
                 overall_start.elapsed().as_millis(),
                 TransferStatus::Failed,
+                None,
             ).await;
             return Err(e.into());
         }
     };
+    let active = ActiveTransferGuard::register(&transfer_id, target_ip.clone(), "send", &actual_file_name, wire_size);
     let mut sent: u64 = 0;
-    let mut buffer = vec![0u8; 64 * 1024];
+    let mut body_read: u64 = 0;
+    let mut next_milestone: u64 = 25;
+    let send_buffer = send_buffer_size_for(&peer_key).await;
+    crumbs.add(format!("send buffer {} bytes", send_buffer));
+    let mut buffer = vec![0u8; send_buffer];
     let mut last_log = Instant::now();
     let transfer_start = Instant::now();
-    while sent < file_size {
+    let speed_unit = read_settings().await.speed_unit;
+    sync_send_limit().await;
+    while body_read < body_size {
+        if !wait_if_paused(&transfer_id, batch_id.as_deref()).await {
+            CANCELLED_SEND_IDS.lock().await.insert(transfer_id.clone());
+        }
         // Check if transfer was cancelled
-        if is_send_cancelled(&target_ip, target_port).await {
+        if is_send_cancelled(&target_ip, target_port).await
+            || is_batch_cancelled(batch_id.as_deref().unwrap_or_default()).await
+            || CANCELLED_SEND_IDS.lock().await.remove(&transfer_id)
+        {
             error!("({addr}) Send transfer was cancelled by user");
             tauri_log(&app_handle, "warn", format!("Send transfer to {} was cancelled", addr)).await;
             let _ = add_recent_transfer(
@@ -1207,18 +6197,21 @@ pub async fn send_file_with_progress(
                 target_ip.clone(),
                 overall_start.elapsed().as_millis(),
                 TransferStatus::Cancelled,
+                None,
             ).await;
             // Cleanup cancelled state
             let mut cancelled = CANCELLED_TRANSFERS.lock().await;
             cancelled.remove(&format!("{}:{}", target_ip, target_port));
-            return Err(anyhow::anyhow!("Transfer cancelled by user"));
+            lifecycle.finish("cancelled");
+            return Err(TransferAborted("Transfer cancelled by user".to_string()).into());
         }
 
-        let to_read = std::cmp::min(buffer.len() as u64, file_size - sent) as usize;
+        let to_read = std::cmp::min(buffer.len() as u64, body_size - body_read) as usize;
         let n = match file.read(&mut buffer[..to_read]).await {
             Ok(n) => n,
             Err(e) => {
                 error!("File read error: {}", e);
+                crumbs.fail(format!("file read error: {}", e)).await;
                 let _ = add_recent_transfer(
                     app_handle.clone(),
                     actual_file_name.clone(),
@@ -1228,14 +6221,30 @@ pub async fn send_file_with_progress(
                     target_ip.clone(),
                     overall_start.elapsed().as_millis(),
                     TransferStatus::Failed,
+                    None,
                 ).await;
                 return Err(e.into());
             }
         };
         if n == 0 { break; }
-        if let Err(e) = stream.write_all(&buffer[..n]).await {
+        body_read += n as u64;
+        let sealed;
+        let framed;
+        let out = match (body_cipher.as_mut(), encoder.as_mut()) {
+            (Some(c), _) => {
+                sealed = c.seal(&buffer[..n])?;
+                &sealed[..]
+            }
+            (None, Some(e)) => {
+                framed = e.feed(&buffer[..n])?;
+                &framed[..]
+            }
+            (None, None) => &buffer[..n],
+        };
+        if let Err(e) = stream.write_all(out).await {
             error!("Failed to send file chunk at {} bytes: {}", sent, e);
             tauri_log(&app_handle, "error", format!("Failed to send chunk at {} to {}: {}", sent, addr, e)).await;
+            crumbs.fail(format!("failed to send chunk at {} bytes: {}", sent, e)).await;
             let _ = add_recent_transfer(
                 app_handle.clone(),
                 actual_file_name.clone(),
@@ -1245,26 +6254,33 @@ pub async fn send_file_with_progress(
                 target_ip.clone(),
                 overall_start.elapsed().as_millis(),
                 TransferStatus::Failed,
+                None,
             ).await;
             return Err(e.into());
         }
-        sent += n as u64;
+        // A streamed body's progress is measured in file bytes, like its `total`
+        sent += if encoder.is_some() { n as u64 } else { out.len() as u64 };
+        // Not writing lets TCP flow control hold the bytes back, same as on the receive side
+        throttle_send(out.len() as u64).await;
+        active.update(sent);
+        crumbs.progress(&mut next_milestone, sent, wire_size);
 
-        let progress_percentage = (sent as f64 / file_size as f64) * 100.0;
+        let progress_percentage = percent_of(sent, wire_size);
         let _ = app_handle.emit("file_progress", progress_percentage);
 
         // Calcola ETA per il progresso
         let elapsed_ms = transfer_start.elapsed().as_millis();
-        let (eta_ms, eta_formatted) = calculate_eta(sent, file_size, elapsed_ms);
+        let (eta_ms, eta_formatted) = calculate_eta(sent, wire_size, elapsed_ms);
+        let speed = speed_mb_per_sec(sent, elapsed_ms);
 
         // --- OVERALL PROGRESS SUPPORT ---
         if let (Some(overall_sent), Some(overall_total)) = (&overall_sent, overall_total) {
             let mut global = overall_sent.lock().await;
             *global += n as u64;
-            let overall_percent = (*global as f64 / overall_total as f64) * 100.0;
+            let overall_percent = percent_of(*global, overall_total);
             // Calcolo ETA generale
             let elapsed_ms = transfer_start.elapsed().as_millis();
-            let bytes_remaining = overall_total - *global;
+            let bytes_remaining = overall_total.saturating_sub(*global);
             let bytes_per_ms = if elapsed_ms > 0 {
                 *global as f64 / elapsed_ms as f64
             } else {
@@ -1292,8 +6308,12 @@ pub async fn send_file_with_progress(
             let progress = serde_json::json!({
                 "transfer_id": transfer_id,
                 "sent": sent,
-                "total": file_size,
+                "total": wire_size,
                 "percent": progress_percentage,
+                "batch_id": batch_id,
+                "file_name": display_name,
+                "file_index": file_index,
+                "total_files": total_files,
                 "overall_sent": *global,
                 "overall_total": overall_total,
                 "overall_percent": overall_percent,
@@ -1303,33 +6323,37 @@ pub async fn send_file_with_progress(
                 "eta_ms": eta_ms,
                 "eta_formatted": eta_formatted,
                 "overall_eta_ms": overall_eta_ms,
-                "overall_eta_formatted": overall_eta_formatted
+                "overall_eta_formatted": overall_eta_formatted,
+                "speed": speed,
+                "speed_formatted": format_speed(speed, speed_unit)
             });
             let _ = app_handle.emit("transfer_progress", progress);
         } else {
             let progress = serde_json::json!({
                 "transfer_id": transfer_id,
                 "sent": sent,
-                "total": file_size,
+                "total": wire_size,
                 "percent": progress_percentage,
                 "ip": target_ip,
                 "port": target_port,
                 "direction": "send",
                 "eta_ms": eta_ms,
-                "eta_formatted": eta_formatted
+                "eta_formatted": eta_formatted,
+                "speed": speed,
+                "speed_formatted": format_speed(speed, speed_unit)
             });
             let _ = app_handle.emit("transfer_progress", progress);
         }
         // --- END OVERALL PROGRESS SUPPORT ---
-        info!("Sent {} / {} bytes", sent, file_size);
+        info!("Sent {} / {} bytes", sent, wire_size);
 
         // Log solo per il progresso generale, non per ogni file
         if let (Some(overall_sent), Some(overall_total)) = (&overall_sent, overall_total) {
             if last_log.elapsed().as_secs_f64() >= 1.0 {
                 let global = overall_sent.lock().await;
-                let overall_percent = (*global as f64 / overall_total as f64) * 100.0;
+                let overall_percent = percent_of(*global, overall_total);
                 let elapsed_ms = transfer_start.elapsed().as_millis();
-                let bytes_remaining = overall_total - *global;
+                let bytes_remaining = overall_total.saturating_sub(*global);
                 let bytes_per_ms = if elapsed_ms > 0 {
                     *global as f64 / elapsed_ms as f64
                 } else {
@@ -1386,6 +6410,30 @@ pub async fn send_file_with_progress(
             }
         }
     } // END OF WHILE LOOP FOR SENDING FILE
+    if let Some(e) = encoder.take() {
+        let tail = e.finish()?;
+        if let Err(e) = stream.write_all(&tail).await {
+            error!("Failed to send the end of the compressed body: {}", e);
+            crumbs.fail(format!("failed to send compressed tail: {}", e)).await;
+            let _ = add_recent_transfer(
+                app_handle.clone(),
+                actual_file_name.clone(),
+                file_size,
+                TransferType::Sent,
+                target_ip.clone(),
+                target_ip.clone(),
+                overall_start.elapsed().as_millis(),
+                TransferStatus::Failed,
+                None,
+            ).await;
+            return Err(e.into());
+        }
+        throttle_send(tail.len() as u64).await;
+    }
+    // The batch total counts uncompressed bytes; credit what compression saved
+    if let Some(ref overall_sent) = overall_sent {
+        *overall_sent.lock().await += file_size - body_size;
+    }
 
     if let Err(e) = stream.flush().await {
         warn!("Flush after sending file failed: {}", e);
@@ -1395,8 +6443,11 @@ pub async fn send_file_with_progress(
         tauri_log(&app_handle, "info", "File data flushed to socket.").await;
     }
 
-    // gracefully close the write half to signal EOF to the server
-    if let Err(e) = AsyncWriteExt::shutdown(&mut stream).await {
+    // gracefully close the write half to signal EOF to the server; a kept-alive connection
+    // stays open for the next offer and the receipt arrives on it all the same
+    if keep_alive {
+        info!("Keeping connection to {} open for the next file", addr);
+    } else if let Err(e) = AsyncWriteExt::shutdown(stream).await {
         warn!("Socket shutdown after send failed: {}", e);
         tauri_log(&app_handle, "warn", format!("Socket shutdown after send failed for {}: {}", addr, e)).await;
     } else {
@@ -1404,12 +6455,35 @@ pub async fn send_file_with_progress(
         tauri_log(&app_handle, "info", "Write half shutdown completed.").await;
     }
 
+    let delivery_confirmed = match timeout(RECEIPT_TIMEOUT, read_receipt(stream)).await {
+        Ok(Some(receipt)) => {
+            let matches = receipt.transfer_id == transfer_id
+                && receipt.received == file_size
+                && offer.sha256.as_ref().is_some_and(|h| h.eq_ignore_ascii_case(&receipt.sha256));
+            if !matches {
+                warn!("Receipt for {} does not match what was sent: {:?}", transfer_id, receipt);
+                tauri_log(&app_handle, "warn", format!("Delivery receipt mismatch for {}", transfer_id)).await;
+            }
+            crumbs.add(format!("receipt received (match={})", matches));
+            matches
+        }
+        Ok(None) => {
+            info!("No delivery receipt from {} (older peer?)", addr);
+            false
+        }
+        Err(_) => {
+            warn!("Timed out waiting for delivery receipt from {}", addr);
+            false
+        }
+    };
+
     let _ = app_handle.emit("transfer_complete", serde_json::json!({
         "transfer_id": transfer_id,
         "path": path,
         "ip": target_ip,
         "port": target_port,
-        "direction": "send"
+        "direction": "send",
+        "delivery_confirmed": delivery_confirmed
     }));
     info!("Invio del file completato: {:?}", path);
     info!("Target: {}:{}, Local addr: {}", target_ip, target_port, addr);
@@ -1438,18 +6512,21 @@ pub async fn send_file_with_progress(
             batch_info
         )
     ).await;
+    update_peer_profile(&peer_key, file_size, transfer_start.elapsed().as_millis()).await;
+
     // Salvataggio record completato (invio)
     let elapsed_ms = overall_start.elapsed().as_millis();
-    let _ = add_recent_transfer(
+    let _ = record_transfer(
         app_handle.clone(),
         actual_file_name.clone(),
         file_size,
         TransferType::Sent,
         target_ip.clone(),
-        target_ip.clone(),
         elapsed_ms,
         TransferStatus::Completed,
+        RecordExtras { verified: None, delivery_confirmed, peer_uuid: ack_json.get("device_uuid").and_then(|v| v.as_str()).map(str::to_string) },
     ).await;
+    lifecycle.finish("completed");
 
     Ok(())
 } // END OF send_file_with_progress FUNCTION
@@ -1486,9 +6563,14 @@ pub async fn get_system_stats() -> Result<serde_json::Value, String> {
 }
 
 use chrono::Local;
+// helper: una data dei record (rfc3339), None se non valida
+fn parse_record_time(s: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::DateTime::parse_from_rfc3339(s).ok()
+}
+
 // helper: controlla se una data (rfc3339) è "oggi"
 fn datetime_is_today(s: &str) -> bool {
-    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+    if let Some(dt) = parse_record_time(s) {
         // Converti a timezone locale
         let dt_local = dt.with_timezone(&Local).date_naive();
         let today = Local::now().date_naive();
@@ -1551,15 +6633,76 @@ pub async fn get_today_stats(
         0.0
     };
 
+    let speed_unit = read_settings().await.speed_unit;
     Ok(serde_json::json!({
         "transfers_today": count,
-        "avg_speed": avg_speed
+        "avg_speed": avg_speed,
+        "avg_speed_formatted": format_speed(avg_speed, speed_unit),
+        "speed_unit": speed_unit
+    }))
+}
+
+fn average_speed(records: &[&TransferRecord]) -> f64 {
+    if records.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = records.iter().map(|r| r.speed).sum();
+    (sum / records.len() as f64 * 10.0).round() / 10.0
+}
+
+/// Completed-transfer stats over the trailing 7 (`"week"`) or 30 (`"month"`) local days, today
+/// included, with a per-day breakdown (oldest first) for charts.
+#[tauri::command]
+pub async fn get_stats_for_period(period: String) -> Result<serde_json::Value, String> {
+    let days: i64 = match period.as_str() {
+        "week" => 7,
+        "month" => 30,
+        other => return Err(format!("invalid period: {} (expected week or month)", other)),
+    };
+    let records = get_recent_transfers().await.unwrap_or_default();
+    let today = Local::now().date_naive();
+    let first_day = today - chrono::Duration::days(days - 1);
+    // Giorno locale di ogni trasferimento completato nel periodo
+    let completed: Vec<(chrono::NaiveDate, &TransferRecord)> = records.iter()
+        .filter(|r| matches!(r.status, TransferStatus::Completed))
+        .filter_map(|r| parse_record_time(&r.start_time).map(|t| (t.with_timezone(&Local).date_naive(), r)))
+        .filter(|(day, _)| *day >= first_day && *day <= today)
+        .collect();
+
+    let speed_unit = read_settings().await.speed_unit;
+    let per_day: Vec<serde_json::Value> = (0..days)
+        .map(|offset| {
+            let day = first_day + chrono::Duration::days(offset);
+            let on_day: Vec<&TransferRecord> = completed.iter().filter(|(d, _)| *d == day).map(|(_, r)| *r).collect();
+            serde_json::json!({
+                "date": day.format("%Y-%m-%d").to_string(),
+                "transfers": on_day.len(),
+                "total_bytes": on_day.iter().map(|r| r.file_size).sum::<u64>(),
+                "avg_speed": average_speed(&on_day),
+            })
+        })
+        .collect();
+    let all: Vec<&TransferRecord> = completed.iter().map(|(_, r)| *r).collect();
+    let avg_speed = average_speed(&all);
+    Ok(serde_json::json!({
+        "period": period,
+        "from": first_day.format("%Y-%m-%d").to_string(),
+        "to": today.format("%Y-%m-%d").to_string(),
+        "transfers": all.len(),
+        "total_bytes": all.iter().map(|r| r.file_size).sum::<u64>(),
+        "avg_speed": avg_speed,
+        "avg_speed_formatted": format_speed(avg_speed, speed_unit),
+        "speed_unit": speed_unit,
+        "days": per_day
     }))
 }
 
 #[tauri::command]
 pub async fn respond_transfer(args: RespondTransferArgs) {
     // Store user accept/deny decision so receiver loop can continue
+    if let (true, Some(limit)) = (args.accept, args.max_accept_size) {
+        USER_SIZE_LIMITS.lock().await.insert(args.transfer_id.clone(), limit);
+    }
     {
         let mut map = TRANSFER_RESPONSES.lock().await;
         map.insert(args.transfer_id.clone(), args.accept);
@@ -1592,16 +6735,212 @@ pub async fn cancel_transfer_send(target_ip: String, target_port: u16) -> Result
     let key = format!("{}:{}", target_ip, target_port);
     let mut cancelled = CANCELLED_TRANSFERS.lock().await;
     cancelled.insert(key);
+    let paused_sends: Vec<String> = ACTIVE_TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, t)| t.direction == "send" && t.peer == target_ip)
+        .map(|(id, _)| id.clone())
+        .collect();
+    for id in paused_sends {
+        release_pause(&id);
+    }
     Ok(())
 }
 
 #[tauri::command]
 pub async fn cancel_transfer_receive(transfer_id: String) -> Result<(), String> {
+    release_pause(&transfer_id);
     let mut cancelled = CANCELLED_RECEIVE.lock().await;
     cancelled.insert(transfer_id);
     Ok(())
 }
 
+// --- Pause / resume ---
+// Paused transfers by id. The send/receive loops wait on the Notify between chunks, so while
+// paused the socket stays open but nothing is read or written and no progress is emitted.
+static PAUSED_TRANSFERS: Lazy<std::sync::Mutex<HashMap<String, std::sync::Arc<tokio::sync::Notify>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+// How often a paused loop re-checks its batch for cancellation
+const PAUSE_POLL: Duration = Duration::from_secs(1);
+
+#[tauri::command]
+pub fn pause_transfer(app_handle: AppHandle, transfer_id: String) -> Result<(), String> {
+    if !ACTIVE_TRANSFERS.lock().unwrap().contains_key(&transfer_id) {
+        return Err(format!("no active transfer {}", transfer_id));
+    }
+    PAUSED_TRANSFERS.lock().unwrap().entry(transfer_id.clone()).or_default();
+    info!("Transfer {} paused", transfer_id);
+    let _ = app_handle.emit("transfer_paused", serde_json::json!({ "transfer_id": transfer_id }));
+    Ok(())
+}
+
+#[tauri::command]
+pub fn resume_transfer(app_handle: AppHandle, transfer_id: String) -> Result<(), String> {
+    if !release_pause(&transfer_id) {
+        return Err(format!("transfer {} is not paused", transfer_id));
+    }
+    info!("Transfer {} resumed", transfer_id);
+    let _ = app_handle.emit("transfer_resumed", serde_json::json!({ "transfer_id": transfer_id }));
+    Ok(())
+}
+
+/// Unpause and wake the loop; false when the transfer wasn't paused.
+fn release_pause(transfer_id: &str) -> bool {
+    match PAUSED_TRANSFERS.lock().unwrap().remove(transfer_id) {
+        Some(notify) => {
+            // A stored permit covers a loop that hasn't started waiting yet
+            notify.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Block the calling transfer loop while it is paused. False once it has been paused for longer
+/// than the pause timeout; the caller then cancels it. A cancelled batch ends the wait early.
+async fn wait_if_paused(transfer_id: &str, batch_id: Option<&str>) -> bool {
+    let Some(notify) = PAUSED_TRANSFERS.lock().unwrap().get(transfer_id).cloned() else {
+        return true;
+    };
+    let limit = read_settings().await.pause_timeout_secs.unwrap_or(DEFAULT_PAUSE_TIMEOUT_SECS);
+    let paused_at = Instant::now();
+    loop {
+        if timeout(PAUSE_POLL, notify.notified()).await.is_ok() || !PAUSED_TRANSFERS.lock().unwrap().contains_key(transfer_id) {
+            return true;
+        }
+        if let Some(batch_id) = batch_id {
+            if is_batch_cancelled(batch_id).await {
+                release_pause(transfer_id);
+                return true;
+            }
+        }
+        if limit > 0 && paused_at.elapsed() >= Duration::from_secs(limit) {
+            release_pause(transfer_id);
+            warn!("Transfer {} paused for over {}s, cancelling it", transfer_id, limit);
+            return false;
+        }
+    }
+}
+
+// Batches cancelled as a whole, on either side of the transfer, with when the last of their
+// connections closed (None while one is still open). Kept CANCELLED_BATCH_GRACE_SECS past that so
+// a sender reconnecting for its next file is still refused, then forgotten.
+static CANCELLED_BATCHES: Lazy<std::sync::Mutex<HashMap<String, Option<Instant>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+const CANCELLED_BATCH_GRACE_SECS: u64 = 30;
+// Open send and receive connections per batch
+static BATCH_CONNECTIONS: Lazy<std::sync::Mutex<HashMap<String, usize>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Counts a connection towards its batch while held; dropping the batch's last one starts the
+/// grace period of a cancelled batch.
+struct BatchConnection(String);
+
+impl BatchConnection {
+    fn open(batch_id: &str) -> Self {
+        *BATCH_CONNECTIONS.lock().unwrap().entry(batch_id.to_string()).or_insert(0) += 1;
+        BatchConnection(batch_id.to_string())
+    }
+}
+
+impl Drop for BatchConnection {
+    fn drop(&mut self) {
+        let last = {
+            let mut conns = BATCH_CONNECTIONS.lock().unwrap();
+            let Some(open) = conns.get_mut(&self.0) else { return; };
+            *open -= 1;
+            let last = *open == 0;
+            if last {
+                conns.remove(&self.0);
+            }
+            last
+        };
+        if last {
+            if let Some(closed) = CANCELLED_BATCHES.lock().unwrap().get_mut(&self.0) {
+                *closed = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// Cancel every file of a batch: in-flight files stop and remove their partial data, queued
+/// sends are dropped, later offers of the batch are refused, and `batch_cancelled` is emitted.
+#[tauri::command]
+pub async fn cancel_batch(app_handle: AppHandle, batch_id: String) -> Result<(), String> {
+    if batch_id.is_empty() {
+        return Err("batch_id is empty".to_string());
+    }
+    let open = BATCH_CONNECTIONS.lock().unwrap().contains_key(&batch_id);
+    CANCELLED_BATCHES.lock().unwrap().insert(batch_id.clone(), (!open).then(Instant::now));
+    BATCH_RESPONSES.lock().await.remove(&batch_id);
+    USER_SIZE_LIMITS.lock().await.remove(&batch_id);
+    let dropped = {
+        let mut sched = SEND_SCHEDULER.lock().unwrap();
+        let before = sched.waiting.len();
+        sched.waiting.retain(|q| q.batch_id.as_deref() != Some(batch_id.as_str()));
+        before - sched.waiting.len()
+    };
+    info!("[BATCH] {} cancelled, {} queued sends dropped", batch_id, dropped);
+    let _ = app_handle.emit("batch_cancelled", serde_json::json!({
+        "batch_id": batch_id,
+        "dropped_queued": dropped,
+    }));
+    Ok(())
+}
+
+// Sends stopped by cancel_all_transfers; the send loop removes its id when it stops
+static CANCELLED_SEND_IDS: Lazy<TokioMutex<std::collections::HashSet<String>>> =
+    Lazy::new(|| TokioMutex::new(std::collections::HashSet::new()));
+
+/// Stop everything: every active send and receive is cancelled (their loops remove partial files),
+/// queued sends are dropped, and `all_transfers_cancelled` reports what was stopped.
+#[tauri::command]
+pub async fn cancel_all_transfers(app_handle: AppHandle) -> Result<(), String> {
+    // Snapshot first: the loops take these locks too, so none is held while taking another
+    let active: Vec<(String, &'static str)> = ACTIVE_TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, t)| (id.clone(), t.direction))
+        .collect();
+    let dropped_queued: Vec<String> = {
+        let mut sched = SEND_SCHEDULER.lock().unwrap();
+        sched.waiting.drain(..).map(|q| q.transfer_id).collect()
+    };
+    let (sends, receives): (Vec<_>, Vec<_>) = active.into_iter().partition(|(_, dir)| *dir == "send");
+    let sends: Vec<String> = sends.into_iter().map(|(id, _)| id).collect();
+    let receives: Vec<String> = receives.into_iter().map(|(id, _)| id).collect();
+    CANCELLED_SEND_IDS.lock().await.extend(sends.iter().cloned());
+    CANCELLED_RECEIVE.lock().await.extend(receives.iter().cloned());
+    for id in sends.iter().chain(receives.iter()) {
+        release_pause(id);
+    }
+    info!("Cancel all: {} sends, {} receives, {} queued", sends.len(), receives.len(), dropped_queued.len());
+    tauri_log(&app_handle, "warn", format!("Cancelled all transfers ({} sends, {} receives, {} queued)", sends.len(), receives.len(), dropped_queued.len())).await;
+    let _ = app_handle.emit("all_transfers_cancelled", serde_json::json!({
+        "sends": sends,
+        "receives": receives,
+        "dropped_queued": dropped_queued,
+    }));
+    Ok(())
+}
+
+async fn is_batch_cancelled(batch_id: &str) -> bool {
+    let mut cancelled = CANCELLED_BATCHES.lock().unwrap();
+    cancelled.retain(|_, closed| closed.map_or(true, |at| at.elapsed() < Duration::from_secs(CANCELLED_BATCH_GRACE_SECS)));
+    cancelled.contains_key(batch_id)
+}
+
+/// History entries for files of a stopped batch that never got to send, so the batch's files all show up.
+async fn record_unsent_cancelled(app_handle: &AppHandle, target_ip: &str, paths: &[PathBuf]) {
+    for path in paths {
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
+        let file_size = fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+        let _ = record_transfer(app_handle.clone(), file_name, file_size, TransferType::Sent, target_ip.to_string(), 0, TransferStatus::Cancelled, RecordExtras::default()).await;
+    }
+}
+
 // Helper function to check if a send transfer should be cancelled
 async fn is_send_cancelled(target_ip: &str, target_port: u16) -> bool {
     let key = format!("{}:{}", target_ip, target_port);
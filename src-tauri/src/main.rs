@@ -3,6 +3,9 @@
 use tauri::Manager;
 
 mod file_transfer;
+mod web_receive;
+mod diagnostics;
+mod mdns_discovery;
 use crate::file_transfer::{list_trusted_devices};
 
 use std::{
@@ -24,13 +27,80 @@ use mac_address::get_mac_address;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Device {
+    // Discovery magic, see DISCOVERY_APP
+    #[serde(default)]
+    app: Option<String>,
+    #[serde(default)]
+    proto: Option<u32>,
     name: String,
     ip: String,
+    // Routable IPv6 address when the sender has IPv6 on; transfers prefer it when we do too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ipv6: Option<String>,
     port: u16,
     status: String,
     last_seen: String,
     #[serde(default)]
     mac: Option<String>,
+    // Peer's AirShare version, lets the UI warn before sending to an incompatible peer
+    #[serde(default)]
+    app_version: Option<String>,
+    // Local annotation for get_devices, not meaningful on the wire
+    #[serde(default)]
+    is_favorite: bool,
+    // False when the device can't take transfers right now (e.g. low disk space); old peers omit it
+    #[serde(default = "default_true")]
+    accepting: bool,
+    // Optional protocol features (e.g. "folder-tar"); old peers advertise none
+    #[serde(default)]
+    capabilities: Vec<String>,
+    // Stable per-installation id; old peers omit it and are matched by IP/MAC
+    #[serde(default)]
+    device_uuid: Option<String>,
+    // Set on a refresh_discovery heartbeat: receivers answer with an immediate heartbeat
+    #[serde(default)]
+    probe: bool,
+    // Local annotation for get_devices from our monotonic clock; `last_seen` is the sender's wall clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_seen_secs_ago: Option<u64>,
+    // Local annotation for get_devices: last ping round trip, see latency_loop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    // HMAC of heartbeat_signing_payload with the sender's heartbeat key; checked by paired peers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sig: Option<String>,
+    // Local annotation: None for unpaired peers, false when a paired device id came with a bad signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature_valid: Option<bool>,
+}
+
+// The identity-bearing fields of a heartbeat, in a fixed order so both ends sign the same bytes
+fn heartbeat_signing_payload(dev: &Device) -> String {
+    format!(
+        "{}|{}|{}|{}|{}",
+        dev.device_uuid.as_deref().unwrap_or_default(),
+        dev.name,
+        dev.ip,
+        dev.mac.as_deref().unwrap_or_default(),
+        dev.last_seen
+    )
+}
+
+// Heartbeats carry this magic so unrelated JSON broadcasts on the port are dropped early.
+// Builds from before it sent neither field; their heartbeats count as protocol 0.
+const DISCOVERY_APP: &str = "airshare";
+const DISCOVERY_PROTO: u32 = 1;
+
+#[derive(Deserialize)]
+struct DiscoveryMagic {
+    #[serde(default)]
+    app: Option<String>,
+    #[serde(default)]
+    proto: Option<u32>,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Clone, Debug)]
@@ -42,20 +112,55 @@ struct DeviceEntry {
 type SharedDevices = Arc<Mutex<Vec<DeviceEntry>>>;
 
 const BROADCAST_PORT: u16 = 40123;
+// All-nodes link-local group; every IPv6 host is a member, so no join is needed to hear it
+const IPV6_DISCOVERY_GROUP: std::net::Ipv6Addr = std::net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
 const HEARTBEAT_INTERVAL_SECS: u64 = 2;
 const DEVICE_TIMEOUT_SECS: u64 = 5;
+// How often the heartbeat re-checks free disk space
+const ACCEPTING_CHECK_INTERVAL_SECS: u64 = 30;
+// Reachability check used by refresh_discovery
+const PROBE_TIMEOUT_MS: u64 = 800;
+// At most one probe-triggered heartbeat per interval, so probes can't cause a storm
+const PROBE_ANSWER_MIN_INTERVAL_MS: u64 = 1000;
+// How often latency_loop pings listed devices
+const LATENCY_INTERVAL_SECS: u64 = 15;
+// Backoff between attempts to rebind a failed discovery socket
+const LISTENER_REBIND_MIN_BACKOFF_MS: u64 = 1000;
+const LISTENER_REBIND_MAX_BACKOFF_MS: u64 = 30_000;
 
-fn get_local_ip() -> Option<String> {
-    if let Ok(addrs) = get_if_addrs() {
-        for iface in addrs {
-            if !iface.is_loopback() {
-                if let std::net::IpAddr::V4(ipv4) = iface.ip() {
-                    return Some(ipv4.to_string());
-                }
-            }
-        }
+// Wakes the heartbeat loop for an out-of-cycle heartbeat
+static HEARTBEAT_NOW: once_cell::sync::Lazy<tokio::sync::Notify> = once_cell::sync::Lazy::new(tokio::sync::Notify::new);
+static PROBE_REQUESTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+// Port the IPv4 discovery listener currently holds; 0 while it isn't bound
+static DISCOVERY_LISTENER_PORT: std::sync::atomic::AtomicU16 = std::sync::atomic::AtomicU16::new(0);
+
+/// Port the IPv4 discovery listener is bound to right now, if any.
+fn discovery_listener_port() -> Option<u16> {
+    match DISCOVERY_LISTENER_PORT.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => None,
+        port => Some(port),
     }
-    None
+}
+static LAST_PROBE_ANSWER: once_cell::sync::Lazy<Mutex<Option<Instant>>> = once_cell::sync::Lazy::new(|| Mutex::new(None));
+
+fn get_local_ip() -> Option<String> {
+    local_interface().map(|(_, ipv4)| ipv4.to_string())
+}
+
+/// Name and IPv4 address of the interface discovery runs on: the first non-loopback one.
+fn local_interface() -> Option<(String, std::net::Ipv4Addr)> {
+    get_if_addrs().ok()?.into_iter().find_map(|iface| match iface.ip() {
+        std::net::IpAddr::V4(ipv4) if !iface.is_loopback() => Some((iface.name, ipv4)),
+        _ => None,
+    })
+}
+
+/// First IPv6 address usable without a scope id: not loopback and not link-local (fe80::/10).
+fn get_local_ipv6() -> Option<String> {
+    get_if_addrs().ok()?.into_iter().find_map(|iface| match iface.ip() {
+        std::net::IpAddr::V6(v6) if !v6.is_loopback() && (v6.segments()[0] & 0xffc0) != 0xfe80 => Some(v6.to_string()),
+        _ => None,
+    })
 }
 
 // ✅ AGGIUNTA: Funzione per normalizzare il nome del dispositivo
@@ -112,14 +217,32 @@ use tauri_plugin_dialog;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let devices: SharedDevices = Arc::new(Mutex::new(Vec::new()));
 
-    tauri::Builder::default()
+    let mut builder = tauri::Builder::default();
+    #[cfg(desktop)]
+    {
+        // A second instance could only fight over ports 40123/40124: focus the running window instead
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, _argv, _cwd| {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        }));
+    }
+
+    builder
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        // Let everything through here; the effective level is log::max_level, see set_log_level
+        .plugin(tauri_plugin_log::Builder::default().level(log::LevelFilter::Trace).build())
         .manage(devices)
         .setup(|app| {
             // Clone the app handle before moving it into async tasks
             let app_handle = app.handle().clone();
             let devices_for_listener = app.state::<SharedDevices>().inner().clone();
             let devices_for_cleanup = app.state::<SharedDevices>().inner().clone();
+            let app_handle_for_cleanup = app.handle().clone();
+            let app_handle_for_listener = app.handle().clone();
 
             // Now spawn the tasks with the cloned handle
             tokio::spawn(async move {
@@ -128,15 +251,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             });
 
+            let app_handle_for_web = app.handle().clone();
             tokio::spawn(async move {
-                udp_broadcast_heartbeat_loop().await;
+                web_receive::apply_settings(app_handle_for_web).await;
             });
+
+            let devices_for_heartbeat = app.state::<SharedDevices>().inner().clone();
             tokio::spawn(async move {
-                udp_listener_loop(devices_for_listener).await;
+                let backend = file_transfer::discovery_backend().await;
+                if backend.uses_mdns() {
+                    tokio::spawn(mdns_discovery::run(devices_for_listener.clone(), app_handle_for_listener.clone()));
+                }
+                if !backend.uses_broadcast() {
+                    return;
+                }
+                tokio::spawn(udp_broadcast_heartbeat_loop(devices_for_heartbeat));
+                if file_transfer::ipv6_enabled().await {
+                    tokio::spawn(udp_listener_loop(devices_for_listener.clone(), app_handle_for_listener.clone(), true));
+                }
+                udp_listener_loop(devices_for_listener, app_handle_for_listener, false).await;
             });
+            let devices_for_latency = devices_for_cleanup.clone();
             tokio::spawn(async move {
-                cleanup_loop(devices_for_cleanup).await;
+                cleanup_loop(devices_for_cleanup, app_handle_for_cleanup).await;
             });
+            tokio::spawn(latency_loop(devices_for_latency));
+            tokio::spawn(file_transfer::hash_cache_loop());
+            tokio::spawn(file_transfer::apply_saved_log_level());
 
             Ok(())
         })
@@ -149,24 +290,124 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             file_transfer::add_recent_transfer,
             file_transfer::get_recent_transfers,
             file_transfer::delete_recent_transfer,
+            file_transfer::clear_recent_transfers,
+            file_transfer::get_recent_transfers_filtered,
             file_transfer::get_auto_accept_trusted,
             file_transfer::set_auto_accept_trusted,
             file_transfer::list_trusted_devices,
             file_transfer::get_system_stats,
             file_transfer::get_today_stats,
+            file_transfer::get_stats_for_period,
             file_transfer::add_trusted_device_mac,
             file_transfer::remove_trusted_device_mac,
             file_transfer::cancel_transfer_send,
-            file_transfer::cancel_transfer_receive
+            file_transfer::cancel_transfer_receive,
+            file_transfer::pause_transfer,
+            file_transfer::resume_transfer,
+            file_transfer::cleanup_temp_artifacts,
+            file_transfer::get_transfer_status,
+            file_transfer::get_last_failure_report,
+            file_transfer::get_failure_reports_enabled,
+            file_transfer::set_failure_reports_enabled,
+            file_transfer::get_collision_policy,
+            file_transfer::set_collision_policy,
+            file_transfer::get_allow_sender_overwrite,
+            file_transfer::set_allow_sender_overwrite,
+            file_transfer::get_startup_errors,
+            file_transfer::get_receive_high_water_mark,
+            file_transfer::get_settings_save_status,
+            file_transfer::set_receive_high_water_mark,
+            file_transfer::list_favorites,
+            file_transfer::add_favorite,
+            file_transfer::remove_favorite,
+            file_transfer::begin_batch,
+            file_transfer::get_auto_accept_folder_timeout,
+            file_transfer::set_auto_accept_folder_timeout,
+            file_transfer::get_min_free_space,
+            file_transfer::set_min_free_space,
+            file_transfer::get_device_uuid,
+            file_transfer::get_auto_copy_received_text,
+            file_transfer::set_auto_copy_received_text,
+            file_transfer::send_text,
+            file_transfer::set_transfer_priority,
+            file_transfer::get_dedup_enabled,
+            file_transfer::set_dedup_enabled,
+            file_transfer::get_compression,
+            file_transfer::set_compression,
+            file_transfer::get_web_receive_enabled,
+            file_transfer::set_web_receive_enabled,
+            file_transfer::get_speed_unit,
+            file_transfer::set_speed_unit,
+            file_transfer::get_discovery_mode,
+            file_transfer::set_discovery_mode,
+            refresh_discovery,
+            file_transfer::get_peer_profiles,
+            send_file_to_device,
+            file_transfer::get_safe_subnets,
+            file_transfer::set_safe_subnets,
+            file_transfer::get_foreign_network_action,
+            file_transfer::set_foreign_network_action,
+            diagnostics::run_diagnostics,
+            file_transfer::cancel_batch,
+            preview_send,
+            file_transfer::get_max_receive_bytes_per_sec,
+            file_transfer::set_max_receive_bytes_per_sec,
+            file_transfer::get_send_rate_limit,
+            file_transfer::set_send_rate_limit,
+            file_transfer::export_history_csv,
+            file_transfer::export_transfer_history,
+            file_transfer::get_pairing_key,
+            file_transfer::pair_device,
+            file_transfer::unpair_device,
+            file_transfer::list_paired_devices,
+            file_transfer::cancel_all_transfers,
+            file_transfer::get_offer_rate_limit,
+            file_transfer::set_offer_rate_limit,
+            file_transfer::list_watched_folders,
+            file_transfer::add_watched_folder,
+            file_transfer::remove_watched_folder,
+            file_transfer::rehash_watched_folders,
+            send_folder,
+            file_transfer::get_log_level,
+            file_transfer::set_log_level,
+            file_transfer::query_peer_identity,
+            file_transfer::get_batches,
+            send_files,
+            file_transfer::get_effective_config,
+            file_transfer::get_encryption_pin,
+            file_transfer::reset_encryption_pin,
+            file_transfer::get_pause_timeout,
+            file_transfer::set_pause_timeout,
+            file_transfer::get_transfer_port,
+            file_transfer::set_transfer_port,
+            file_transfer::get_discovery_port,
+            file_transfer::set_discovery_port,
+            file_transfer::get_default_download_dir,
+            file_transfer::set_default_download_dir,
+            file_transfer::get_ipv6_enabled,
+            file_transfer::set_ipv6_enabled,
+            file_transfer::get_discovery_backend,
+            file_transfer::set_discovery_backend,
+            file_transfer::get_device_nicknames,
+            file_transfer::set_device_nickname,
+            file_transfer::ping_device,
+            file_transfer::list_blocked_devices,
+            file_transfer::block_device,
+            file_transfer::unblock_device
          ])
-        .run(tauri::generate_context!())
-        .expect("error running tauri app");
+        .build(tauri::generate_context!())
+        .expect("error building tauri app")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                file_transfer::flush_settings_on_exit();
+            }
+        });
 
     Ok(())
 }
 
 // ✅ MODIFICATA: Funzione per inviare heartbeat con nome normalizzato
-async fn udp_broadcast_heartbeat_loop() {
+async fn udp_broadcast_heartbeat_loop(devices: SharedDevices) {
     let hostname = hostname::get()
         .map(|h| h.to_string_lossy().to_string())
         .unwrap_or_else(|_| "Unknown".to_string());
@@ -174,60 +415,261 @@ async fn udp_broadcast_heartbeat_loop() {
     // ✅ Normalizza il nome del dispositivo
     let name = normalize_device_name(&hostname);
     
-    let port = BROADCAST_PORT;
     let ip = get_local_ip().unwrap_or_else(|| "0.0.0.0".to_string());
+    let ipv6 = if file_transfer::ipv6_enabled().await { get_local_ipv6() } else { None };
     
     // get local MAC (optional)
     let mac = match get_mac_address() {
         Ok(Some(ma)) => Some(format!("{}", ma).to_lowercase()),
         _ => None,
     };
+    if mac.is_none() {
+        warn!("[BROADCAST] MAC address unavailable, peers will identify this device by its device id {}", *file_transfer::DEVICE_UUID);
+    }
 
     let device = Device {
+        app: Some(DISCOVERY_APP.to_string()),
+        proto: Some(DISCOVERY_PROTO),
         name: name.clone(),  // ✅ Usa il nome normalizzato
         ip: ip.clone(),
-        port,
+        ipv6: ipv6.clone(),
+        port: file_transfer::file_server_port(),
         status: "Online".to_string(),
         last_seen: Utc::now().to_rfc3339(),
         mac: mac.clone(),
+        app_version: Some(file_transfer::APP_VERSION.to_string()),
+        is_favorite: false,
+        accepting: file_transfer::is_accepting_transfers().await,
+        capabilities: file_transfer::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        device_uuid: Some(file_transfer::DEVICE_UUID.clone()),
+        probe: false,
+        last_seen_secs_ago: None,
+        latency_ms: None,
+        sig: None,
+        signature_valid: None,
     };
     
     let socket = TokioUdpSocket::bind(("0.0.0.0", 0)).await.expect("bind failed");
     socket.set_broadcast(true).expect("set broadcast failed");
-    let broadcast_addr = SocketAddr::from(([255,255,255,255], BROADCAST_PORT));
+    let discovery_port = file_transfer::configured_discovery_port().await;
+    let broadcast_addr = SocketAddr::from(([255,255,255,255], discovery_port));
+    let multicast_addr = SocketAddr::from((IPV6_DISCOVERY_GROUP, discovery_port));
+    // Sends the same heartbeat to the IPv6 group; only opened when we have an address to advertise
+    let socket_v6 = match ipv6 {
+        Some(_) => match TokioUdpSocket::bind(("::", 0)).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                warn!("[BROADCAST] IPv6 heartbeat socket unavailable: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
     
     debug!("[BROADCAST] Avvio heartbeat con nome normalizzato: {}", name);
     
+    let mut accepting = device.accepting;
+    let mut last_accepting_check = Instant::now();
     loop {
+        if last_accepting_check.elapsed() >= Duration::from_secs(ACCEPTING_CHECK_INTERVAL_SECS) {
+            accepting = file_transfer::is_accepting_transfers().await;
+            last_accepting_check = Instant::now();
+        }
         let mut to_send = device.clone();
         to_send.last_seen = Utc::now().to_rfc3339();
+        // The file server may have bound a fallback port after this loop started
+        to_send.port = file_transfer::file_server_port();
+        to_send.accepting = accepting;
+        to_send.probe = PROBE_REQUESTED.swap(false, std::sync::atomic::Ordering::SeqCst);
+        to_send.sig = file_transfer::sign_heartbeat(&heartbeat_signing_payload(&to_send));
         
         // ✅ Log per debug
         debug!("[BROADCAST] Invio heartbeat: name={}, ip={}, port={}", to_send.name, to_send.ip, to_send.port);
         
         let json = serde_json::to_string(&to_send).unwrap();
-        let _ = socket.send_to(json.as_bytes(), &broadcast_addr).await;
-        time::sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECS)).await;
+        match file_transfer::discovery_mode().await {
+            file_transfer::DiscoveryMode::Open => {
+                let _ = socket.send_to(json.as_bytes(), &broadcast_addr).await;
+                if let Some(ref s) = socket_v6 {
+                    let _ = s.send_to(json.as_bytes(), &multicast_addr).await;
+                }
+            }
+            file_transfer::DiscoveryMode::KnownOnly => {
+                // Known peers only: favorites by IP plus peers we have heard from (the listener only keeps known ones)
+                let mut targets: Vec<String> = file_transfer::read_favorites().await
+                    .into_iter()
+                    .map(|f| f.ip)
+                    .filter(|ip| !ip.is_empty())
+                    .collect();
+                targets.extend(devices.lock().unwrap().iter().map(|e| e.device.ip.clone()));
+                targets.sort();
+                targets.dedup();
+                for target in targets {
+                    if let Ok(ip) = target.parse::<std::net::IpAddr>() {
+                        let _ = socket.send_to(json.as_bytes(), SocketAddr::new(ip, discovery_port)).await;
+                    }
+                }
+            }
+            file_transfer::DiscoveryMode::Off => {}
+        }
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECS)) => {}
+            _ = HEARTBEAT_NOW.notified() => {}
+        }
+    }
+}
+
+// Errors that concern a single datagram (e.g. ICMP port unreachable surfacing as a reset on
+// Windows); anything else means the socket itself is unusable.
+fn is_transient_socket_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::Interrupted
+            | std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionRefused
+    )
+}
+
+/// Bind the discovery socket; the IPv6 one is IPv6-only so it can share the port with the IPv4 one.
+/// The IPv4 one only listens on the interface from local_interface, or on all of them while none is up.
+async fn bind_discovery_socket(port: u16, ipv6: bool) -> std::io::Result<TokioUdpSocket> {
+    if ipv6 {
+        return TokioUdpSocket::from_std(file_transfer::bind_ipv6_only(socket2::Type::DGRAM, port)?.into());
+    }
+    match local_interface() {
+        Some((name, ipv4)) => TokioUdpSocket::from_std(bind_ipv4_on_interface(&name, ipv4, port)?.into()),
+        None => TokioUdpSocket::bind(("0.0.0.0", port)).await,
+    }
+}
+
+// Linux and macOS only deliver broadcasts to sockets bound to the wildcard address, so there the
+// socket is tied to the interface instead, falling back to all interfaces if that is refused.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_ipv4_on_interface(name: &str, _ipv4: std::net::Ipv4Addr, port: u16) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)?;
+    if let Err(e) = socket.bind_device(Some(name.as_bytes())) {
+        warn!("[LISTENER] Could not restrict discovery to {}: {}, listening on all interfaces", name, e);
     }
+    socket.bind(&SocketAddr::from(([0, 0, 0, 0], port)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
 }
 
-async fn udp_listener_loop(devices: SharedDevices) {
-    let socket = match TokioUdpSocket::bind(("0.0.0.0", BROADCAST_PORT)).await {
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn bind_ipv4_on_interface(name: &str, _ipv4: std::net::Ipv4Addr, port: u16) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)?;
+    let index = std::ffi::CString::new(name)
+        .ok()
+        .and_then(|name| std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) }));
+    if let Err(e) = socket.bind_device_by_index_v4(index) {
+        warn!("[LISTENER] Could not restrict discovery to {}: {}, listening on all interfaces", name, e);
+    }
+    socket.bind(&SocketAddr::from(([0, 0, 0, 0], port)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+fn bind_ipv4_on_interface(_name: &str, ipv4: std::net::Ipv4Addr, port: u16) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)?;
+    socket.bind(&SocketAddr::from((ipv4, port)).into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Rebind the discovery socket after a fatal error, backing off while no interface is up.
+async fn rebind_listener_socket(port: u16, ipv6: bool) -> TokioUdpSocket {
+    let mut backoff = Duration::from_millis(LISTENER_REBIND_MIN_BACKOFF_MS);
+    loop {
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_millis(LISTENER_REBIND_MAX_BACKOFF_MS));
+        let Some(local_ip) = get_local_ip() else {
+            warn!("[LISTENER] No usable network interface, retrying in {:?}", backoff);
+            continue;
+        };
+        match bind_discovery_socket(port, ipv6).await {
+            Ok(s) => {
+                log::info!("[LISTENER] Discovery socket rebound on port {} (interface {})", port, local_ip);
+                return s;
+            }
+            Err(e) => warn!("[LISTENER] Rebind on port {} failed: {}, retrying in {:?}", port, e, backoff),
+        }
+    }
+}
+
+async fn udp_listener_loop(devices: SharedDevices, app_handle: tauri::AppHandle, ipv6: bool) {
+    let discovery_port = file_transfer::configured_discovery_port().await;
+    let mut socket = match bind_discovery_socket(discovery_port, ipv6).await {
         Ok(s) => s,
+        Err(e) if ipv6 => {
+            warn!("[LISTENER] IPv6 discovery on port {} not started: {}", discovery_port, e);
+            return;
+        }
         Err(e) => {
-            error!("Failed to bind to port {}: {}", BROADCAST_PORT, e);
+            file_transfer::report_bind_error(&app_handle, discovery_port, &e);
             return;
         }
     };
+    if !ipv6 {
+        DISCOVERY_LISTENER_PORT.store(discovery_port, std::sync::atomic::Ordering::Relaxed);
+    }
     let mut buf = [0u8; 2048];
     loop {
-        let Ok((len, addr)) = socket.recv_from(&mut buf).await else { continue; };
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) if is_transient_socket_error(&e) => {
+                debug!("[LISTENER] Transient receive error: {}", e);
+                continue;
+            }
+            Err(e) => {
+                error!("[LISTENER] Discovery socket failed: {}, rebinding", e);
+                drop(socket);
+                if !ipv6 {
+                    DISCOVERY_LISTENER_PORT.store(0, std::sync::atomic::Ordering::Relaxed);
+                }
+                socket = rebind_listener_socket(discovery_port, ipv6).await;
+                if !ipv6 {
+                    DISCOVERY_LISTENER_PORT.store(discovery_port, std::sync::atomic::Ordering::Relaxed);
+                }
+                continue;
+            }
+        };
         let data = &buf[..len];
-        let Ok(dev): Result<Device, _> = serde_json::from_slice(data) else {
-            warn!("Failed to parse device data from {}: {:?}", addr, String::from_utf8_lossy(data));
+        let Ok(magic) = serde_json::from_slice::<DiscoveryMagic>(data) else {
+            debug!("[LISTENER] Ignoring non-JSON packet from {} ({} bytes)", addr, len);
+            continue;
+        };
+        if magic.app.as_deref().is_some_and(|app| app != DISCOVERY_APP) {
+            debug!("[LISTENER] Ignoring non-AirShare packet from {} ({} bytes)", addr, len);
+            continue;
+        }
+        let proto = magic.proto.unwrap_or(0);
+        if proto > DISCOVERY_PROTO {
+            debug!("[LISTENER] Ignoring heartbeat from {} with newer discovery protocol {} (local {})", addr, proto, DISCOVERY_PROTO);
             continue;
+        }
+        let mut dev: Device = match serde_json::from_slice(data) {
+            Ok(dev) => dev,
+            // Without the magic, anything JSON on the port could land here
+            Err(_) if magic.app.is_none() => {
+                debug!("[LISTENER] Ignoring non-AirShare packet from {} ({} bytes)", addr, len);
+                continue;
+            }
+            Err(_) => {
+                warn!("Failed to parse device data from {}: {:?}", addr, String::from_utf8_lossy(data));
+                continue;
+            }
         };
         // Ignore own heartbeat
+        if dev.device_uuid.as_deref() == Some(file_transfer::DEVICE_UUID.as_str()) {
+            continue;
+        }
+        // Older builds advertised the discovery port here instead of the file-server port
+        if dev.port == BROADCAST_PORT {
+            dev.port = file_transfer::FILE_SERVER_PORT;
+        }
         match get_local_ip() {
             Some(local_ip) => {
                 if dev.ip == local_ip {
@@ -240,62 +682,409 @@ async fn udp_listener_loop(devices: SharedDevices) {
         }
         
         debug!("[LISTENER] Ricevuto dispositivo: name={}, ip={}", dev.name, dev.ip);
+
+        if dev.probe {
+            let mut last = LAST_PROBE_ANSWER.lock().unwrap();
+            if last.map_or(true, |t| t.elapsed() >= Duration::from_millis(PROBE_ANSWER_MIN_INTERVAL_MS)) {
+                *last = Some(Instant::now());
+                HEARTBEAT_NOW.notify_one();
+            }
+        }
+
+        check_device_signature(&mut dev, &app_handle, addr.ip(), true).await;
+
+        if file_transfer::discovery_mode().await == file_transfer::DiscoveryMode::KnownOnly
+            && !file_transfer::is_known_peer(&dev.ip, dev.mac.as_deref(), dev.device_uuid.as_deref()).await
+        {
+            debug!("[LISTENER] Ignoro dispositivo sconosciuto (KnownOnly): {}", dev.ip);
+            continue;
+        }
         
-        let now = Instant::now();
-        let mut devs = devices.lock().unwrap();
-        if let Some(existing) = devs.iter_mut().find(|d| d.device.ip == dev.ip) {
-            existing.device = dev.clone();
-            existing.last_seen_instant = now;
-            debug!("[LISTENER] Dispositivo aggiornato: {}", dev.name);
-        } else {
-            devs.push(DeviceEntry {
-                device: dev.clone(),
-                last_seen_instant: now,
-            });
-            debug!("[LISTENER] Nuovo dispositivo aggiunto: {}", dev.name);
+        file_transfer::note_peer_ipv6(&dev.ip, dev.ipv6.as_deref());
+        merge_device(&devices, &app_handle, dev);
+    }
+}
+
+/// Paired devices must prove their identity; anyone can claim a device id.
+/// Sets `signature_valid` and emits `heartbeat_signature_invalid` on a bad signature.
+/// `fresh` also rejects stale or replayed heartbeats; mDNS records are signed once per
+/// registration and re-resolved as they are, so they're only checked for the signature.
+async fn check_device_signature(dev: &mut Device, app_handle: &tauri::AppHandle, from: std::net::IpAddr, fresh: bool) {
+    let sent_at = fresh.then_some(dev.last_seen.as_str());
+    dev.signature_valid = match dev.device_uuid.as_deref() {
+        Some(uuid) => file_transfer::verify_heartbeat(uuid, &heartbeat_signing_payload(dev), dev.sig.as_deref(), sent_at).await,
+        None => None,
+    };
+    if dev.signature_valid == Some(false) {
+        warn!("[LISTENER] Heartbeat from {} claims paired device {:?} without a valid signature", from, dev.device_uuid);
+        let _ = app_handle.emit("heartbeat_signature_invalid", serde_json::json!({
+            "ip": from.to_string(),
+            "device_uuid": dev.device_uuid,
+            "name": dev.name,
+        }));
+    }
+}
+
+/// Add a discovered device to the shared list or refresh its entry; used by every discovery backend.
+/// Emits `device_online` with the device when it wasn't listed yet.
+fn merge_device(devices: &SharedDevices, app_handle: &tauri::AppHandle, dev: Device) {
+    let now = Instant::now();
+    let mut devs = devices.lock().unwrap();
+    // Same installation even if its IP changed; peers without a device id fall back to IP
+    let same_device = |d: &&mut DeviceEntry| match (&d.device.device_uuid, &dev.device_uuid) {
+        (Some(a), Some(b)) => a == b,
+        _ => d.device.ip == dev.ip,
+    };
+    if let Some(existing) = devs.iter_mut().find(same_device) {
+        // Don't let a forged heartbeat redirect a verified device's entry
+        if dev.signature_valid == Some(false) && existing.device.signature_valid == Some(true) {
+            return;
         }
+        debug!("[LISTENER] Dispositivo aggiornato: {}", dev.name);
+        existing.device = dev;
+        existing.last_seen_instant = now;
+    } else {
+        debug!("[LISTENER] Nuovo dispositivo aggiunto: {}", dev.name);
+        devs.push(DeviceEntry {
+            device: dev.clone(),
+            last_seen_instant: now,
+        });
+        drop(devs);
+        let _ = app_handle.emit("device_online", &dev);
     }
 }
 
-async fn cleanup_loop(devices: SharedDevices) {
+/// `reason` is "timeout" (no heartbeat within DEVICE_TIMEOUT_SECS) or "unreachable" (refresh_discovery).
+fn emit_device_offline(app_handle: &tauri::AppHandle, dev: &Device, reason: &str) {
+    let _ = app_handle.emit("device_offline", serde_json::json!({
+        "ip": dev.ip,
+        "name": dev.name,
+        "mac": dev.mac,
+        "device_uuid": dev.device_uuid,
+        "reason": reason,
+    }));
+}
+
+/// Ping every listed device that understands pings, so get_devices can show latency without the UI asking.
+async fn latency_loop(devices: SharedDevices) {
     loop {
-        {
+        time::sleep(Duration::from_secs(LATENCY_INTERVAL_SECS)).await;
+        let peers: Vec<(String, u16)> = {
+            let devs = devices.lock().unwrap();
+            devs.iter()
+                .filter(|e| e.device.capabilities.iter().any(|c| c == file_transfer::PING_CAPABILITY))
+                .map(|e| (e.device.ip.clone(), e.device.port))
+                .collect()
+        };
+        for (ip, port) in peers {
+            if let Err(e) = file_transfer::ping_device(ip.clone(), port).await {
+                debug!("[LATENCY] Ping to {} failed: {}", ip, e);
+            }
+        }
+    }
+}
+
+async fn cleanup_loop(devices: SharedDevices, app_handle: tauri::AppHandle) {
+    loop {
+        let removed: Vec<Device> = {
             let mut devs = devices.lock().unwrap();
             let now = Instant::now();
-            let before_count = devs.len();
-            devs.retain(|entry| now.duration_since(entry.last_seen_instant).as_secs() < DEVICE_TIMEOUT_SECS);
-            let after_count = devs.len();
-            
-            if before_count != after_count {
-                debug!("[CLEANUP] Rimossi {} dispositivi inattivi", before_count - after_count);
-            }
+            let (kept, removed): (Vec<_>, Vec<_>) = devs
+                .drain(..)
+                .partition(|entry| now.duration_since(entry.last_seen_instant).as_secs() < DEVICE_TIMEOUT_SECS);
+            *devs = kept;
+            removed.into_iter().map(|entry| entry.device).collect()
+        };
+        if !removed.is_empty() {
+            debug!("[CLEANUP] Rimossi {} dispositivi inattivi", removed.len());
+        }
+        for dev in &removed {
+            emit_device_offline(&app_handle, dev, "timeout");
         }
         time::sleep(Duration::from_secs(1)).await;
     }
 }
 
+/// Send an immediate probe heartbeat (peers answer with theirs) and drop listed devices
+/// whose file server no longer accepts connections. Returns the number of devices kept.
 #[tauri::command]
-fn get_devices(devices: tauri::State<'_, SharedDevices>) -> Vec<Device> {
-    let devs = devices.lock().unwrap();
-    let device_list: Vec<Device> = devs.iter().map(|entry| entry.device.clone()).collect();
+async fn refresh_discovery(app_handle: tauri::AppHandle, devices: tauri::State<'_, SharedDevices>) -> Result<usize, String> {
+    PROBE_REQUESTED.store(true, std::sync::atomic::Ordering::SeqCst);
+    HEARTBEAT_NOW.notify_one();
+
+    let peers: Vec<(String, u16)> = {
+        let devs = devices.lock().unwrap();
+        devs.iter().map(|e| (e.device.ip.clone(), e.device.port)).collect()
+    };
+    let mut checks = tokio::task::JoinSet::new();
+    for (ip, port) in peers {
+        checks.spawn(async move {
+            let addr = format!("{}:{}", ip, port);
+            let reachable = matches!(
+                time::timeout(Duration::from_millis(PROBE_TIMEOUT_MS), TcpStream::connect(&addr)).await,
+                Ok(Ok(_))
+            );
+            (ip, reachable)
+        });
+    }
+    let mut unreachable = Vec::new();
+    while let Some(Ok((ip, reachable))) = checks.join_next().await {
+        if !reachable {
+            unreachable.push(ip);
+        }
+    }
+
+    let mut devs = devices.lock().unwrap();
+    devs.retain(|e| {
+        let keep = !unreachable.contains(&e.device.ip);
+        if !keep {
+            emit_device_offline(&app_handle, &e.device, "unreachable");
+        }
+        keep
+    });
+    debug!("[REFRESH] Rimossi {} dispositivi non raggiungibili", unreachable.len());
+    Ok(devs.len())
+}
+
+#[tauri::command]
+async fn get_devices(devices: tauri::State<'_, SharedDevices>) -> Result<Vec<Device>, String> {
+    let favorites = file_transfer::read_favorites().await;
+    let mut device_list: Vec<Device> = {
+        let devs = devices.lock().unwrap();
+        devs.iter()
+            .map(|entry| Device {
+                last_seen_secs_ago: Some(entry.last_seen_instant.elapsed().as_secs()),
+                latency_ms: file_transfer::peer_latency(&entry.device.ip),
+                ..entry.device.clone()
+            })
+            .collect()
+    };
+    for device in device_list.iter_mut() {
+        device.is_favorite = favorites.iter().any(|f| f.matches(&device.ip, device.mac.as_deref(), device.device_uuid.as_deref()));
+    }
+
+    // Favorites stay listed while offline so the UI can show them greyed out
+    for fav in &favorites {
+        if device_list.iter().any(|d| fav.matches(&d.ip, d.mac.as_deref(), d.device_uuid.as_deref())) {
+            continue;
+        }
+        device_list.push(Device {
+            app: None,
+            proto: None,
+            name: fav.name.clone(),
+            ip: fav.ip.clone(),
+            ipv6: None,
+            port: file_transfer::FILE_SERVER_PORT,
+            status: "Offline".to_string(),
+            last_seen: fav.last_seen.clone(),
+            mac: fav.mac.clone(),
+            app_version: None,
+            is_favorite: true,
+            accepting: false,
+            capabilities: Vec::new(),
+            device_uuid: fav.device_uuid.clone(),
+            probe: false,
+            last_seen_secs_ago: None,
+            latency_ms: None,
+            sig: None,
+            signature_valid: None,
+        });
+    }
+    // Blocked devices disappear from the UI entirely, favorites included
+    let blocked = file_transfer::read_blocked_devices().await;
+    device_list.retain(|d| !file_transfer::is_blocked(&blocked, &d.ip, d.mac.as_deref(), d.device_uuid.as_deref()));
+    let nicknames = file_transfer::read_device_nicknames().await;
+    for device in device_list.iter_mut() {
+        if let Some(nickname) = file_transfer::nickname_for(&nicknames, &device.ip, device.mac.as_deref()) {
+            device.name = nickname.clone();
+        }
+    }
+    // Stable sort: favorites first, discovery order otherwise
+    device_list.sort_by_key(|d| !d.is_favorite);
     
     debug!("[GET_DEVICES] Ritornando {} dispositivi", device_list.len());
     for device in &device_list {
         debug!("[GET_DEVICES] - {}: {}", device.name, device.ip);
     }
     
-    device_list
+    Ok(device_list)
 }
 
 #[tauri::command]
 async fn send_file(app_handle: tauri::AppHandle, ip: String, port: u16, file_path: String) -> Result<String, String> {
     let path = std::path::PathBuf::from(file_path);
-    match file_transfer::send_file(ip, port, path, app_handle, None).await {
+    match file_transfer::send_file(ip, port, path, app_handle, None, None).await {
         Ok(_) => Ok("File inviato con successo".into()),
         Err(e) => Err(e.to_string()),
     }
 }
 
+/// Send a folder: as one tar stream when the peer supports it, otherwise file by file as a batch
+/// (the receiver then gets the files without their subfolders).
+#[tauri::command]
+async fn send_folder(
+    app_handle: tauri::AppHandle,
+    devices: tauri::State<'_, SharedDevices>,
+    ip: String,
+    port: u16,
+    folder_path: String,
+    batch_id: Option<String>,
+) -> Result<String, String> {
+    let folder = std::path::PathBuf::from(&folder_path);
+    if !folder.is_dir() {
+        return Err(format!("not a folder: {}", folder_path));
+    }
+    let supports_tar = devices
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|e| e.device.ip == ip && e.device.capabilities.iter().any(|c| c == file_transfer::FOLDER_TAR_KIND));
+    if supports_tar {
+        log::info!("[MAIN] send_folder: {:?} to {} as tar", folder, ip);
+        let result = file_transfer::send_folder_tar(ip, port, folder, app_handle.clone(), batch_id.clone()).await;
+        if let Some(ref batch_id) = batch_id {
+            file_transfer::record_batch_outcome(&app_handle, batch_id, &result).await;
+        }
+        return match result {
+            Ok(_) => Ok("Cartella inviata con successo".into()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    let files = file_transfer::list_folder_files(folder).await;
+    log::info!("[MAIN] send_folder: {} without tar support, sending {} files one by one", ip, files.len());
+    let batch_id = batch_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    for path in files {
+        let result = file_transfer::send_file(ip.clone(), port, path, app_handle.clone(), Some(batch_id.clone()), None).await;
+        file_transfer::record_batch_outcome(&app_handle, &batch_id, &result).await;
+        if let Err(e) = result {
+            if e.downcast_ref::<file_transfer::TransferAborted>().is_some() {
+                return Err(e.to_string());
+            }
+            warn!("[MAIN] send_folder: file failed, continuing: {}", e);
+        }
+    }
+    Ok("Cartella inviata con successo".into())
+}
+
+/// Send several files as one batch: over a single connection when the peer supports it,
+/// otherwise one connection per file as before. Progress is reported against the whole batch.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn send_files(
+    app_handle: tauri::AppHandle,
+    devices: tauri::State<'_, SharedDevices>,
+    ip: String,
+    port: u16,
+    paths: Vec<String>,
+    batch_id: String,
+    priority: Option<i32>,
+    encryption_pin: Option<String>,
+) -> Result<String, String> {
+    let paths: Vec<std::path::PathBuf> = paths.into_iter().map(std::path::PathBuf::from).collect();
+    let batch_id = if batch_id.is_empty() { uuid::Uuid::new_v4().to_string() } else { batch_id };
+    let multiplexed = devices
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|e| e.device.ip == ip && e.device.capabilities.iter().any(|c| c == file_transfer::MULTI_FILE_CAPABILITY));
+    // Same counters as send_file_with_progress: begin_batch's own, otherwise the global one
+    let (overall_sent, overall_total) = match file_transfer::batch_progress_counter(&batch_id).await {
+        Some(counter) => counter,
+        None => {
+            *OVERALL_SENT.lock().await = 0;
+            let mut total = 0;
+            for path in &paths {
+                total += tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+            }
+            (OVERALL_SENT.clone(), total)
+        }
+    };
+    if multiplexed {
+        log::info!("[MAIN] send_files: {} files to {} on one connection", paths.len(), ip);
+        return match file_transfer::send_files_multiplexed(ip, port, paths, app_handle, batch_id, overall_sent, overall_total, priority, encryption_pin).await {
+            Ok(_) => Ok("File inviati con successo".into()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    let total_files = paths.len();
+    for (index, path) in paths.into_iter().enumerate() {
+        let result = file_transfer::send_file_with_progress(
+            ip.clone(),
+            port,
+            path,
+            app_handle.clone(),
+            Some(index),
+            Some(total_files),
+            None,
+            Some(overall_sent.clone()),
+            Some(overall_total),
+            Some(batch_id.clone()),
+            None,
+            priority,
+            encryption_pin.clone(),
+        ).await;
+        file_transfer::record_batch_outcome(&app_handle, &batch_id, &result).await;
+        if let Err(e) = result {
+            if e.downcast_ref::<file_transfer::TransferAborted>().is_some() {
+                return Err(e.to_string());
+            }
+            warn!("[MAIN] send_files: file failed, continuing: {}", e);
+        }
+    }
+    Ok("File inviati con successo".into())
+}
+
+/// Find an online device by device id (preferred) or exact name.
+fn resolve_device(devices: &SharedDevices, name_or_uuid: &str) -> Result<Device, String> {
+    let devs = devices.lock().unwrap();
+    if let Some(entry) = devs.iter().find(|e| e.device.device_uuid.as_deref() == Some(name_or_uuid)) {
+        return Ok(entry.device.clone());
+    }
+    let by_name: Vec<&DeviceEntry> = devs.iter().filter(|e| e.device.name == name_or_uuid).collect();
+    match by_name.as_slice() {
+        [entry] => Ok(entry.device.clone()),
+        [] => Err(format!("device '{}' is offline or unknown", name_or_uuid)),
+        many => Err(format!(
+            "device name '{}' is ambiguous ({} devices: {}), use the device id",
+            name_or_uuid,
+            many.len(),
+            many.iter().map(|e| e.device.ip.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+#[tauri::command]
+async fn send_file_to_device(
+    app_handle: tauri::AppHandle,
+    devices: tauri::State<'_, SharedDevices>,
+    device_name_or_uuid: String,
+    file_path: String,
+) -> Result<String, String> {
+    let device = resolve_device(devices.inner(), &device_name_or_uuid)?;
+    log::info!("[MAIN] send_file_to_device: '{}' -> {}", device_name_or_uuid, device.ip);
+    send_file(app_handle, device.ip, device.port, file_path).await
+}
+
+/// Preview a send to `target_ip` without connecting: per-path size and MIME, folder totals and an ETA.
+#[tauri::command]
+async fn preview_send(
+    devices: tauri::State<'_, SharedDevices>,
+    paths: Vec<String>,
+    target_ip: String,
+    target_port: u16,
+) -> Result<file_transfer::SendPreview, String> {
+    // Speed profiles are keyed by device id when the peer sent one, by IP otherwise
+    let peer_key = devices
+        .lock()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .find(|d| d.device.ip == target_ip)
+        .and_then(|d| d.device.device_uuid.clone())
+        .unwrap_or_else(|| target_ip.clone());
+    log::info!("[MAIN] preview_send: {} paths for {}:{}", paths.len(), target_ip, target_port);
+    file_transfer::preview_send_paths(paths, &peer_key).await
+}
+
 // Global state for tracking overall transfer progress
 static OVERALL_SENT: once_cell::sync::Lazy<std::sync::Arc<tokio::sync::Mutex<u64>>> = 
     once_cell::sync::Lazy::new(|| std::sync::Arc::new(tokio::sync::Mutex::new(0)));
@@ -310,7 +1099,10 @@ async fn send_file_with_progress(
     total_files: Option<usize>,
     file_name: Option<String>,
     total_size: Option<u64>,
-    batch_id: String
+    batch_id: String,
+    collision_hint: Option<file_transfer::CollisionPolicy>,
+    priority: Option<i32>,
+    encryption_pin: Option<String>,
 ) -> Result<String, String> {
     let path_buf = std::path::PathBuf::from(&path);
     
@@ -342,8 +1134,16 @@ async fn send_file_with_progress(
         }
     }
     
-    let batch_id_option = if batch_id.is_empty() { None } else { Some(batch_id) };
-    match file_transfer::send_file_with_progress(ip, port, path_buf, app_handle, file_index, total_files, file_name, Some(OVERALL_SENT.clone()), total_size, batch_id_option).await {
+    // Batches allocated with begin_batch carry their own counters; otherwise use the global one
+    let (overall_sent, overall_total) = match file_transfer::batch_progress_counter(&batch_id).await {
+        Some((counter, total)) => (counter, Some(total)),
+        None => (OVERALL_SENT.clone(), total_size),
+    };
+
+    let batch_id_option = if batch_id.is_empty() { None } else { Some(batch_id.clone()) };
+    let result = file_transfer::send_file_with_progress(ip, port, path_buf, app_handle.clone(), file_index, total_files, file_name, Some(overall_sent), overall_total, batch_id_option, collision_hint, priority, encryption_pin).await;
+    file_transfer::record_batch_outcome(&app_handle, &batch_id, &result).await;
+    match result {
         Ok(_) => Ok("File inviato con successo".into()),
         Err(e) => Err(e.to_string()),
     }
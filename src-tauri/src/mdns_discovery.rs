@@ -0,0 +1,235 @@
+// mDNS/DNS-SD discovery for networks that drop broadcasts: advertises `_airshare._tcp` with this
+// device's identity in TXT records and browses for peers, feeding the same device list as the
+// UDP heartbeat so get_devices returns both.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use log::{debug, info, warn};
+use mac_address::get_mac_address;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo, VERIFY_TIMEOUT_DEFAULT};
+use tauri::AppHandle;
+
+use crate::{file_transfer, Device, SharedDevices};
+
+const SERVICE_TYPE: &str = "_airshare._tcp.local.";
+// How often known peers are verified and the browse is restarted to re-list what is still cached
+const REQUERY_INTERVAL_SECS: u64 = 30;
+
+/// Advertise (in `Open` discovery mode) and browse until the daemon stops.
+pub async fn run(devices: SharedDevices, app_handle: AppHandle) {
+    let daemon = match ServiceDaemon::new() {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("[MDNS] Daemon could not start: {}", e);
+            return;
+        }
+    };
+    let mut receiver = match daemon.browse(SERVICE_TYPE) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("[MDNS] Browse for {} failed: {}", SERVICE_TYPE, e);
+            return;
+        }
+    };
+    info!("[MDNS] Browsing for {}", SERVICE_TYPE);
+
+    // Resolved peers by service full name and until when their records are live. mDNS only
+    // reports changes, so they're re-listed each tick until then; a restarted browse replays
+    // the ones still in the daemon's cache, which renews them.
+    let mut peers: HashMap<String, (Device, Instant)> = HashMap::new();
+    // Port and `accepting` currently registered; None while not advertising
+    let mut advertised: Option<(u16, bool)> = None;
+    let mut accepting = file_transfer::is_accepting_transfers().await;
+    let mut last_accepting_check = Instant::now();
+    let mut last_requery = Instant::now();
+    let mut refresh = tokio::time::interval(Duration::from_secs(crate::HEARTBEAT_INTERVAL_SECS));
+    loop {
+        tokio::select! {
+            event = receiver.recv_async() => match event {
+                Ok(ServiceEvent::ServiceResolved(service)) => {
+                    let Some(mut dev) = device_from_service(&service) else {
+                        continue;
+                    };
+                    if dev.device_uuid.as_deref() == Some(file_transfer::DEVICE_UUID.as_str()) {
+                        continue;
+                    }
+                    let from = dev.ip.parse().unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into());
+                    crate::check_device_signature(&mut dev, &app_handle, from, false).await;
+                    if file_transfer::discovery_mode().await == file_transfer::DiscoveryMode::KnownOnly
+                        && !file_transfer::is_known_peer(&dev.ip, dev.mac.as_deref(), dev.device_uuid.as_deref()).await
+                    {
+                        debug!("[MDNS] Ignoring unknown device (KnownOnly): {}", dev.ip);
+                        continue;
+                    }
+                    debug!("[MDNS] Resolved {}: name={}, ip={}", service.get_fullname(), dev.name, dev.ip);
+                    crate::merge_device(&devices, &app_handle, dev.clone());
+                    let live_until = Instant::now() + Duration::from_secs(service.get_host_ttl().into());
+                    peers.insert(service.get_fullname().to_string(), (dev, live_until));
+                }
+                Ok(ServiceEvent::ServiceRemoved(_, fullname)) => {
+                    debug!("[MDNS] {} went away", fullname);
+                    peers.remove(&fullname);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("[MDNS] Browse stopped: {}", e);
+                    return;
+                }
+            },
+            _ = refresh.tick() => {
+                let now = Instant::now();
+                peers.retain(|_, (_, live_until)| *live_until > now);
+                for (dev, _) in peers.values() {
+                    crate::merge_device(&devices, &app_handle, Device { last_seen: Utc::now().to_rfc3339(), ..dev.clone() });
+                }
+                if last_requery.elapsed() >= Duration::from_secs(REQUERY_INTERVAL_SECS) {
+                    // Peers that don't answer are flushed and reported as ServiceRemoved
+                    for fullname in peers.keys() {
+                        if let Err(e) = daemon.verify(fullname.clone(), VERIFY_TIMEOUT_DEFAULT) {
+                            debug!("[MDNS] Verify of {} failed: {}", fullname, e);
+                        }
+                    }
+                    match daemon.browse(SERVICE_TYPE) {
+                        Ok(r) => receiver = r,
+                        Err(e) => warn!("[MDNS] Re-browse for {} failed: {}", SERVICE_TYPE, e),
+                    }
+                    last_requery = now;
+                }
+                if last_accepting_check.elapsed() >= Duration::from_secs(crate::ACCEPTING_CHECK_INTERVAL_SECS) {
+                    accepting = file_transfer::is_accepting_transfers().await;
+                    last_accepting_check = now;
+                }
+                // Advertising reaches everyone on the link, so only Open mode does it. Re-registered
+                // when the file server bound a fallback port or `accepting` changed.
+                let open = file_transfer::discovery_mode().await == file_transfer::DiscoveryMode::Open;
+                let wanted = open.then(|| (file_transfer::file_server_port(), accepting));
+                if wanted != advertised {
+                    advertised = match wanted {
+                        Some((_, accepting)) => advertise(&daemon, accepting).map(|port| (port, accepting)),
+                        None => {
+                            unadvertise(&daemon);
+                            None
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn service_fullname() -> String {
+    format!("{}.{}", file_transfer::DEVICE_UUID.as_str(), SERVICE_TYPE)
+}
+
+/// Register (or re-register) this device's service; returns the advertised port.
+fn advertise(daemon: &ServiceDaemon, accepting: bool) -> Option<u16> {
+    let Some(ip) = crate::get_local_ip() else {
+        warn!("[MDNS] No LAN interface found, not advertising");
+        return None;
+    };
+    let hostname = hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "Unknown".to_string());
+    let port = file_transfer::file_server_port();
+    let mut me = Device {
+        app: Some(crate::DISCOVERY_APP.to_string()),
+        proto: Some(crate::DISCOVERY_PROTO),
+        name: crate::normalize_device_name(&hostname),
+        ip: ip.clone(),
+        ipv6: None,
+        port,
+        status: "Online".to_string(),
+        last_seen: Utc::now().to_rfc3339(),
+        mac: get_mac_address().ok().flatten().map(|m| m.to_string().to_lowercase()),
+        app_version: Some(file_transfer::APP_VERSION.to_string()),
+        is_favorite: false,
+        accepting,
+        capabilities: file_transfer::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        device_uuid: Some(file_transfer::DEVICE_UUID.clone()),
+        probe: false,
+        last_seen_secs_ago: None,
+        latency_ms: None,
+        sig: None,
+        signature_valid: None,
+    };
+    // Signed once at registration: paired peers verify it like a heartbeat signature
+    me.sig = file_transfer::sign_heartbeat(&crate::heartbeat_signing_payload(&me));
+
+    let mut txt: HashMap<String, String> = HashMap::new();
+    txt.insert("name".to_string(), me.name.clone());
+    txt.insert("ip".to_string(), me.ip.clone());
+    txt.insert("id".to_string(), file_transfer::DEVICE_UUID.clone());
+    txt.insert("version".to_string(), file_transfer::APP_VERSION.to_string());
+    txt.insert("caps".to_string(), me.capabilities.join(","));
+    txt.insert("ts".to_string(), me.last_seen.clone());
+    txt.insert("accepting".to_string(), if me.accepting { "1" } else { "0" }.to_string());
+    if let Some(mac) = me.mac {
+        txt.insert("mac".to_string(), mac);
+    }
+    if let Some(sig) = me.sig {
+        txt.insert("sig".to_string(), sig);
+    }
+    let host = format!("{}.local.", file_transfer::DEVICE_UUID.as_str());
+    let service = match ServiceInfo::new(SERVICE_TYPE, file_transfer::DEVICE_UUID.as_str(), &host, ip.as_str(), port, txt) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("[MDNS] Invalid service info: {}", e);
+            return None;
+        }
+    };
+    match daemon.register(service) {
+        Ok(()) => {
+            info!("[MDNS] Advertising {} on {}:{}", SERVICE_TYPE, ip, port);
+            Some(port)
+        }
+        Err(e) => {
+            warn!("[MDNS] Register failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Withdraw this device's service, e.g. after leaving `Open` discovery mode.
+fn unadvertise(daemon: &ServiceDaemon) {
+    match daemon.unregister(&service_fullname()) {
+        Ok(_) => info!("[MDNS] Stopped advertising {}", SERVICE_TYPE),
+        Err(e) => warn!("[MDNS] Unregister failed: {}", e),
+    }
+}
+
+/// Rebuild a heartbeat-shaped Device from a resolved service's TXT records.
+fn device_from_service(service: &ServiceInfo) -> Option<Device> {
+    let prop = |key: &str| service.get_property_val_str(key).map(str::to_string);
+    if prop("id").is_none() && prop("name").is_none() {
+        return None;
+    }
+    // Prefer the address the peer signed, as long as it is one it was actually resolved at
+    let resolved: Vec<String> = service.get_addresses_v4().into_iter().map(|a| a.to_string()).collect();
+    let ip = prop("ip")
+        .filter(|ip| resolved.contains(ip))
+        .or_else(|| resolved.first().cloned())?;
+    Some(Device {
+        app: Some(crate::DISCOVERY_APP.to_string()),
+        proto: Some(crate::DISCOVERY_PROTO),
+        name: prop("name").unwrap_or_else(|| service.get_hostname().trim_end_matches(".local.").to_string()),
+        ip,
+        ipv6: None,
+        port: service.get_port(),
+        status: "Online".to_string(),
+        last_seen: prop("ts").unwrap_or_else(|| Utc::now().to_rfc3339()),
+        mac: prop("mac"),
+        app_version: prop("version"),
+        is_favorite: false,
+        // Peers from before the TXT key always claimed to accept
+        accepting: prop("accepting").as_deref() != Some("0"),
+        capabilities: prop("caps")
+            .map(|c| c.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default(),
+        device_uuid: prop("id"),
+        probe: false,
+        last_seen_secs_ago: None,
+        latency_ms: None,
+        sig: prop("sig"),
+        signature_valid: None,
+    })
+}
@@ -0,0 +1,249 @@
+// Browser-based receive: a tiny HTTP server on the LAN interface so devices without AirShare
+// can upload from a web page. Each upload is bridged into the regular TCP file server on
+// localhost, so it goes through the same prompt, destination, collision and history logic.
+use std::sync::Mutex;
+use log::{info, warn, error};
+use once_cell::sync::Lazy;
+use tauri::AppHandle;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::file_transfer::{self, FileOffer};
+
+pub const DEFAULT_WEB_RECEIVE_PORT: u16 = 40125;
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+static SERVER_TASK: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+const UPLOAD_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>AirShare</title>
+<style>
+body { font-family: sans-serif; max-width: 32rem; margin: 3rem auto; padding: 0 1rem; }
+button { padding: .6rem 1.2rem; }
+#status { margin-top: 1rem; white-space: pre-line; }
+</style>
+</head>
+<body>
+<h1>AirShare</h1>
+<p>Choose files to send to this computer. The recipient has to accept them.</p>
+<input type="file" id="files" multiple>
+<button id="send">Send</button>
+<div id="status"></div>
+<script>
+document.getElementById('send').onclick = async () => {
+  const files = document.getElementById('files').files;
+  const status = document.getElementById('status');
+  const batch = Date.now().toString(36) + Math.random().toString(36).slice(2);
+  status.textContent = '';
+  for (const f of files) {
+    status.textContent += f.name + '… ';
+    try {
+      const res = await fetch('/upload', {
+        method: 'PUT',
+        headers: { 'X-File-Name': encodeURIComponent(f.name), 'X-Batch-Id': batch },
+        body: f
+      });
+      status.textContent += (res.ok ? 'OK' : await res.text()) + '\n';
+    } catch (e) {
+      status.textContent += e + '\n';
+    }
+  }
+};
+</script>
+</body>
+</html>
+"#;
+
+/// (Re)start or stop the server to match the current settings.
+pub async fn apply_settings(app_handle: AppHandle) {
+    stop();
+    let Some(port) = file_transfer::web_receive_port().await else {
+        return;
+    };
+    let Some(ip) = crate::get_local_ip() else {
+        warn!("[WEB] No LAN interface found, browser receive not started");
+        return;
+    };
+    let listener = match TcpListener::bind((ip.as_str(), port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            file_transfer::report_bind_error(&app_handle, port, &e);
+            return;
+        }
+    };
+    info!("[WEB] Browser receive listening on http://{}:{}", ip, port);
+    let task = tokio::spawn(async move {
+        loop {
+            let (socket, addr) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("[WEB] Accept failed: {}", e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(socket, addr).await {
+                    warn!("[WEB] ({addr}) Request failed: {}", e);
+                }
+            });
+        }
+    });
+    *SERVER_TASK.lock().unwrap() = Some(task);
+}
+
+fn stop() {
+    if let Some(task) = SERVER_TASK.lock().unwrap().take() {
+        task.abort();
+        info!("[WEB] Browser receive stopped");
+    }
+}
+
+struct RequestHead {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Read up to the blank line; returns the parsed head and any body bytes read past it.
+async fn read_head(socket: &mut TcpStream) -> anyhow::Result<(RequestHead, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_REQUEST_HEAD {
+            anyhow::bail!("request head too large");
+        }
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before end of headers");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+    Ok((RequestHead { method, path, headers }, buf[end + 4..].to_vec()))
+}
+
+async fn respond(socket: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.flush().await
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+async fn handle_connection(mut socket: TcpStream, addr: std::net::SocketAddr) -> anyhow::Result<()> {
+    let (head, leftover) = read_head(&mut socket).await?;
+    match (head.method.as_str(), head.path.as_str()) {
+        ("GET", "/") => respond(&mut socket, "200 OK", "text/html; charset=utf-8", UPLOAD_PAGE).await?,
+        ("PUT", "/upload") => handle_upload(socket, addr, head, leftover).await?,
+        _ => respond(&mut socket, "404 Not Found", "text/plain", "not found").await?,
+    }
+    Ok(())
+}
+
+/// Forward one upload to the local file server as an ordinary offer + body.
+async fn handle_upload(mut socket: TcpStream, addr: std::net::SocketAddr, head: RequestHead, leftover: Vec<u8>) -> anyhow::Result<()> {
+    let Some(file_size) = head.header("Content-Length").and_then(|v| v.parse::<u64>().ok()) else {
+        respond(&mut socket, "411 Length Required", "text/plain", "Content-Length required").await?;
+        return Ok(());
+    };
+    // Only keep the final path component: the name comes from an untrusted browser
+    let raw_name = percent_decode(head.header("X-File-Name").unwrap_or("upload"));
+    let file_name = std::path::Path::new(&raw_name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .filter(|n| !n.is_empty())
+        .unwrap_or("upload")
+        .to_string();
+    let mime = mime_guess::from_path(&file_name).first_or_octet_stream().to_string();
+    let offer = FileOffer {
+        batch_id: head.header("X-Batch-Id").map(|b| format!("web-{}-{}", addr.ip(), b)),
+        sender_mac: None,
+        // Trust granted to a browser applies to its address only
+        sender_uuid: Some(format!("browser:{}", addr.ip())),
+        forwarded_for: Some(addr.ip().to_string()),
+        ..FileOffer::new(file_name, file_size, mime)
+    };
+    info!("[WEB] ({addr}) Upload of {} ({} bytes)", offer.file_name, file_size);
+
+    let mut bridge = TcpStream::connect(("127.0.0.1", file_transfer::file_server_port())).await?;
+    let header = serde_json::to_string(&offer)? + "\n";
+    bridge.write_all(header.as_bytes()).await?;
+
+    let ack = file_transfer::read_json_line(&mut bridge).await?;
+    if !ack.get("accept").and_then(|v| v.as_bool()).unwrap_or(false) {
+        let reason = ack.get("error").and_then(|v| v.as_str()).unwrap_or("rejected");
+        respond(&mut socket, "403 Forbidden", "text/plain", &format!("Rejected: {}", reason)).await?;
+        return Ok(());
+    }
+    if ack.get("already_have").and_then(|v| v.as_bool()).unwrap_or(false) {
+        respond(&mut socket, "200 OK", "text/plain", "already received").await?;
+        return Ok(());
+    }
+
+    let mut remaining = file_size;
+    let first = (leftover.len() as u64).min(remaining) as usize;
+    bridge.write_all(&leftover[..first]).await?;
+    remaining -= first as u64;
+    let mut buffer = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let to_read = (buffer.len() as u64).min(remaining) as usize;
+        let n = socket.read(&mut buffer[..to_read]).await?;
+        if n == 0 {
+            error!("[WEB] ({addr}) Browser closed the upload early with {} bytes left", remaining);
+            anyhow::bail!("upload ended early");
+        }
+        bridge.write_all(&buffer[..n]).await?;
+        remaining -= n as u64;
+    }
+    bridge.flush().await?;
+    // The file server closes its end once the file is on disk
+    let mut rest = Vec::new();
+    let _ = bridge.read_to_end(&mut rest).await;
+    respond(&mut socket, "200 OK", "text/plain", "OK").await?;
+    Ok(())
+}
@@ -56,7 +56,11 @@ export function DeviceDetection({ onDeviceSelect, selectedDevices }: DeviceDetec
         else if (lower.includes('win') || lower.includes('windows')) type = 'windows';
         else if (lower.includes('linux')) type = 'linux';
 
-        const lastSeenEpoch = d.last_seen ? Math.floor(new Date(d.last_seen).getTime() / 1000) : Math.floor(Date.now() / 1000);
+        // Prefer the backend's locally measured age: the sender's last_seen clock may be skewed
+        const nowEpoch = Math.floor(Date.now() / 1000);
+        const lastSeenEpoch = typeof d.last_seen_secs_ago === 'number'
+          ? nowEpoch - d.last_seen_secs_ago
+          : d.last_seen ? Math.floor(new Date(d.last_seen).getTime() / 1000) : nowEpoch;
         const status: Device['status'] = isDeviceOnline(lastSeenEpoch) ? 'online' : 'offline';
 
         return {
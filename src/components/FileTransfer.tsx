@@ -4,7 +4,7 @@ import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
 import { Button } from './ui/button';
 import { Card } from './ui/card';
-import { Upload, X, File, Send, Users, AlertCircle } from 'lucide-react';
+import { Upload, X, File, Folder, Send, Users, AlertCircle } from 'lucide-react';
 import { motion, AnimatePresence } from 'motion/react';
 import type { Device } from '../types/device';
 import { useTranslation } from "react-i18next";
@@ -22,6 +22,7 @@ interface UploadFile {
   name: string;
   size: number;
   path?: string;
+  isDir?: boolean;
 }
 
 export function FileTransfer({ 
@@ -279,6 +280,7 @@ export function FileTransfer({
           name: fileInfo?.name || (path.split(/[\\/]/).pop() || 'file'),
           size: typeof fileInfo?.size === 'number' ? fileInfo.size : 0,
           path,
+          isDir: fileInfo?.is_file === false,
         };
         onFilesChange([...selectedFiles, item]);
       } catch (error) {
@@ -301,6 +303,15 @@ export function FileTransfer({
     }
   };
 
+  const handleFolderDialog = async () => {
+    const selected = await open({ directory: true, multiple: false });
+    if (typeof selected !== 'string') return;
+    onFilesChange([
+      ...selectedFiles,
+      { name: selected.split(/[\\/]/).pop() || 'folder', size: 0, path: selected, isDir: true },
+    ]);
+  };
+
   function generateBatchId() {
     if (typeof crypto !== "undefined" && typeof (crypto as any).randomUUID === "function") {
       return (crypto as any).randomUUID();
@@ -366,41 +377,26 @@ export function FileTransfer({
       }));
 
       try {
-        const totalSize = selectedFiles.reduce((sum, file) => sum + file.size, 0);
-        for (let i = 0; i < selectedFiles.length; i++) {
-          const f = selectedFiles[i];
-          const filePath = f.path || f.name;
-          if (!f.path) {
+        const folders = selectedFiles.filter(f => f.isDir && f.path);
+        const files = selectedFiles.filter(f => !f.isDir);
+        for (const f of folders) {
+          // The backend picks tar streaming or a per-file batch depending on the peer
+          await invoke('send_folder', { ip: targetIp, port: targetPort, folderPath: f.path, batchId });
+        }
+        if (files.length > 0) {
+          files.filter(f => !f.path).forEach(f => {
             console.warn('⚠️ [FileTransfer] Path mancante per', f.name, '-', t('select_file_button_hint'));
-          }
-
-          console.log(
-            `[FileTransfer] Invio file (batchId=${batchId}, deviceKey=${deviceKey}, fileIndex=${i}, fileName=${f.name})`
-          );
-          console.log(
-            `➡️ [FileTransfer] Sending batchId to backend with file:`, { batchId, deviceKey, fileName: f.name }
-          );
+          });
           console.log(
-            `🔗 [FileTransfer] Batch ID globale per questo trasferimento: ${batchId} (file ${i + 1}/${selectedFiles.length})`
+            `🔗 [FileTransfer] Batch ID globale per questo trasferimento: ${batchId} (${files.length} file, deviceKey=${deviceKey})`
           );
-
-          const invokeParams = {
+          // One call for the whole batch: the backend reuses a single connection when the peer supports it
+          await invoke('send_files', {
             ip: targetIp,
             port: targetPort,
-            path: filePath,
-            fileIndex: i,
-            totalFiles: selectedFiles.length,
-            fileName: f.name,
-            totalSize: totalSize,
+            paths: files.map(f => f.path || f.name),
             batchId: batchId
-          };
-          
-          console.log('🔍 [FileTransfer] Parametri invoke completi:', JSON.stringify(invokeParams, null, 2));
-          console.log('🔍 [FileTransfer] Tipo di batchId:', typeof batchId, 'Valore:', batchId);
-          console.log('🔍 [FileTransfer] batchId è stringa vuota?', batchId === '');
-          console.log('🔍 [FileTransfer] batchId è null/undefined?', batchId == null);
-          
-          await invoke('send_file_with_progress', invokeParams);
+          });
         }
         toast.success(t("transfer_success", { device: deviceKey }));
       } catch (err) {
@@ -637,10 +633,10 @@ export function FileTransfer({
                   {selectedFiles.map((f, idx) => (
                     <div key={`${f.name}-${idx}`} className="flex items-center justify-between p-3 rounded-lg bg-slate-50 border border-slate-200">
                       <div className="flex items-center gap-3">
-                        <File className="w-6 h-6 text-slate-700" />
+                        {f.isDir ? <Folder className="w-6 h-6 text-slate-700" /> : <File className="w-6 h-6 text-slate-700" />}
                         <div className="text-left">
                           <p className="text-slate-800 truncate max-w-xs">{f.name}</p>
-                          <p className="text-slate-600 text-sm">{formatFileSize(f.size)}</p>
+                          {!f.isDir && <p className="text-slate-600 text-sm">{formatFileSize(f.size)}</p>}
                         </div>
                       </div>
                       <Button
@@ -681,7 +677,17 @@ export function FileTransfer({
           </div>
         )}
 
-        <div className="mt-6 flex justify-end">
+        <div className="mt-6 flex justify-between">
+          <Button
+            onClick={handleFolderDialog}
+            disabled={isUploading}
+            variant="ghost"
+            className="text-slate-700 hover:text-slate-900 hover:bg-slate-100"
+            type="button"
+          >
+            <Folder className="w-4 h-4 mr-2" />
+            {t("select_folder")}
+          </Button>
           <Button
             onClick={handleSend}
             disabled={!canSend}